@@ -0,0 +1,137 @@
+use crate::{
+    types::{Fields, Variant, Variants},
+    utils::*,
+};
+use proc_macro2::TokenStream;
+use std::convert::TryFrom;
+use syn::{
+    parse::{Parse, ParseStream, Result},
+    punctuated::Pair,
+    spanned::Spanned,
+    Error,
+};
+
+/// An enum mapped to D-Bus error names, each variant being either a unit
+/// variant or holding the error message.
+#[derive(Debug, Clone)]
+pub struct DeriveErrorDef {
+    pub metas: Metas,
+    pub name: syn::Ident,
+    pub generics: syn::Generics,
+    pub variants: Variants,
+}
+
+impl DeriveErrorDef {
+    fn impl_error(self) -> Result<TokenStream> {
+        let dbus = self.metas.find_meta_nested("dbus");
+        let rbus_module = dbus.find_rbus_module("rbus");
+        let interface = dbus.find_meta_value_str("interface").map(syn::LitStr::value);
+
+        let ty_name = &self.name;
+        let (impl_generics, type_generics, where_clause) = self.generics.split_for_impl();
+
+        let mut to_arms = Vec::with_capacity(self.variants.len());
+        let mut from_arms = Vec::with_capacity(self.variants.len());
+        for variant in self.variants.iter() {
+            let name = error_name(variant, interface.as_ref())?;
+            let variant_name = &variant.name;
+
+            match variant.fields {
+                Fields::Unit => {
+                    to_arms.push(quote::quote!(#ty_name::#variant_name => (#name, String::new())));
+                    from_arms.push(quote::quote!(#name => Some(#ty_name::#variant_name)));
+                }
+                Fields::Unnamed(ref fields) if fields.len() == 1 => {
+                    to_arms.push(quote::quote!(#ty_name::#variant_name(message) => (#name, message.to_string())));
+                    from_arms.push(quote::quote!(#name => Some(#ty_name::#variant_name(error.message().into()))));
+                }
+                _ => {
+                    return Err(Error::new(
+                        variant.span,
+                        "Error variants must be unit or hold a single message field",
+                    ));
+                }
+            }
+        }
+
+        Ok(quote::quote! {
+            impl #impl_generics #rbus_module::message::DBusError for #ty_name #type_generics #where_clause {
+                fn to_method_error(&self) -> #rbus_module::message::MethodError {
+                    use #rbus_module::message::{types::ErrorName, MethodError};
+
+                    let (name, message) = match self {
+                        #(#to_arms,)*
+                    };
+                    // Names are checked when deriving.
+                    MethodError::new(ErrorName::new(name).unwrap(), message)
+                }
+
+                fn from_method_error(error: &#rbus_module::message::MethodError) -> Option<Self> {
+                    match AsRef::<str>::as_ref(error.name()) {
+                        #(#from_arms,)*
+                        _ => None,
+                    }
+                }
+            }
+        })
+    }
+}
+
+/// Return the error name of `variant`, either given with `name` or built
+/// from the `interface` of the enum and the variant name.
+fn error_name(variant: &Variant, interface: Option<&String>) -> Result<syn::LitStr> {
+    let dbus = variant.metas.find_meta_nested("dbus");
+    let name = match (dbus.find_meta_value_str("name"), interface) {
+        (Some(name), _) => name.value(),
+        (None, Some(interface)) => format!("{}.{}", interface, variant.name),
+        (None, None) => {
+            return Err(Error::new(
+                variant.span,
+                "Error variants need a `name`, or the enum an `interface`",
+            ));
+        }
+    };
+
+    if !is_valid_error_name(&name) {
+        return Err(Error::new(variant.span, format!("Invalid D-Bus error name: {}", name)));
+    }
+
+    Ok(syn::LitStr::new(&name, variant.span))
+}
+
+/// Error names follow the interface names rules.
+fn is_valid_error_name(name: &str) -> bool {
+    let is_valid_element = |element: &str| {
+        let mut chars = element.chars();
+        match chars.next() {
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+            _ => return false,
+        }
+        chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+    };
+
+    name.len() <= 255 && name.contains('.') && name.split('.').all(is_valid_element)
+}
+
+impl Parse for DeriveErrorDef {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let derive_input = input.parse::<syn::DeriveInput>()?;
+        let span = derive_input.span();
+
+        let variants = match derive_input.data {
+            syn::Data::Enum(data) => data.variants.into_pairs().map(Pair::into_value).collect::<Vec<_>>(),
+            _ => return Err(Error::new(span, "DBusError can only be derived for enums")),
+        };
+
+        Ok(DeriveErrorDef {
+            metas: Metas::from_attributes(&derive_input.attrs)?,
+            name: derive_input.ident,
+            generics: derive_input.generics,
+            variants: Variants::try_from(variants)?,
+        })
+    }
+}
+
+pub fn derive_error(data: DeriveErrorDef) -> Result<TokenStream> {
+    data.impl_error()
+}
@@ -5,6 +5,7 @@ extern crate proc_macro;
 use proc_macro::TokenStream;
 use proc_macro_utils::impl_macro_input;
 
+mod error;
 mod ext;
 mod types;
 mod utils;
@@ -24,3 +25,8 @@ pub fn impl_type(item: TokenStream) -> TokenStream {
 pub fn derive_dbus_type(item: TokenStream) -> TokenStream {
     impl_macro_input!(types::derive_type(item)?)
 }
+
+#[proc_macro_derive(DBusError, attributes(dbus))]
+pub fn derive_dbus_error(item: TokenStream) -> TokenStream {
+    impl_macro_input!(error::derive_error(item)?)
+}
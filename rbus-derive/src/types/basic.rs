@@ -24,6 +24,7 @@ impl BasicTypeDef {
 
         let encode_method = self.gen_encode_method()?;
         let decode_method = self.gen_decode_method()?;
+        let array_methods = self.gen_array_methods();
 
         let tokens = quote::quote! {
             rbus_derive::impl_type! {
@@ -32,6 +33,7 @@ impl BasicTypeDef {
                 #ty: #code {
                     #encode_method
                     #decode_method
+                    #array_methods
                     #(#methods)*
                 }
             }
@@ -71,6 +73,26 @@ impl BasicTypeDef {
 
         Ok(tokens)
     }
+
+    // Arrays of types written as they are in memory are copied in bulk,
+    // which doesn't hold once encode is overridden.
+    fn gen_array_methods(&self) -> Option<TokenStream> {
+        if self.methods.iter().any(|method| method.name() == "encode") {
+            return None;
+        }
+
+        let tokens = quote::quote! {
+            encode_array(values, marshaller) {
+                marshaller.write_fixed_array(values)
+            }
+
+            decode_array(marshaller) {
+                marshaller.read_fixed_array()
+            }
+        };
+
+        Some(tokens)
+    }
 }
 
 impl Parse for BasicTypeDef {
@@ -206,16 +206,24 @@ impl DeriveEnum {
     fn gen_encode_variant_body(&self, variant: &Variant) -> TokenStream {
         let names = variant.fields.pat_names();
         let signature = variant.fields.signature();
-        let alignment = vec![variant.fields.alignment(); names.len()];
+
+        let values = if variant.fields.is_struct() {
+            quote::quote! {
+                marshaller.write_struct(|marshaller| {
+                    #(marshaller.write_value(#names)?;)*
+                    Ok(())
+                })?;
+            }
+        } else {
+            quote::quote! {
+                #(marshaller.write_value(#names)?;)*
+            }
+        };
 
         let tokens = quote::quote! {
             let signature = Signature::new(#signature)?;
             signature.encode(marshaller)?;
-
-            #(
-            marshaller.write_padding(#alignment)?;
-            #names.encode(marshaller)?;
-            )*
+            #values
         };
 
         tokens
@@ -258,35 +266,35 @@ impl DeriveEnum {
     fn gen_decode_variant_body(&self, ty: &DeriveTypeDef, variant: &Variant) -> TokenStream {
         let ty_name = &ty.name;
         let variant_name = &variant.name;
-        let alignment = vec![variant.fields.alignment(); variant.fields.len()];
 
         let construct = match variant.fields {
             Fields::Named(ref fields) => {
                 let (names, types) = Fields::split_named(&fields);
 
+                let labels = names.iter().map(|name| name.to_string()).collect::<Vec<_>>();
+
                 quote::quote! {
                     #ty_name::#variant_name {
-                        #(#names: {
-                            marshaller.read_padding(#alignment)?;
-                            <#types>::decode(marshaller)?
-                        },)*
+                        #(#names: marshaller.read_field::<#types>(#labels)?,)*
                     }
                 }
             }
             Fields::Unnamed(_) => {
                 let types = variant.fields.types();
+                let labels = (0..types.len()).map(|index| index.to_string());
 
                 quote::quote! {
-                    #ty_name::#variant_name(#({
-                        marshaller.read_padding(#alignment)?;
-                        <#types>::decode(marshaller)?
-                    }),*)
+                    #ty_name::#variant_name(#(marshaller.read_field::<#types>(#labels)?),*)
                 }
             }
             Fields::Unit => quote::quote!(#ty_name::#variant_name),
         };
 
-        quote::quote!(return Ok(#construct))
+        if variant.fields.is_struct() {
+            quote::quote!(return marshaller.read_struct(|marshaller| Ok(#construct)))
+        } else {
+            quote::quote!(return Ok(#construct))
+        }
     }
 }
 
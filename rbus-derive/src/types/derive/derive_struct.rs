@@ -59,35 +59,23 @@ impl DeriveStruct {
             .into_iter()
             .map(|field| {
                 let dbus = field.dbus();
-                let mut tokens = TokenStream::new();
-
-                if gen.is_packed() {
-                    let ty = field.ty;
-                    tokens.extend(quote::quote! {
-                        marshaller.write_padding(<#ty>::alignment())?;
-                    });
-                } else {
-                    tokens.extend(quote::quote! {
-                        marshaller.write_padding(Self::alignment())?;
-                    });
-                }
-
                 let name = &field.name;
-                tokens.extend(quote::quote! {
-                    self.#name.encode(marshaller)?;
-                });
+                let ty = field.ty;
 
-                if let Some(value) = dbus.find_meta_value_str("mutate_marshaller") {
-                    let path: TokenStream = value.parse()?;
+                let mut tokens = quote::quote! {
+                    marshaller.write_padding(<#ty>::alignment())?;
+                    self.#name.encode(marshaller)?;
+                };
 
+                if dbus.has_word("endianness") {
                     tokens.extend(quote::quote! {
-                        #path(&self.#name, marshaller);
+                        marshaller.set_endianness(self.#name);
                     });
                 }
 
-                Ok(tokens)
+                tokens
             })
-            .collect::<Result<Vec<_>>>()?;
+            .collect::<Vec<_>>();
 
         let mut body = quote::quote!(#(#fields)*);
 
@@ -98,7 +86,19 @@ impl DeriveStruct {
             });
         };
 
-        body.extend(quote::quote!(Ok(())));
+        let body = if gen.is_packed() {
+            quote::quote! {
+                #body
+                Ok(())
+            }
+        } else {
+            quote::quote! {
+                marshaller.write_struct(|marshaller| {
+                    #body
+                    Ok(())
+                })
+            }
+        };
 
         Ok(gen.gen_encode_method(syn::parse_quote!(marshaller), body, &[]))
     }
@@ -110,36 +110,23 @@ impl DeriveStruct {
             .into_iter()
             .map(|field| {
                 let dbus = field.dbus();
-                let mut tokens = TokenStream::new();
-
-                if gen.is_packed() {
-                    let ty = field.ty;
-                    tokens.extend(quote::quote! {
-                        marshaller.read_padding(<#ty>::alignment())?;
-                    });
-                } else {
-                    tokens.extend(quote::quote! {
-                        marshaller.read_padding(Self::alignment())?;
-                    });
-                }
-
                 let binding = field.binding;
                 let ty = field.ty;
-                tokens.extend(quote::quote! {
-                    let #binding = <#ty>::decode(marshaller)?;
-                });
+                let name = field.name.to_string();
 
-                if let Some(value) = dbus.find_meta_value_str("mutate_marshaller") {
-                    let path: TokenStream = value.parse()?;
+                let mut tokens = quote::quote! {
+                    let #binding = marshaller.read_field::<#ty>(#name)?;
+                };
 
+                if dbus.has_word("endianness") {
                     tokens.extend(quote::quote! {
-                        #path(&#binding, marshaller);
+                        marshaller.set_endianness(#binding);
                     });
                 }
 
-                Ok(tokens)
+                tokens
             })
-            .collect::<Result<Vec<_>>>()?;
+            .collect::<Vec<_>>();
         let bindings = self.fields.bindings();
 
         let mut body = quote::quote!(#(#fields)*);
@@ -151,7 +138,19 @@ impl DeriveStruct {
             });
         };
 
-        body.extend(quote::quote!(Ok(Self #bindings)));
+        let body = if gen.is_packed() {
+            quote::quote! {
+                #body
+                Ok(Self #bindings)
+            }
+        } else {
+            quote::quote! {
+                marshaller.read_struct(|marshaller| {
+                    #body
+                    Ok(Self #bindings)
+                })
+            }
+        };
 
         Ok(gen.gen_decode_method(syn::parse_quote!(marshaller), body, &[]))
     }
@@ -51,9 +51,14 @@ impl Fields {
         }
     }
 
+    /// Return true if the fields are encoded as a D-Bus struct
+    pub fn is_struct(&self) -> bool {
+        self.is_named() || self.len() > 1
+    }
+
     pub fn signature(&self) -> TokenStream {
         let types = self.types();
-        if self.is_named() || self.len() > 1 {
+        if self.is_struct() {
             let signature_format_str = format!("({})", "{}".repeat(self.len()));
             quote::quote!(format!(#signature_format_str, #(<#types>::signature()),*))
         } else if self.len() == 1 {
@@ -64,19 +69,6 @@ impl Fields {
         }
     }
 
-    pub fn alignment(&self) -> TokenStream {
-        if self.is_named() || self.len() > 1 {
-            let alignment = syn::LitInt::new(8, syn::IntSuffix::None, Span::call_site());
-            quote::quote!(#alignment)
-        } else if self.len() == 1 {
-            let types = self.types();
-            let ty = types[0];
-            quote::quote!(<#ty>::alignment())
-        } else {
-            quote::quote!(0)
-        }
-    }
-
     pub fn pat(&self, named: bool) -> TokenStream {
         match self {
             Fields::Named(fields) => {
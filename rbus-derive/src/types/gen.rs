@@ -5,6 +5,8 @@ use std::collections::HashMap;
 use syn::{spanned::Spanned, Error, Result};
 
 const DBUS_TYPE_METHOD_NAMES: &[&str] = &["code", "signature", "alignment", "encode", "decode"];
+// Methods with a default implementation in the `DBusType` trait.
+const DBUS_TYPE_OPTIONAL_METHOD_NAMES: &[&str] = &["encode_array", "decode_array"];
 
 pub struct ImplGeneratorOptions {
     pub default_rbus_module: String,
@@ -67,9 +69,9 @@ impl ImplGenerator {
 
     pub fn add_method<T: Into<String>>(&mut self, name: T, method: TokenStream) {
         let name = name.into();
-        let accept_names = DBUS_TYPE_METHOD_NAMES;
+        let accept_names = DBUS_TYPE_METHOD_NAMES.iter().chain(DBUS_TYPE_OPTIONAL_METHOD_NAMES);
 
-        if accept_names.contains(&name.as_str()) {
+        if accept_names.into_iter().any(|&accept_name| accept_name == name) {
             self.methods.insert(name, method);
         }
     }
@@ -118,10 +120,14 @@ impl ImplGenerator {
                     .ok_or_else(|| Error::new(self.span, format!("Missing method: {}", name)))
             })
             .collect::<Result<Vec<_>>>()?;
+        let optional_methods = DBUS_TYPE_OPTIONAL_METHOD_NAMES
+            .iter()
+            .filter_map(|&name| self.methods.get(name));
 
         let mut tokens = quote::quote! {
             impl #impl_generics #rbus_module::types::DBusType for #ty #where_clause {
                 #(#methods)*
+                #(#optional_methods)*
             }
         };
 
@@ -194,4 +200,45 @@ impl ImplGenerator {
             }
         }
     }
+
+    pub fn gen_encode_array_method<Body: ToTokens>(
+        &self,
+        values: syn::Ident,
+        marshaller: syn::Ident,
+        body: Body,
+        attrs: &[Attribute],
+    ) -> TokenStream {
+        let rbus_module = self.rbus_module();
+
+        quote::quote! {
+            #(#attrs)*
+            fn encode_array<Inner>(#values: &[Self], #marshaller: &mut #rbus_module::marshal::Marshaller<Inner>)
+                -> #rbus_module::Result<()>
+            where
+                Inner: std::io::Write
+            {
+                #body
+            }
+        }
+    }
+
+    pub fn gen_decode_array_method<Body: ToTokens>(
+        &self,
+        marshaller: syn::Ident,
+        body: Body,
+        attrs: &[Attribute],
+    ) -> TokenStream {
+        let rbus_module = self.rbus_module();
+
+        quote::quote! {
+            #(#attrs)*
+            fn decode_array<Inner>(#marshaller: &mut #rbus_module::marshal::Marshaller<Inner>)
+                -> #rbus_module::Result<Vec<Self>>
+            where
+                Inner: std::io::Read
+            {
+                #body
+            }
+        }
+    }
 }
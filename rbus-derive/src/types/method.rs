@@ -59,6 +59,8 @@ impl Method {
             "alignment" => gen.gen_alignment_method(body, &attrs),
             "encode" => self.gen_encode_method(gen)?,
             "decode" => self.gen_decode_method(gen)?,
+            "encode_array" => self.gen_encode_array_method(gen)?,
+            "decode_array" => self.gen_decode_array_method(gen)?,
             _ => return Err(Error::new(self.name.span(), "Invalid DBusType method name")),
         };
 
@@ -86,6 +88,28 @@ impl Method {
 
         Ok(gen.gen_decode_method(marshaller, body, &attrs))
     }
+
+    fn gen_encode_array_method(&self, gen: &ImplGenerator) -> Result<TokenStream> {
+        let Method { attrs, args, body, .. } = self;
+        let mut args = args.iter().cloned();
+        let (values, marshaller) = match (args.next(), args.next()) {
+            (Some(values), Some(marshaller)) => (values, marshaller),
+            _ => return Err(Error::new(self.args.span(), "Not enough arguments")),
+        };
+
+        Ok(gen.gen_encode_array_method(values, marshaller, body, &attrs))
+    }
+
+    fn gen_decode_array_method(&self, gen: &ImplGenerator) -> Result<TokenStream> {
+        let Method { attrs, args, body, .. } = self;
+        let marshaller = args
+            .first()
+            .map(|pair| pair.into_value())
+            .cloned()
+            .ok_or_else(|| Error::new(args.span(), "Not enough arguments"))?;
+
+        Ok(gen.gen_decode_array_method(marshaller, body, &attrs))
+    }
 }
 
 impl Parse for Method {
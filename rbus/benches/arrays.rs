@@ -0,0 +1,126 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rbus::{
+    marshal::{Endianness, Marshaller},
+    types::ByteBuf,
+};
+
+/// The size of the payloads, about the size of an uncompressed 1080p image.
+const PAYLOAD_SIZE: usize = 8 * 1024 * 1024;
+
+const ENDIANNESSES: &[(&str, Endianness)] = &[("little", Endianness::Little), ("big", Endianness::Big)];
+
+fn encode<T: rbus::types::DBusType>(value: &T, endianness: Endianness) -> Vec<u8> {
+    let mut marshaller = Marshaller::new(Vec::new(), endianness);
+    marshaller.write_value(value).unwrap();
+    marshaller.into_inner()
+}
+
+/// Encode an array one element at a time, as done for non fixed size types.
+fn encode_per_element<T: rbus::types::DBusType>(values: &[T], endianness: Endianness) -> Vec<u8> {
+    let mut marshaller = Marshaller::new(Vec::new(), endianness);
+    marshaller
+        .write_array(T::alignment(), |marshaller| {
+            for value in values.iter() {
+                marshaller.write_value(value)?;
+            }
+            Ok(())
+        })
+        .unwrap();
+    marshaller.into_inner()
+}
+
+fn decode_per_element<T: rbus::types::DBusType>(data: &[u8], endianness: Endianness) -> Vec<T> {
+    let mut values = Vec::new();
+    let mut marshaller = Marshaller::from_slice(data, endianness);
+    marshaller
+        .read_array(T::alignment(), |marshaller| {
+            values.push(marshaller.read_value()?);
+            Ok(())
+        })
+        .unwrap();
+    values
+}
+
+fn bench_bytes(c: &mut Criterion) {
+    let data: Vec<u8> = (0..PAYLOAD_SIZE).map(|i| i as u8).collect();
+    let bytes = ByteBuf::from(data.clone());
+    let encoded = encode(&data, Endianness::Little);
+
+    let mut group = c.benchmark_group("ay");
+    group.throughput(Throughput::Bytes(PAYLOAD_SIZE as u64));
+    group.sample_size(20);
+
+    group.bench_function("encode/per_element", |b| {
+        b.iter(|| encode_per_element(&data, Endianness::Little))
+    });
+    group.bench_function("encode/bulk", |b| b.iter(|| encode(&bytes, Endianness::Little)));
+    group.bench_function("decode/per_element", |b| {
+        b.iter(|| decode_per_element::<u8>(&encoded, Endianness::Little))
+    });
+    group.bench_function("decode/bulk", |b| {
+        b.iter(|| {
+            let mut marshaller = Marshaller::from_slice(&encoded, Endianness::Little);
+            marshaller.read_value::<ByteBuf>().unwrap()
+        })
+    });
+    group.finish();
+}
+
+fn bench_fixed<T>(c: &mut Criterion, name: &str, values: Vec<T>)
+where
+    T: rbus::types::DBusType,
+{
+    let mut group = c.benchmark_group(name);
+    group.throughput(Throughput::Bytes(PAYLOAD_SIZE as u64));
+    group.sample_size(20);
+
+    for &(endianness_name, endianness) in ENDIANNESSES {
+        let encoded = encode(&values, endianness);
+
+        group.bench_with_input(
+            BenchmarkId::new("encode/per_element", endianness_name),
+            &values,
+            |b, values| b.iter(|| encode_per_element(values, endianness)),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("encode/bulk", endianness_name),
+            &values,
+            |b, values| b.iter(|| encode(values, endianness)),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("decode/per_element", endianness_name),
+            &encoded,
+            |b, encoded| b.iter(|| decode_per_element::<T>(encoded, endianness)),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("decode/bulk", endianness_name),
+            &encoded,
+            |b, encoded| {
+                b.iter(|| {
+                    let mut marshaller = Marshaller::from_slice(encoded, endianness);
+                    marshaller.read_value::<Vec<T>>().unwrap()
+                })
+            },
+        );
+    }
+
+    group.finish();
+}
+
+fn bench_u32(c: &mut Criterion) {
+    let values = (0..PAYLOAD_SIZE / 4).map(|i| i as u32).collect();
+    bench_fixed::<u32>(c, "au", values);
+}
+
+fn bench_u64(c: &mut Criterion) {
+    let values = (0..PAYLOAD_SIZE / 8).map(|i| i as u64).collect();
+    bench_fixed::<u64>(c, "at", values);
+}
+
+fn bench_f64(c: &mut Criterion) {
+    let values = (0..PAYLOAD_SIZE / 8).map(|i| i as f64 * 0.5).collect();
+    bench_fixed::<f64>(c, "ad", values);
+}
+
+criterion_group!(benches, bench_bytes, bench_u32, bench_u64, bench_f64);
+criterion_main!(benches);
@@ -0,0 +1,570 @@
+use custom_error::custom_error;
+use std::{
+    env,
+    ffi::{OsStr, OsString},
+    fmt,
+    os::unix::ffi::{OsStrExt, OsStringExt},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// The address of the system bus when `DBUS_SYSTEM_BUS_ADDRESS` isn't set.
+pub const DEFAULT_SYSTEM_BUS_ADDRESS: &str = "unix:path=/var/run/dbus/system_bus_socket";
+
+custom_error! {
+    pub AddressError
+        InvalidAddress { message: String }
+            = "Invalid address: {message}",
+        MissingKey { transport: String, key: String }
+            = "`{transport}` addresses require a `{key}` key",
+        NoSessionBus
+            = "No session bus address: DBUS_SESSION_BUS_ADDRESS isn't set and there is no $XDG_RUNTIME_DIR/bus",
+}
+
+fn invalid<T: Into<String>>(message: T) -> AddressError {
+    AddressError::InvalidAddress {
+        message: message.into(),
+    }
+}
+
+/// A D-Bus address: alternative server addresses to try in order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Address(Vec<ServerAddress>);
+
+impl Address {
+    pub fn new(alternatives: Vec<ServerAddress>) -> Address {
+        Address(alternatives)
+    }
+
+    pub fn alternatives(&self) -> &[ServerAddress] {
+        &self.0
+    }
+
+    /// Return the address of the session bus, from `DBUS_SESSION_BUS_ADDRESS`
+    /// or else the `bus` socket in `XDG_RUNTIME_DIR`.
+    pub fn session() -> Result<Address, AddressError> {
+        session_address(|name| env::var_os(name))
+    }
+
+    /// Return the address of the system bus, from `DBUS_SYSTEM_BUS_ADDRESS`
+    /// or else the default system bus socket.
+    pub fn system() -> Result<Address, AddressError> {
+        system_address(|name| env::var_os(name))
+    }
+}
+
+fn session_address<F: Fn(&str) -> Option<OsString>>(var: F) -> Result<Address, AddressError> {
+    if let Some(address) = var("DBUS_SESSION_BUS_ADDRESS") {
+        return parse_env_address(&address);
+    }
+
+    match var("XDG_RUNTIME_DIR") {
+        Some(dir) if Path::new(&dir).join("bus").exists() => {
+            let transport = Transport::Unix(UnixTransport::Path(Path::new(&dir).join("bus")));
+            Ok(Address(vec![ServerAddress::new(transport)]))
+        }
+        _ => Err(AddressError::NoSessionBus),
+    }
+}
+
+fn system_address<F: Fn(&str) -> Option<OsString>>(var: F) -> Result<Address, AddressError> {
+    match var("DBUS_SYSTEM_BUS_ADDRESS") {
+        Some(address) => parse_env_address(&address),
+        None => DEFAULT_SYSTEM_BUS_ADDRESS.parse(),
+    }
+}
+
+fn parse_env_address(address: &OsStr) -> Result<Address, AddressError> {
+    address
+        .to_str()
+        .ok_or_else(|| invalid("Addresses must be ASCII"))?
+        .parse()
+}
+
+impl FromStr for Address {
+    type Err = AddressError;
+
+    fn from_str(s: &str) -> Result<Address, AddressError> {
+        let alternatives = s
+            .split(';')
+            .filter(|alternative| !alternative.is_empty())
+            .map(str::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if alternatives.is_empty() {
+            return Err(invalid("Empty address"));
+        }
+
+        Ok(Address(alternatives))
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (index, alternative) in self.0.iter().enumerate() {
+            if index > 0 {
+                f.write_str(";")?;
+            }
+            write!(f, "{}", alternative)?;
+        }
+        Ok(())
+    }
+}
+
+/// One of the alternatives of an address: a transport and the GUID the
+/// server is expected to have.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerAddress {
+    pub transport: Transport,
+    pub guid: Option<String>,
+}
+
+impl ServerAddress {
+    pub fn new(transport: Transport) -> ServerAddress {
+        ServerAddress { transport, guid: None }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Transport {
+    Unix(UnixTransport),
+    Tcp(TcpTransport),
+    /// TCP with a nonce, read from `noncefile`, sent before authenticating.
+    NonceTcp {
+        tcp: TcpTransport,
+        noncefile: Option<PathBuf>,
+    },
+    /// A process started with `argv`, talking over its standard input and
+    /// output. `argv[0]` defaults to `path`.
+    UnixExec {
+        path: PathBuf,
+        argv: Vec<OsString>,
+    },
+    Autolaunch {
+        scope: Option<String>,
+    },
+    /// A transport unknown to this implementation, which clients skip.
+    Other {
+        name: String,
+        options: Vec<(String, Vec<u8>)>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UnixTransport {
+    Path(PathBuf),
+    /// A socket in the Linux abstract namespace.
+    Abstract(Vec<u8>),
+    /// A directory to listen in, with a random path or abstract name.
+    Dir(PathBuf),
+    /// Like `Dir`, but preferring the abstract namespace.
+    TmpDir(PathBuf),
+    /// A socket in `XDG_RUNTIME_DIR`, to listen on.
+    Runtime,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TcpFamily {
+    Ipv4,
+    Ipv6,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TcpTransport {
+    pub host: Option<String>,
+    /// The interface to listen on.
+    pub bind: Option<String>,
+    pub port: Option<u16>,
+    pub family: Option<TcpFamily>,
+}
+
+/// The `key=value` pairs of a server address, with their values unescaped.
+struct Options(Vec<(String, Vec<u8>)>);
+
+impl Options {
+    fn parse(s: &str) -> Result<Options, AddressError> {
+        let mut options: Vec<(String, Vec<u8>)> = Vec::new();
+        for pair in s.split(',').filter(|pair| !pair.is_empty()) {
+            let (key, value) = match pair.find('=') {
+                Some(index) => (&pair[..index], &pair[index + 1..]),
+                None => return Err(invalid(format!("Missing value for `{}`", pair))),
+            };
+
+            if key.is_empty() {
+                return Err(invalid("Empty key"));
+            }
+            if options.iter().any(|(other, _)| other == key) {
+                return Err(invalid(format!("Duplicate key `{}`", key)));
+            }
+
+            options.push((key.into(), unescape(value)?));
+        }
+
+        Ok(Options(options))
+    }
+
+    fn take(&mut self, key: &str) -> Option<Vec<u8>> {
+        let index = self.0.iter().position(|(other, _)| other == key)?;
+        Some(self.0.remove(index).1)
+    }
+
+    fn take_str(&mut self, key: &str) -> Result<Option<String>, AddressError> {
+        match self.take(key) {
+            Some(value) => String::from_utf8(value)
+                .map(Some)
+                .map_err(|_| invalid(format!("The value of `{}` must be UTF-8", key))),
+            None => Ok(None),
+        }
+    }
+
+    fn take_path(&mut self, key: &str) -> Option<PathBuf> {
+        self.take(key).map(|value| OsString::from_vec(value).into())
+    }
+}
+
+impl FromStr for ServerAddress {
+    type Err = AddressError;
+
+    fn from_str(s: &str) -> Result<ServerAddress, AddressError> {
+        let (name, options) = match s.find(':') {
+            Some(index) => (&s[..index], &s[index + 1..]),
+            None => return Err(invalid(format!("Missing transport name in `{}`", s))),
+        };
+
+        let mut options = Options::parse(options)?;
+        let guid = options.take_str("guid")?;
+        let transport = match name {
+            "unix" => Transport::Unix(parse_unix(&mut options)?),
+            "tcp" => Transport::Tcp(parse_tcp(&mut options)?),
+            "nonce-tcp" => Transport::NonceTcp {
+                tcp: parse_tcp(&mut options)?,
+                noncefile: options.take_path("noncefile"),
+            },
+            "unixexec" => parse_unixexec(&mut options)?,
+            "autolaunch" => Transport::Autolaunch {
+                scope: options.take_str("scope")?,
+            },
+            "" => return Err(invalid("Empty transport name")),
+            name => Transport::Other {
+                name: name.into(),
+                options: options.0,
+            },
+        };
+
+        Ok(ServerAddress { transport, guid })
+    }
+}
+
+fn parse_unix(options: &mut Options) -> Result<UnixTransport, AddressError> {
+    let mut transports = Vec::with_capacity(1);
+    if let Some(path) = options.take_path("path") {
+        transports.push(UnixTransport::Path(path));
+    }
+    if let Some(name) = options.take("abstract") {
+        transports.push(UnixTransport::Abstract(name));
+    }
+    if let Some(dir) = options.take_path("dir") {
+        transports.push(UnixTransport::Dir(dir));
+    }
+    if let Some(dir) = options.take_path("tmpdir") {
+        transports.push(UnixTransport::TmpDir(dir));
+    }
+    if let Some(runtime) = options.take("runtime") {
+        if runtime != b"yes" {
+            return Err(invalid("The only valid value of `runtime` is `yes`"));
+        }
+        transports.push(UnixTransport::Runtime);
+    }
+
+    match transports.len() {
+        1 => Ok(transports.remove(0)),
+        0 => Err(AddressError::MissingKey {
+            transport: "unix".into(),
+            key: "path".into(),
+        }),
+        _ => Err(invalid(
+            "Only one of `path`, `abstract`, `dir`, `tmpdir` and `runtime` can be given",
+        )),
+    }
+}
+
+fn parse_tcp(options: &mut Options) -> Result<TcpTransport, AddressError> {
+    let port = match options.take_str("port")? {
+        Some(port) => Some(port.parse().map_err(|_| invalid(format!("Invalid port `{}`", port)))?),
+        None => None,
+    };
+    let family = match options.take_str("family")?.as_deref() {
+        Some("ipv4") => Some(TcpFamily::Ipv4),
+        Some("ipv6") => Some(TcpFamily::Ipv6),
+        Some(family) => return Err(invalid(format!("Invalid family `{}`", family))),
+        None => None,
+    };
+
+    Ok(TcpTransport {
+        host: options.take_str("host")?,
+        bind: options.take_str("bind")?,
+        port,
+        family,
+    })
+}
+
+fn parse_unixexec(options: &mut Options) -> Result<Transport, AddressError> {
+    let path = options.take_path("path").ok_or_else(|| AddressError::MissingKey {
+        transport: "unixexec".into(),
+        key: "path".into(),
+    })?;
+
+    // argv0 defaults to the path, even when other arguments are given.
+    let argv0 = match options.take("argv0") {
+        Some(arg) => OsString::from_vec(arg),
+        None => path.clone().into_os_string(),
+    };
+    let mut argv = vec![argv0];
+    while let Some(arg) = options.take(&format!("argv{}", argv.len())) {
+        argv.push(OsString::from_vec(arg));
+    }
+
+    Ok(Transport::UnixExec { path, argv })
+}
+
+impl ServerAddress {
+    /// Return the transport name and options, as printed.
+    fn options(&self) -> (&str, Vec<(String, Vec<u8>)>) {
+        fn option<K: Into<String>, V: AsRef<[u8]>>(key: K, value: V) -> (String, Vec<u8>) {
+            (key.into(), value.as_ref().to_vec())
+        }
+
+        let mut options = Vec::new();
+        let name = match self.transport {
+            Transport::Unix(ref unix) => {
+                options.push(match unix {
+                    UnixTransport::Path(path) => option("path", path.as_os_str().as_bytes()),
+                    UnixTransport::Abstract(name) => option("abstract", name),
+                    UnixTransport::Dir(dir) => option("dir", dir.as_os_str().as_bytes()),
+                    UnixTransport::TmpDir(dir) => option("tmpdir", dir.as_os_str().as_bytes()),
+                    UnixTransport::Runtime => option("runtime", "yes"),
+                });
+                "unix"
+            }
+            Transport::Tcp(ref tcp) | Transport::NonceTcp { ref tcp, .. } => {
+                if let Some(host) = &tcp.host {
+                    options.push(option("host", host));
+                }
+                if let Some(bind) = &tcp.bind {
+                    options.push(option("bind", bind));
+                }
+                if let Some(port) = tcp.port {
+                    options.push(option("port", port.to_string()));
+                }
+                match tcp.family {
+                    Some(TcpFamily::Ipv4) => options.push(option("family", "ipv4")),
+                    Some(TcpFamily::Ipv6) => options.push(option("family", "ipv6")),
+                    None => {}
+                }
+
+                match self.transport {
+                    Transport::NonceTcp {
+                        noncefile: Some(ref noncefile),
+                        ..
+                    } => {
+                        options.push(option("noncefile", noncefile.as_os_str().as_bytes()));
+                        "nonce-tcp"
+                    }
+                    Transport::NonceTcp { .. } => "nonce-tcp",
+                    _ => "tcp",
+                }
+            }
+            Transport::UnixExec { ref path, ref argv } => {
+                options.push(option("path", path.as_os_str().as_bytes()));
+                for (index, arg) in argv.iter().enumerate() {
+                    options.push(option(format!("argv{}", index), arg.as_bytes()));
+                }
+                "unixexec"
+            }
+            Transport::Autolaunch { ref scope } => {
+                if let Some(scope) = scope {
+                    options.push(option("scope", scope));
+                }
+                "autolaunch"
+            }
+            Transport::Other {
+                ref name,
+                options: ref other,
+            } => {
+                options.extend(other.iter().cloned());
+                name
+            }
+        };
+
+        if let Some(guid) = &self.guid {
+            options.push(option("guid", guid));
+        }
+
+        (name, options)
+    }
+}
+
+impl fmt::Display for ServerAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (name, options) = self.options();
+        write!(f, "{}:", name)?;
+        for (index, (key, value)) in options.iter().enumerate() {
+            if index > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{}={}", key, escape(value))?;
+        }
+        Ok(())
+    }
+}
+
+/// Bytes which don't have to be escaped in values.
+fn is_optionally_escaped(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"-_/.\\*".contains(&byte)
+}
+
+fn escape(value: &[u8]) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for &byte in value {
+        if is_optionally_escaped(byte) {
+            escaped.push(byte as char);
+        } else {
+            escaped.push_str(&format!("%{:02x}", byte));
+        }
+    }
+    escaped
+}
+
+fn unescape(value: &str) -> Result<Vec<u8>, AddressError> {
+    let bytes = value.as_bytes();
+    let mut unescaped = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        match bytes[index] {
+            b'%' => {
+                let byte = value
+                    .get(index + 1..index + 3)
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .ok_or_else(|| invalid(format!("Invalid escape sequence in `{}`", value)))?;
+                unescaped.push(byte);
+                index += 3;
+            }
+            byte if is_optionally_escaped(byte) => {
+                unescaped.push(byte);
+                index += 1;
+            }
+            byte => {
+                return Err(invalid(format!(
+                    "Character `{}` must be escaped in `{}`",
+                    byte as char, value
+                )));
+            }
+        }
+    }
+    Ok(unescaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_address() {
+        let address: Address = "unix:path=/tmp/dbus%20test,guid=0123;tcp:host=localhost,port=4242,family=ipv4;\
+                                unixexec:path=/bin/dbus,argv1=-x;foo:bar=baz"
+            .parse()
+            .unwrap();
+
+        assert_eq!(
+            address.alternatives(),
+            &[
+                ServerAddress {
+                    transport: Transport::Unix(UnixTransport::Path("/tmp/dbus test".into())),
+                    guid: Some("0123".into()),
+                },
+                ServerAddress::new(Transport::Tcp(TcpTransport {
+                    host: Some("localhost".into()),
+                    bind: None,
+                    port: Some(4242),
+                    family: Some(TcpFamily::Ipv4),
+                })),
+                ServerAddress::new(Transport::UnixExec {
+                    path: "/bin/dbus".into(),
+                    argv: vec!["/bin/dbus".into(), "-x".into()],
+                }),
+                ServerAddress::new(Transport::Other {
+                    name: "foo".into(),
+                    options: vec![("bar".into(), b"baz".to_vec())],
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_print_address() {
+        for &address in &[
+            "unix:path=/tmp/dbus%20test,guid=0123",
+            "unix:abstract=/tmp/dbus-%00",
+            "unix:runtime=yes;unix:tmpdir=/tmp",
+            "nonce-tcp:host=127.0.0.1,port=0,noncefile=/tmp/nonce",
+            "unixexec:path=/bin/sh,argv0=sh,argv1=-c,argv2=dbus-stdio%3b",
+            "autolaunch:scope=%2a%2f",
+        ] {
+            let parsed: Address = address.parse().unwrap();
+            assert_eq!(parsed.to_string().parse::<Address>().unwrap(), parsed);
+        }
+
+        let address: Address = "autolaunch:scope=%2a%2f".parse().unwrap();
+        assert_eq!(address.to_string(), "autolaunch:scope=*/");
+    }
+
+    #[test]
+    fn test_invalid_address() {
+        for &address in &[
+            "",
+            "unix",
+            ":path=/tmp",
+            "unix:path=/tmp,abstract=foo",
+            "unix:",
+            "unix:path=a b",
+            "unix:path=%4",
+            "unix:path=/a,path=/b",
+            "tcp:port=http",
+            "unixexec:argv0=sh",
+        ] {
+            assert!(address.parse::<Address>().is_err(), "{}", address);
+        }
+    }
+
+    #[test]
+    fn test_bus_addresses() {
+        let vars = |address: &'static str| {
+            move |name: &str| match name {
+                "DBUS_SESSION_BUS_ADDRESS" | "DBUS_SYSTEM_BUS_ADDRESS" => Some(address.into()),
+                _ => None,
+            }
+        };
+
+        assert_eq!(
+            session_address(vars("unix:abstract=foo")).unwrap().to_string(),
+            "unix:abstract=foo"
+        );
+        assert_eq!(system_address(vars("tcp:port=1")).unwrap().to_string(), "tcp:port=1");
+        assert_eq!(
+            system_address(|_| None).unwrap().to_string(),
+            DEFAULT_SYSTEM_BUS_ADDRESS
+        );
+
+        let dir = env::temp_dir();
+        let runtime_dir = |dir: PathBuf| {
+            move |name: &str| match name {
+                "XDG_RUNTIME_DIR" => Some(dir.clone().into_os_string()),
+                _ => None,
+            }
+        };
+        assert!(matches!(
+            session_address(runtime_dir(dir.join("rbus-missing-dir"))),
+            Err(AddressError::NoSessionBus)
+        ));
+    }
+}
@@ -0,0 +1,363 @@
+use super::{cookie_digest, hex_encode, random_bytes, AuthError, Command, Guid, Keyring, LineReader};
+use std::{mem, str};
+
+/// The authentication mechanisms supported by the client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mechanism {
+    External,
+    DbusCookieSha1,
+    Anonymous,
+}
+
+impl Mechanism {
+    pub fn name(self) -> &'static str {
+        match self {
+            Mechanism::External => "EXTERNAL",
+            Mechanism::DbusCookieSha1 => "DBUS_COOKIE_SHA1",
+            Mechanism::Anonymous => "ANONYMOUS",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Mechanism> {
+        match name {
+            "EXTERNAL" => Some(Mechanism::External),
+            "DBUS_COOKIE_SHA1" => Some(Mechanism::DbusCookieSha1),
+            "ANONYMOUS" => Some(Mechanism::Anonymous),
+            _ => None,
+        }
+    }
+}
+
+/// The outcome of a successful authentication.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Authenticated {
+    pub guid: Guid,
+    pub unix_fd: bool,
+}
+
+#[derive(Debug)]
+enum State {
+    Initial,
+    WaitingForData,
+    WaitingForReject,
+    WaitingForAgree(Guid),
+    Done(Authenticated),
+}
+
+/// The client side of the authentication exchange.
+///
+/// `start` queues the credentials byte and the first `AUTH` command, then
+/// the server lines are given to `feed` until `authenticated` returns the
+/// outcome. The bytes to send are returned by `take_output`.
+#[derive(Debug)]
+pub struct Client {
+    uid: u32,
+    mechanisms: Vec<Mechanism>,
+    negotiate_unix_fd: bool,
+    keyring: Keyring,
+    current: usize,
+    state: State,
+    reader: LineReader,
+    output: Vec<u8>,
+}
+
+impl Client {
+    pub fn new() -> Client {
+        Client {
+            uid: unsafe { libc::getuid() },
+            mechanisms: vec![Mechanism::External, Mechanism::DbusCookieSha1, Mechanism::Anonymous],
+            negotiate_unix_fd: false,
+            keyring: Keyring::user(),
+            current: 0,
+            state: State::Initial,
+            reader: LineReader::default(),
+            output: Vec::new(),
+        }
+    }
+
+    pub fn uid(&mut self, uid: u32) {
+        self.uid = uid;
+    }
+
+    /// Set the mechanisms to try, in order.
+    pub fn mechanisms(&mut self, mechanisms: Vec<Mechanism>) {
+        self.mechanisms = mechanisms;
+    }
+
+    /// Ask the server to allow passing unix fds once authenticated.
+    pub fn negotiate_unix_fd(&mut self, negotiate: bool) {
+        self.negotiate_unix_fd = negotiate;
+    }
+
+    pub fn keyring(&mut self, keyring: Keyring) {
+        self.keyring = keyring;
+    }
+
+    /// Return the mechanism currently tried.
+    pub fn mechanism(&self) -> Option<Mechanism> {
+        self.mechanisms.get(self.current).copied()
+    }
+
+    /// Queue the credentials NUL byte and the `AUTH` command of the first
+    /// mechanism.
+    pub fn start(&mut self) -> Result<(), AuthError> {
+        self.output.push(b'\0');
+        self.current = 0;
+        self.auth()
+    }
+
+    /// Handle bytes received from the server, returning how many of them
+    /// were used. Bytes following the last line of the exchange are left.
+    pub fn feed(&mut self, data: &[u8]) -> Result<usize, AuthError> {
+        if let State::Initial = self.state {
+            return Err(AuthError::NotStarted);
+        }
+
+        let mut used = 0;
+        while !self.is_done() && used < data.len() {
+            let (line, length) = self.reader.next_line(&data[used..])?;
+            used += length;
+
+            match line {
+                Some(line) => self.handle(&line)?,
+                None => break,
+            }
+        }
+
+        Ok(used)
+    }
+
+    /// Return the bytes to send to the server.
+    pub fn take_output(&mut self) -> Vec<u8> {
+        mem::take(&mut self.output)
+    }
+
+    pub fn is_done(&self) -> bool {
+        matches!(self.state, State::Done(_))
+    }
+
+    pub fn authenticated(&self) -> Option<&Authenticated> {
+        match &self.state {
+            State::Done(authenticated) => Some(authenticated),
+            _ => None,
+        }
+    }
+
+    fn send(&mut self, command: Command) {
+        self.output.extend_from_slice(&command.to_line());
+    }
+
+    fn auth(&mut self) -> Result<(), AuthError> {
+        let mechanism = match self.mechanism() {
+            Some(mechanism) => mechanism,
+            None => {
+                return Err(AuthError::Rejected {
+                    mechanisms: String::new(),
+                });
+            }
+        };
+
+        let initial_response = match mechanism {
+            Mechanism::External | Mechanism::DbusCookieSha1 => self.uid.to_string(),
+            Mechanism::Anonymous => concat!("rbus ", env!("CARGO_PKG_VERSION")).into(),
+        };
+
+        self.send(Command::Auth {
+            mechanism: Some(mechanism.name().into()),
+            initial_response: Some(initial_response.into_bytes()),
+        });
+        self.state = State::WaitingForData;
+        Ok(())
+    }
+
+    /// Try the next mechanism supported by the server after a rejection.
+    fn fallback(&mut self, supported: Vec<String>) -> Result<(), AuthError> {
+        let next = self.mechanisms[self.current + 1..]
+            .iter()
+            .position(|mechanism| supported.is_empty() || supported.iter().any(|name| name == mechanism.name()));
+
+        match next {
+            Some(index) => {
+                self.current += index + 1;
+                self.auth()
+            }
+            None => Err(AuthError::Rejected {
+                mechanisms: supported.join(" "),
+            }),
+        }
+    }
+
+    fn handle(&mut self, line: &[u8]) -> Result<(), AuthError> {
+        let command = Command::parse(line)?;
+        let unexpected = || AuthError::UnexpectedCommand {
+            line: String::from_utf8_lossy(line).into_owned(),
+        };
+
+        match (mem::replace(&mut self.state, State::Initial), command) {
+            (State::WaitingForData, Command::Ok(guid)) if self.negotiate_unix_fd => {
+                self.send(Command::NegotiateUnixFd);
+                self.state = State::WaitingForAgree(guid);
+            }
+            (State::WaitingForData, Command::Ok(guid)) => self.begin(guid, false),
+            (State::WaitingForData, Command::Data(data)) => match self.respond(&data) {
+                Some(response) => {
+                    self.send(Command::Data(response));
+                    self.state = State::WaitingForData;
+                }
+                None => {
+                    self.send(Command::Cancel);
+                    self.state = State::WaitingForReject;
+                }
+            },
+            (State::WaitingForData, Command::Error(_)) => {
+                self.send(Command::Cancel);
+                self.state = State::WaitingForReject;
+            }
+            (State::WaitingForData, Command::Rejected(supported))
+            | (State::WaitingForReject, Command::Rejected(supported)) => self.fallback(supported)?,
+            (State::WaitingForData, _) => {
+                self.send(Command::Error("Unexpected command".into()));
+                self.state = State::WaitingForData;
+            }
+            (State::WaitingForAgree(guid), Command::AgreeUnixFd) => self.begin(guid, true),
+            (State::WaitingForAgree(guid), Command::Error(_)) => self.begin(guid, false),
+            _ => return Err(unexpected()),
+        }
+
+        Ok(())
+    }
+
+    fn begin(&mut self, guid: Guid, unix_fd: bool) {
+        self.send(Command::Begin);
+        self.state = State::Done(Authenticated { guid, unix_fd });
+    }
+
+    /// Return the response to a server challenge, or `None` if the current
+    /// mechanism can't answer it.
+    fn respond(&self, data: &[u8]) -> Option<Vec<u8>> {
+        match self.mechanism()? {
+            Mechanism::DbusCookieSha1 => {
+                let data = str::from_utf8(data).ok()?;
+                let words = data.split(' ').collect::<Vec<_>>();
+                let (context, id, server_challenge) = match words.as_slice() {
+                    [context, id, challenge] => (context, id, challenge),
+                    _ => return None,
+                };
+
+                let cookie = self.keyring.cookie(context, id).ok()?;
+                let client_challenge = hex_encode(&random_bytes(16).ok()?);
+                let digest = cookie_digest(server_challenge, &client_challenge, &cookie);
+                Some(format!("{} {}", client_challenge, digest).into_bytes())
+            }
+            Mechanism::External | Mechanism::Anonymous if data.is_empty() => Some(Vec::new()),
+            Mechanism::External | Mechanism::Anonymous => None,
+        }
+    }
+}
+
+impl Default for Client {
+    fn default() -> Client {
+        Client::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        env,
+        fs::{self, DirBuilder},
+        os::unix::fs::DirBuilderExt,
+    };
+
+    const GUID: &str = "0123456789abcdef0123456789abcdef";
+
+    fn exchange(client: &mut Client, server: &[u8]) -> Vec<u8> {
+        assert_eq!(client.feed(server).unwrap(), server.len());
+        client.take_output()
+    }
+
+    #[test]
+    fn test_external() {
+        let mut client = Client::new();
+        client.uid(1000);
+        client.negotiate_unix_fd(true);
+
+        client.start().unwrap();
+        assert_eq!(client.take_output(), b"\0AUTH EXTERNAL 31303030\r\n");
+        assert_eq!(
+            exchange(&mut client, format!("OK {}\r\n", GUID).as_bytes()),
+            b"NEGOTIATE_UNIX_FD\r\n"
+        );
+        assert!(!client.is_done());
+        assert_eq!(exchange(&mut client, b"AGREE_UNIX_FD\r\n"), b"BEGIN\r\n");
+
+        let authenticated = client.authenticated().unwrap();
+        assert_eq!(authenticated.guid.as_str(), GUID);
+        assert!(authenticated.unix_fd);
+    }
+
+    #[test]
+    fn test_fallback() {
+        let mut client = Client::new();
+        client.uid(1000);
+        client.mechanisms(vec![
+            Mechanism::External,
+            Mechanism::DbusCookieSha1,
+            Mechanism::Anonymous,
+        ]);
+
+        client.start().unwrap();
+        client.take_output();
+        let output = exchange(&mut client, b"REJECTED ANONYMOUS\r\n");
+        assert!(output.starts_with(b"AUTH ANONYMOUS "));
+        assert_eq!(client.mechanism(), Some(Mechanism::Anonymous));
+
+        // Lines following the exchange are left to the caller.
+        let data = format!("OK {}\r\nl\0\0\0", GUID);
+        assert_eq!(client.feed(data.as_bytes()).unwrap(), 37);
+        assert_eq!(client.take_output(), b"BEGIN\r\n");
+        assert!(!client.authenticated().unwrap().unix_fd);
+
+        let mut client = Client::new();
+        client.mechanisms(vec![Mechanism::External]);
+        client.start().unwrap();
+        assert!(matches!(
+            client.feed(b"REJECTED DBUS_COOKIE_SHA1\r\n"),
+            Err(AuthError::Rejected { .. })
+        ));
+        assert!(matches!(Client::new().feed(b"OK\r\n"), Err(AuthError::NotStarted)));
+    }
+
+    #[test]
+    fn test_cookie_sha1() {
+        let dir = env::temp_dir().join(format!("rbus-client-test-{}", std::process::id()));
+        DirBuilder::new().mode(0o700).create(&dir).unwrap();
+        fs::write(dir.join("org_freedesktop_general"), "7 1500000000 c00c1e\n").unwrap();
+
+        let mut client = Client::new();
+        client.uid(1000);
+        client.mechanisms(vec![Mechanism::DbusCookieSha1]);
+        client.keyring(Keyring::new(&dir));
+        client.start().unwrap();
+        assert_eq!(client.take_output(), b"\0AUTH DBUS_COOKIE_SHA1 31303030\r\n");
+
+        let challenge = hex_encode(b"org_freedesktop_general 7 5e12e7");
+        let output = exchange(&mut client, format!("DATA {}\r\n", challenge).as_bytes());
+        let response = match Command::parse(&output[..output.len() - 2]).unwrap() {
+            Command::Data(response) => String::from_utf8(response).unwrap(),
+            command => panic!("Unexpected command: {:?}", command),
+        };
+        let (client_challenge, digest) = response.split_at(response.find(' ').unwrap());
+        assert_eq!(&digest[1..], cookie_digest("5e12e7", client_challenge, "c00c1e"));
+
+        // An unknown cookie makes the client cancel and wait for a rejection.
+        let output = exchange(
+            &mut client,
+            format!("DATA {}\r\n", hex_encode(b"org_freedesktop_general 8 5e12e7")).as_bytes(),
+        );
+        assert_eq!(output, b"CANCEL\r\n");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,193 @@
+use super::{hex_decode, hex_encode, AuthError, Guid};
+use std::str;
+
+/// A line of the authentication exchange, sent by the client or the server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Start authenticating with a mechanism and an optional initial
+    /// response. A bare `AUTH` asks the server for its mechanisms.
+    Auth {
+        mechanism: Option<String>,
+        initial_response: Option<Vec<u8>>,
+    },
+    Cancel,
+    Begin,
+    Data(Vec<u8>),
+    Error(String),
+    NegotiateUnixFd,
+    Rejected(Vec<String>),
+    Ok(Guid),
+    AgreeUnixFd,
+}
+
+impl Command {
+    /// Parse a line, without its `\r\n` terminator.
+    pub fn parse(line: &[u8]) -> Result<Command, AuthError> {
+        let invalid = || AuthError::InvalidCommand {
+            line: String::from_utf8_lossy(line).into_owned(),
+        };
+
+        let line = str::from_utf8(line).map_err(|_| invalid())?;
+        if !line.is_ascii() {
+            return Err(invalid());
+        }
+
+        let (name, args) = match line.find(' ') {
+            Some(index) => (&line[..index], line[index + 1..].trim()),
+            None => (line, ""),
+        };
+        let words = args.split_whitespace().collect::<Vec<_>>();
+
+        let command = match (name, words.as_slice()) {
+            ("AUTH", []) => Command::Auth {
+                mechanism: None,
+                initial_response: None,
+            },
+            ("AUTH", [mechanism]) => Command::Auth {
+                mechanism: Some((*mechanism).into()),
+                initial_response: None,
+            },
+            ("AUTH", [mechanism, response]) => Command::Auth {
+                mechanism: Some((*mechanism).into()),
+                initial_response: Some(hex_decode(response)?),
+            },
+            ("CANCEL", []) => Command::Cancel,
+            ("BEGIN", []) => Command::Begin,
+            ("DATA", []) => Command::Data(Vec::new()),
+            ("DATA", [data]) => Command::Data(hex_decode(data)?),
+            ("ERROR", _) => Command::Error(args.into()),
+            ("NEGOTIATE_UNIX_FD", []) => Command::NegotiateUnixFd,
+            ("REJECTED", mechanisms) => Command::Rejected(mechanisms.iter().map(|&name| name.into()).collect()),
+            ("OK", [guid]) => Command::Ok(Guid::new(*guid)?),
+            ("AGREE_UNIX_FD", []) => Command::AgreeUnixFd,
+            _ => return Err(invalid()),
+        };
+
+        Ok(command)
+    }
+
+    /// Return the line for this command, `\r\n` terminator included.
+    pub fn to_line(&self) -> Vec<u8> {
+        let line = match self {
+            Command::Auth {
+                mechanism,
+                initial_response,
+            } => {
+                let mut line = String::from("AUTH");
+                if let Some(mechanism) = mechanism {
+                    line.push(' ');
+                    line.push_str(mechanism);
+                }
+                if let Some(response) = initial_response {
+                    line.push(' ');
+                    line.push_str(&hex_encode(response));
+                }
+                line
+            }
+            Command::Cancel => "CANCEL".into(),
+            Command::Begin => "BEGIN".into(),
+            Command::Data(data) if data.is_empty() => "DATA".into(),
+            Command::Data(data) => format!("DATA {}", hex_encode(data)),
+            Command::Error(message) if message.is_empty() => "ERROR".into(),
+            Command::Error(message) => format!("ERROR {}", message),
+            Command::NegotiateUnixFd => "NEGOTIATE_UNIX_FD".into(),
+            Command::Rejected(mechanisms) => {
+                let mut line = String::from("REJECTED");
+                for mechanism in mechanisms {
+                    line.push(' ');
+                    line.push_str(mechanism);
+                }
+                line
+            }
+            Command::Ok(guid) => format!("OK {}", guid),
+            Command::AgreeUnixFd => "AGREE_UNIX_FD".into(),
+        };
+
+        let mut line = line.into_bytes();
+        line.extend_from_slice(b"\r\n");
+        line
+    }
+}
+
+/// Split received bytes into lines, keeping what follows the last complete
+/// line for later.
+#[derive(Debug, Default)]
+pub(crate) struct LineReader {
+    buffer: Vec<u8>,
+}
+
+impl LineReader {
+    /// Return the next complete line of `data` appended to the buffered
+    /// bytes, along with how many bytes of `data` were used.
+    pub fn next_line(&mut self, data: &[u8]) -> Result<(Option<Vec<u8>>, usize), AuthError> {
+        let buffered = self.buffer.len();
+        let search_start = buffered.saturating_sub(1);
+        self.buffer.extend_from_slice(data);
+
+        let end = self.buffer[search_start..]
+            .windows(2)
+            .position(|window| window == b"\r\n")
+            .map(|index| search_start + index);
+
+        match end {
+            Some(end) => {
+                let rest = self.buffer.split_off(end + 2);
+                let mut line = std::mem::take(&mut self.buffer);
+                line.truncate(end);
+                Ok((Some(line), data.len() - rest.len()))
+            }
+            None if self.buffer.len() >= super::MAX_LINE_LENGTH => Err(AuthError::LineTooLong),
+            None => Ok((None, data.len())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_roundtrip() {
+        let commands = vec![
+            Command::Auth {
+                mechanism: None,
+                initial_response: None,
+            },
+            Command::Auth {
+                mechanism: Some("EXTERNAL".into()),
+                initial_response: Some(b"1000".to_vec()),
+            },
+            Command::Data(Vec::new()),
+            Command::Data(b"abc".to_vec()),
+            Command::Error("Nope".into()),
+            Command::Rejected(vec!["EXTERNAL".into(), "ANONYMOUS".into()]),
+            Command::Ok(Guid::new("0123456789abcdef0123456789abcdef").unwrap()),
+            Command::NegotiateUnixFd,
+            Command::AgreeUnixFd,
+            Command::Begin,
+            Command::Cancel,
+        ];
+
+        for command in commands {
+            let line = command.to_line();
+            assert!(line.ends_with(b"\r\n"));
+            assert_eq!(Command::parse(&line[..line.len() - 2]).unwrap(), command);
+        }
+
+        assert_eq!(Command::to_line(&Command::Data(b"1000".to_vec())), b"DATA 31303030\r\n");
+        assert!(Command::parse(b"BEGIN now").is_err());
+        assert!(Command::parse(b"OK").is_err());
+        assert!(Command::parse(b"DATA xyz").is_err());
+        assert!(Command::parse(b"HELLO").is_err());
+    }
+
+    #[test]
+    fn test_line_reader() {
+        let mut reader = LineReader::default();
+        assert_eq!(reader.next_line(b"OK 01\r").unwrap(), (None, 6));
+        assert_eq!(reader.next_line(b"\nAGREE").unwrap(), (Some(b"OK 01".to_vec()), 1));
+        assert_eq!(reader.next_line(b"AGREE").unwrap(), (None, 5));
+        assert_eq!(reader.next_line(b"\r\n").unwrap(), (Some(b"AGREE".to_vec()), 2));
+        assert!(reader.next_line(&vec![b'a'; crate::auth::MAX_LINE_LENGTH]).is_err());
+    }
+}
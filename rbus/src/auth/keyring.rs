@@ -0,0 +1,328 @@
+use super::{hex_encode, random_bytes, AuthError};
+use std::{
+    env,
+    fs::{self, DirBuilder, OpenOptions},
+    io::{self, Write},
+    os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt},
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Cookies older than this aren't handed out for new exchanges.
+const NEW_COOKIE_AGE: u64 = 5 * 60;
+/// Cookies older than this are removed from the keyring.
+const COOKIE_EXPIRATION: u64 = NEW_COOKIE_AGE + 2 * 60;
+/// Cookies created further than this in the future are removed too.
+const MAX_TIME_TRAVEL: u64 = 5 * 60;
+/// The number of random bytes of a new cookie.
+const COOKIE_SIZE: usize = 24;
+/// Locks are only held while a context file is rewritten, older ones were
+/// left over by a crashed process.
+const STALE_LOCK_AGE: Duration = Duration::from_secs(8);
+const LOCK_RETRY_DELAY: Duration = Duration::from_millis(250);
+
+/// The cookies shared by the clients and servers of a user, used by the
+/// `DBUS_COOKIE_SHA1` mechanism.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keyring {
+    dir: PathBuf,
+    lock_timeout: Duration,
+}
+
+/// A cookie of a keyring context.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cookie {
+    pub id: u32,
+    pub created: u64,
+    pub cookie: String,
+}
+
+impl Keyring {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Keyring {
+        Keyring {
+            dir: dir.into(),
+            lock_timeout: Duration::from_secs(0),
+        }
+    }
+
+    /// Return the keyring of the current user, in `~/.dbus-keyrings`.
+    pub fn user() -> Keyring {
+        let home = env::var_os("HOME").unwrap_or_default();
+        Keyring::new(PathBuf::from(home).join(".dbus-keyrings"))
+    }
+
+    pub fn dir(&self) -> &PathBuf {
+        &self.dir
+    }
+
+    /// Set how long `current_cookie` waits for another process to release
+    /// a context, it fails right away by default since it blocks the thread.
+    pub fn lock_timeout(&mut self, timeout: Duration) {
+        self.lock_timeout = timeout;
+    }
+
+    /// Return the path of the file holding the cookies of `context`.
+    pub fn context_path(&self, context: &str) -> Result<PathBuf, AuthError> {
+        let valid = !context.is_empty()
+            && context
+                .bytes()
+                .all(|byte| byte.is_ascii_graphic() && byte != b'/' && byte != b'\\' && byte != b'.');
+        if !valid {
+            return Err(AuthError::InvalidCookieContext {
+                context: context.into(),
+            });
+        }
+
+        Ok(self.dir.join(context))
+    }
+
+    /// Check that the keyring directory belongs to the current user and
+    /// can't be accessed by anyone else, otherwise its cookies can't be
+    /// trusted.
+    fn check_permissions(&self) -> Result<(), AuthError> {
+        let metadata = fs::metadata(&self.dir)?;
+        let uid = unsafe { libc::geteuid() };
+        if !metadata.is_dir() || metadata.uid() != uid || metadata.mode() & 0o077 != 0 {
+            return Err(AuthError::InsecureKeyring {
+                dir: self.dir.display().to_string(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Read the cookies of `context`, skipping malformed lines.
+    pub fn cookies(&self, context: &str) -> Result<Vec<Cookie>, AuthError> {
+        let path = self.context_path(context)?;
+        self.check_permissions()?;
+        let data = fs::read_to_string(path)?;
+        Ok(data.lines().filter_map(parse_cookie).collect())
+    }
+
+    /// Return the cookie `id` of `context`.
+    pub fn cookie(&self, context: &str, id: &str) -> Result<String, AuthError> {
+        let not_found = || AuthError::CookieNotFound {
+            context: context.into(),
+            id: id.into(),
+        };
+
+        let id = id.parse::<u32>().map_err(|_| not_found())?;
+        self.cookies(context)?
+            .into_iter()
+            .find(|cookie| cookie.id == id)
+            .map(|cookie| cookie.cookie)
+            .ok_or_else(not_found)
+    }
+
+    /// Return a recent cookie of `context` to challenge clients with,
+    /// dropping the expired cookies and adding a new one when needed.
+    pub fn current_cookie(&self, context: &str) -> Result<Cookie, AuthError> {
+        let path = self.context_path(context)?;
+        DirBuilder::new().recursive(true).mode(0o700).create(&self.dir)?;
+        self.check_permissions()?;
+        let _lock = KeyringLock::acquire(&path, self.lock_timeout)?;
+
+        let mut cookies = match fs::read_to_string(&path) {
+            Ok(data) => data.lines().filter_map(parse_cookie).collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs())
+            .unwrap_or_default();
+        let count = cookies.len();
+        cookies.retain(|cookie| cookie.created + COOKIE_EXPIRATION > now && cookie.created <= now + MAX_TIME_TRAVEL);
+        let mut changed = cookies.len() != count;
+
+        let recent = cookies
+            .iter()
+            .filter(|cookie| cookie.created + NEW_COOKIE_AGE > now && cookie.created <= now)
+            .max_by_key(|cookie| cookie.created)
+            .cloned();
+        let current = match recent {
+            Some(cookie) => cookie,
+            None => {
+                let cookie = Cookie {
+                    id: cookies
+                        .iter()
+                        .map(|cookie| cookie.id)
+                        .max()
+                        .map_or(1, |id| id.wrapping_add(1)),
+                    created: now,
+                    cookie: hex_encode(&random_bytes(COOKIE_SIZE)?),
+                };
+                cookies.push(cookie.clone());
+                changed = true;
+                cookie
+            }
+        };
+
+        if changed {
+            write_cookies(&path, &cookies)?;
+        }
+
+        Ok(current)
+    }
+}
+
+/// Replace the cookies file at `path`, through a temporary file so readers
+/// never see it partially written.
+fn write_cookies(path: &Path, cookies: &[Cookie]) -> io::Result<()> {
+    let temp = path.with_extension("tmp");
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&temp)?;
+    for cookie in cookies {
+        writeln!(file, "{} {} {}", cookie.id, cookie.created, cookie.cookie)?;
+    }
+    drop(file);
+
+    fs::rename(&temp, path)
+}
+
+/// The lock file keeping other processes from writing a keyring context.
+struct KeyringLock(PathBuf);
+
+impl KeyringLock {
+    /// Lock the context file at `path`, waiting up to `timeout` for another
+    /// process to release it. Stale locks are removed.
+    fn acquire(path: &Path, timeout: Duration) -> Result<KeyringLock, AuthError> {
+        let lock = path.with_extension("lock");
+        let start = Instant::now();
+
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(&lock) {
+                Ok(_) => return Ok(KeyringLock(lock)),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(e.into()),
+            }
+
+            if is_stale(&lock) {
+                match fs::remove_file(&lock) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                    _ => continue,
+                }
+            }
+
+            if start.elapsed() + LOCK_RETRY_DELAY > timeout {
+                return Err(AuthError::KeyringLocked {
+                    path: path.display().to_string(),
+                });
+            }
+            thread::sleep(LOCK_RETRY_DELAY);
+        }
+    }
+}
+
+fn is_stale(lock: &Path) -> bool {
+    let age = fs::metadata(lock)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok());
+    matches!(age, Some(age) if age > STALE_LOCK_AGE)
+}
+
+impl Drop for KeyringLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+fn parse_cookie(line: &str) -> Option<Cookie> {
+    let mut words = line.split_whitespace();
+    let cookie = Cookie {
+        id: words.next()?.parse().ok()?,
+        created: words.next()?.parse().ok()?,
+        cookie: words.next()?.into(),
+    };
+
+    if words.next().is_some() {
+        return None;
+    }
+
+    Some(cookie)
+}
+
+/// Return the SHA-1 hex digest the `DBUS_COOKIE_SHA1` mechanism expects for
+/// the given challenges and cookie.
+pub(crate) fn cookie_digest(server_challenge: &str, client_challenge: &str, cookie: &str) -> String {
+    let data = format!("{}:{}:{}", server_challenge, client_challenge, cookie);
+    sha1::Sha1::from(data.as_bytes()).digest().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_keyring() {
+        let dir = env::temp_dir().join(format!("rbus-keyring-test-{}", std::process::id()));
+        DirBuilder::new().mode(0o700).create(&dir).unwrap();
+        fs::write(
+            dir.join("org_rbus_test"),
+            "1 1500000000 abcd\ninvalid\n2 1500000100 ef01\n",
+        )
+        .unwrap();
+
+        let keyring = Keyring::new(&dir);
+        assert_eq!(keyring.cookies("org_rbus_test").unwrap().len(), 2);
+        assert_eq!(keyring.cookie("org_rbus_test", "2").unwrap(), "ef01");
+        assert!(keyring.cookie("org_rbus_test", "3").is_err());
+        assert!(keyring.cookie("../test", "1").is_err());
+
+        // Cookies readable by other users may have been tampered with.
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o750)).unwrap();
+        assert!(matches!(keyring.cookies("org_rbus_test"), Err(AuthError::InsecureKeyring { .. })));
+        assert!(matches!(keyring.current_cookie("org_rbus_test"), Err(AuthError::InsecureKeyring { .. })));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_current_cookie() {
+        let dir = env::temp_dir().join(format!("rbus-keyring-rotation-test-{}", std::process::id()));
+        let keyring = Keyring::new(&dir);
+
+        let cookie = keyring.current_cookie("org_rbus_test").unwrap();
+        assert_eq!(cookie.id, 1);
+        assert_eq!(cookie.cookie.len(), COOKIE_SIZE * 2);
+        assert_eq!(keyring.current_cookie("org_rbus_test").unwrap(), cookie);
+
+        // Expired cookies are dropped and old ones aren't handed out anymore.
+        let old = cookie.created - NEW_COOKIE_AGE;
+        let expired = cookie.created - COOKIE_EXPIRATION;
+        fs::write(dir.join("org_rbus_test"), format!("3 {} aa\n4 {} bb\n", expired, old)).unwrap();
+        let cookie = keyring.current_cookie("org_rbus_test").unwrap();
+        assert_eq!(cookie.id, 5);
+        let ids = keyring
+            .cookies("org_rbus_test")
+            .unwrap()
+            .iter()
+            .map(|cookie| cookie.id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![4, 5]);
+        assert!(!dir.join("org_rbus_test.lock").exists());
+
+        // Locks held by other processes are left alone.
+        fs::write(dir.join("org_rbus_test.lock"), "").unwrap();
+        assert!(matches!(
+            keyring.current_cookie("org_rbus_test"),
+            Err(AuthError::KeyringLocked { .. })
+        ));
+        assert!(dir.join("org_rbus_test.lock").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_cookie_digest() {
+        assert_eq!(cookie_digest("a", "b", "c"), "70bce09e827a98fe6acf7c3e9b0bcf136bc382ed");
+    }
+}
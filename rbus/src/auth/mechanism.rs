@@ -0,0 +1,222 @@
+use super::{cookie_digest, hex_encode, random_bytes, Credentials, Keyring};
+use std::str;
+
+/// The outcome of a step of a server mechanism.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    /// The client is authenticated.
+    Accept,
+    /// Send this challenge and wait for the client response.
+    Challenge(Vec<u8>),
+    /// The client failed to authenticate.
+    Reject,
+}
+
+/// A mechanism the server can authenticate clients with.
+///
+/// A mechanism instance handles the exchanges of one server, `start` is
+/// called again for each `AUTH` command selecting it.
+pub trait ServerMechanism: Send {
+    /// The mechanism name, as sent in `AUTH` and `REJECTED` commands.
+    fn name(&self) -> &str;
+
+    /// Handle the `AUTH` command selecting this mechanism.
+    fn start(&mut self, initial_response: Option<&[u8]>, credentials: &Credentials) -> Step;
+
+    /// Handle the client response to the last challenge.
+    fn data(&mut self, data: &[u8], credentials: &Credentials) -> Step;
+}
+
+fn parse_uid(data: &[u8]) -> Option<u32> {
+    str::from_utf8(data).ok()?.parse().ok()
+}
+
+/// The `EXTERNAL` mechanism, checking the uid claimed by the client against
+/// the credentials of the socket.
+#[derive(Debug, Clone)]
+pub struct ExternalMechanism {
+    allowed_uid: Option<u32>,
+}
+
+impl ExternalMechanism {
+    /// Accept the clients running as the same user as this process.
+    pub fn new() -> ExternalMechanism {
+        ExternalMechanism {
+            allowed_uid: Some(unsafe { libc::getuid() }),
+        }
+    }
+
+    /// Accept the clients of any user, as long as they don't lie about it.
+    pub fn any_user() -> ExternalMechanism {
+        ExternalMechanism { allowed_uid: None }
+    }
+
+    pub fn allowed_uid(&self) -> Option<u32> {
+        self.allowed_uid
+    }
+
+    fn check(&self, data: &[u8], credentials: &Credentials) -> Step {
+        let peer_uid = match credentials.uid {
+            Some(uid) => uid,
+            None => return Step::Reject,
+        };
+
+        // An empty response asks for the identity of the credentials.
+        let claimed_uid = if data.is_empty() {
+            Some(peer_uid)
+        } else {
+            parse_uid(data)
+        };
+        let allowed = self.allowed_uid.unwrap_or(peer_uid) == peer_uid;
+
+        if allowed && claimed_uid == Some(peer_uid) {
+            Step::Accept
+        } else {
+            Step::Reject
+        }
+    }
+}
+
+impl Default for ExternalMechanism {
+    fn default() -> ExternalMechanism {
+        ExternalMechanism::new()
+    }
+}
+
+impl ServerMechanism for ExternalMechanism {
+    fn name(&self) -> &str {
+        "EXTERNAL"
+    }
+
+    fn start(&mut self, initial_response: Option<&[u8]>, credentials: &Credentials) -> Step {
+        match initial_response {
+            Some(data) => self.check(data, credentials),
+            None => Step::Challenge(Vec::new()),
+        }
+    }
+
+    fn data(&mut self, data: &[u8], credentials: &Credentials) -> Step {
+        self.check(data, credentials)
+    }
+}
+
+/// The `ANONYMOUS` mechanism, accepting any client.
+#[derive(Debug, Clone, Default)]
+pub struct AnonymousMechanism;
+
+impl ServerMechanism for AnonymousMechanism {
+    fn name(&self) -> &str {
+        "ANONYMOUS"
+    }
+
+    fn start(&mut self, _initial_response: Option<&[u8]>, _credentials: &Credentials) -> Step {
+        Step::Accept
+    }
+
+    fn data(&mut self, _data: &[u8], _credentials: &Credentials) -> Step {
+        Step::Accept
+    }
+}
+
+/// The `DBUS_COOKIE_SHA1` mechanism, checking the client can read a cookie
+/// of the keyring of the user this process runs as.
+#[derive(Debug, Clone)]
+pub struct CookieSha1Mechanism {
+    keyring: Keyring,
+    context: String,
+    uid: u32,
+    challenge: Option<(String, String)>,
+}
+
+impl CookieSha1Mechanism {
+    pub const DEFAULT_CONTEXT: &'static str = "org_freedesktop_general";
+
+    pub fn new(keyring: Keyring) -> CookieSha1Mechanism {
+        CookieSha1Mechanism {
+            keyring,
+            context: CookieSha1Mechanism::DEFAULT_CONTEXT.into(),
+            uid: unsafe { libc::getuid() },
+            challenge: None,
+        }
+    }
+
+    pub fn context<T: Into<String>>(&mut self, context: T) {
+        self.context = context.into();
+    }
+
+    /// Set the uid clients must claim, the one owning the keyring.
+    pub fn uid(&mut self, uid: u32) {
+        self.uid = uid;
+    }
+}
+
+impl ServerMechanism for CookieSha1Mechanism {
+    fn name(&self) -> &str {
+        "DBUS_COOKIE_SHA1"
+    }
+
+    fn start(&mut self, initial_response: Option<&[u8]>, _credentials: &Credentials) -> Step {
+        self.challenge = None;
+        if initial_response.and_then(parse_uid) != Some(self.uid) {
+            return Step::Reject;
+        }
+
+        let cookie = match self.keyring.current_cookie(&self.context) {
+            Ok(cookie) => cookie,
+            Err(_) => return Step::Reject,
+        };
+        let server_challenge = match random_bytes(16) {
+            Ok(bytes) => hex_encode(&bytes),
+            Err(_) => return Step::Reject,
+        };
+
+        let challenge = format!("{} {} {}", self.context, cookie.id, server_challenge);
+        self.challenge = Some((server_challenge, cookie.cookie));
+        Step::Challenge(challenge.into_bytes())
+    }
+
+    fn data(&mut self, data: &[u8], _credentials: &Credentials) -> Step {
+        let (server_challenge, cookie) = match self.challenge.take() {
+            Some(challenge) => challenge,
+            None => return Step::Reject,
+        };
+
+        let response = str::from_utf8(data).ok().and_then(|data| {
+            let index = data.find(' ')?;
+            Some((&data[..index], &data[index + 1..]))
+        });
+
+        match response {
+            Some((client_challenge, digest))
+                if !client_challenge.is_empty()
+                    && digest == cookie_digest(&server_challenge, client_challenge, &cookie) =>
+            {
+                Step::Accept
+            }
+            _ => Step::Reject,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_external() {
+        let credentials = Credentials {
+            uid: Some(1000),
+            ..Credentials::default()
+        };
+
+        let mut mechanism = ExternalMechanism::any_user();
+        assert_eq!(mechanism.start(Some(b"1000"), &credentials), Step::Accept);
+        assert_eq!(mechanism.start(Some(b"0"), &credentials), Step::Reject);
+        assert_eq!(mechanism.start(None, &credentials), Step::Challenge(Vec::new()));
+        assert_eq!(mechanism.data(b"", &credentials), Step::Accept);
+        assert_eq!(mechanism.start(Some(b"1000"), &Credentials::default()), Step::Reject);
+
+        let mut mechanism = ExternalMechanism { allowed_uid: Some(0) };
+        assert_eq!(mechanism.start(Some(b"1000"), &credentials), Step::Reject);
+    }
+}
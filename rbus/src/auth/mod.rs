@@ -0,0 +1,147 @@
+//! The SASL exchange authenticating a connection before any message is sent.
+//!
+//! The client and server state machines don't do any socket I/O: received
+//! bytes are given to them and the bytes they produce must be sent by the
+//! caller. They still touch the filesystem: challenges are read from
+//! `/dev/urandom` and the `DBUS_COOKIE_SHA1` mechanism reads the keyring in
+//! `~/.dbus-keyrings`, which the server also writes new cookies to.
+
+pub use client::*;
+pub use command::*;
+use custom_error::custom_error;
+pub use keyring::*;
+pub use mechanism::*;
+pub use server::*;
+use std::{
+    fmt,
+    fs::File,
+    io,
+    io::Read,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+mod client;
+mod command;
+mod keyring;
+mod mechanism;
+mod server;
+
+/// The maximum length of a line of the exchange, terminator included.
+pub const MAX_LINE_LENGTH: usize = 16 * 1024;
+
+custom_error! {
+    pub AuthError
+        Io { source: io::Error }
+            = "I/O error: {source}",
+        InvalidCommand { line: String }
+            = "Invalid authentication command: {line}",
+        UnexpectedCommand { line: String }
+            = "Unexpected authentication command: {line}",
+        LineTooLong
+            = "Authentication line exceeds the maximum line length",
+        InvalidHex { data: String }
+            = "Invalid hex data: {data}",
+        InvalidGuid { guid: String }
+            = "Invalid GUID: {guid}",
+        InvalidCookieContext { context: String }
+            = "Invalid cookie context: {context}",
+        CookieNotFound { context: String, id: String }
+            = "Cookie {id} not found in the {context} keyring",
+        KeyringLocked { path: String }
+            = "The keyring context {path} is locked by another process",
+        InsecureKeyring { dir: String }
+            = "The keyring directory {dir} must be owned by the current user and only accessible by them",
+        NotStarted
+            = "Authentication wasn't started",
+        Rejected { mechanisms: String }
+            = "Authentication rejected, the server supports: {mechanisms}",
+        MissingCredentialsByte { value: u8 }
+            = "Expected the credentials NUL byte, found {value}",
+        TooManyFailures { failures: u32 }
+            = "Authentication failed {failures} times",
+}
+
+/// The GUID identifying a server, 128 bits written as hex digits.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Guid(String);
+
+impl Guid {
+    pub fn new<T: Into<String>>(guid: T) -> Result<Guid, AuthError> {
+        let guid = guid.into();
+        if guid.len() != 32 || !guid.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            return Err(AuthError::InvalidGuid { guid });
+        }
+
+        Ok(Guid(guid))
+    }
+
+    /// Generate a new GUID: 96 random bits followed by the current time.
+    pub fn generate() -> Result<Guid, AuthError> {
+        let mut bytes = random_bytes(12)?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs() as u32)
+            .unwrap_or_default();
+        bytes.extend_from_slice(&now.to_be_bytes());
+
+        Ok(Guid(hex_encode(&bytes)))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+pub(crate) fn hex_encode(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub(crate) fn hex_decode(data: &str) -> Result<Vec<u8>, AuthError> {
+    let invalid = || AuthError::InvalidHex { data: data.into() };
+    if data.len() % 2 == 1 || !data.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+
+    (0..data.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(&data[index..index + 2], 16).map_err(|_| invalid()))
+        .collect()
+}
+
+/// Return `length` random bytes, read from the system random source.
+pub(crate) fn random_bytes(length: usize) -> io::Result<Vec<u8>> {
+    let mut bytes = vec![0; length];
+    File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(hex_encode(b"\x00\x10rbus"), "001072627573");
+        assert_eq!(hex_decode("001072627573").unwrap(), b"\x00\x10rbus");
+        assert!(hex_decode("0").is_err());
+        assert!(hex_decode("zz").is_err());
+        assert!(hex_decode("+1").is_err());
+    }
+
+    #[test]
+    fn test_guid() {
+        assert!(Guid::new("0123456789abcdef0123456789ABCDEF").is_ok());
+        assert!(Guid::new("0123456789abcdef").is_err());
+        assert!(Guid::new("0123456789abcdef0123456789abcdeg").is_err());
+
+        let guid = Guid::generate().unwrap();
+        assert!(Guid::new(guid.as_str()).is_ok());
+        assert_ne!(Guid::generate().unwrap(), guid);
+    }
+}
@@ -0,0 +1,404 @@
+use super::{AuthError, Authenticated, Command, Guid, LineReader, ServerMechanism, Step};
+use std::{io, mem, os::unix::io::AsRawFd};
+
+/// The default number of failed attempts after which the server gives up.
+pub const DEFAULT_MAX_FAILURES: u32 = 6;
+
+/// The credentials of the peer of a connection, as known by the kernel.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Credentials {
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub pid: Option<u32>,
+}
+
+impl Credentials {
+    /// Return the credentials of the peer of a unix socket.
+    #[cfg(target_os = "linux")]
+    pub fn from_socket<S: AsRawFd>(socket: &S) -> io::Result<Credentials> {
+        let mut ucred = libc::ucred { pid: 0, uid: 0, gid: 0 };
+        let mut length = mem::size_of::<libc::ucred>() as libc::socklen_t;
+        let result = unsafe {
+            libc::getsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PEERCRED,
+                &mut ucred as *mut libc::ucred as *mut libc::c_void,
+                &mut length,
+            )
+        };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Credentials {
+            uid: Some(ucred.uid),
+            gid: Some(ucred.gid),
+            pid: Some(ucred.pid as u32),
+        })
+    }
+}
+
+#[derive(Debug)]
+enum State {
+    WaitingForNul,
+    WaitingForAuth,
+    WaitingForData(usize),
+    WaitingForBegin(bool),
+    Done(Authenticated),
+}
+
+/// The server side of the authentication exchange.
+///
+/// The received bytes are given to `feed` until `authenticated` returns the
+/// outcome, and the bytes to send are returned by `take_output`.
+pub struct Server {
+    guid: Guid,
+    mechanisms: Vec<Box<dyn ServerMechanism>>,
+    credentials: Credentials,
+    unix_fd: bool,
+    max_failures: u32,
+    failures: u32,
+    state: State,
+    reader: LineReader,
+    output: Vec<u8>,
+}
+
+impl Server {
+    /// Create a server with no mechanisms, rejecting every client until some
+    /// are added.
+    pub fn new(guid: Guid) -> Server {
+        Server {
+            guid,
+            mechanisms: Vec::new(),
+            credentials: Credentials::default(),
+            unix_fd: false,
+            max_failures: DEFAULT_MAX_FAILURES,
+            failures: 0,
+            state: State::WaitingForNul,
+            reader: LineReader::default(),
+            output: Vec::new(),
+        }
+    }
+
+    pub fn guid(&self) -> &Guid {
+        &self.guid
+    }
+
+    /// Add a mechanism, the ones added first are advertised first.
+    pub fn add_mechanism<M: ServerMechanism + 'static>(&mut self, mechanism: M) {
+        self.mechanisms.push(Box::new(mechanism));
+    }
+
+    pub fn credentials(&mut self, credentials: Credentials) {
+        self.credentials = credentials;
+    }
+
+    /// Agree to pass unix fds if the client asks for it.
+    pub fn unix_fd(&mut self, unix_fd: bool) {
+        self.unix_fd = unix_fd;
+    }
+
+    /// Set the number of failures after which the server gives up. Every
+    /// `REJECTED` and `ERROR` reply counts, including the ones listing the
+    /// mechanisms, so clients can't keep the exchange going forever.
+    pub fn max_failures(&mut self, max_failures: u32) {
+        self.max_failures = max_failures;
+    }
+
+    /// Handle bytes received from the client, returning how many of them
+    /// were used. Bytes following `BEGIN` are left to the caller.
+    pub fn feed(&mut self, data: &[u8]) -> Result<usize, AuthError> {
+        let mut used = 0;
+        if let (State::WaitingForNul, Some(&value)) = (&self.state, data.first()) {
+            if value != 0 {
+                return Err(AuthError::MissingCredentialsByte { value });
+            }
+            self.state = State::WaitingForAuth;
+            used += 1;
+        }
+
+        while !self.is_done() && used < data.len() {
+            let (line, length) = self.reader.next_line(&data[used..])?;
+            used += length;
+
+            match line {
+                Some(line) => self.handle(&line)?,
+                None => break,
+            }
+        }
+
+        Ok(used)
+    }
+
+    /// Return the bytes to send to the client.
+    pub fn take_output(&mut self) -> Vec<u8> {
+        mem::take(&mut self.output)
+    }
+
+    pub fn is_done(&self) -> bool {
+        matches!(self.state, State::Done(_))
+    }
+
+    pub fn authenticated(&self) -> Option<&Authenticated> {
+        match &self.state {
+            State::Done(authenticated) => Some(authenticated),
+            _ => None,
+        }
+    }
+
+    fn send(&mut self, command: Command) {
+        self.output.extend_from_slice(&command.to_line());
+    }
+
+    fn fail(&mut self) -> Result<(), AuthError> {
+        self.failures += 1;
+        if self.failures >= self.max_failures {
+            return Err(AuthError::TooManyFailures {
+                failures: self.failures,
+            });
+        }
+
+        Ok(())
+    }
+
+    fn reject(&mut self) -> Result<(), AuthError> {
+        self.fail()?;
+
+        let names = self
+            .mechanisms
+            .iter()
+            .map(|mechanism| mechanism.name().into())
+            .collect();
+        self.send(Command::Rejected(names));
+        self.state = State::WaitingForAuth;
+        Ok(())
+    }
+
+    fn step(&mut self, index: usize, step: Step) -> Result<(), AuthError> {
+        match step {
+            Step::Accept => {
+                self.send(Command::Ok(self.guid.clone()));
+                self.state = State::WaitingForBegin(false);
+            }
+            Step::Challenge(data) => {
+                self.send(Command::Data(data));
+                self.state = State::WaitingForData(index);
+            }
+            Step::Reject => self.reject()?,
+        }
+
+        Ok(())
+    }
+
+    fn error(&mut self, state: State, message: &str) -> Result<(), AuthError> {
+        self.fail()?;
+        self.send(Command::Error(message.into()));
+        self.state = state;
+        Ok(())
+    }
+
+    fn handle(&mut self, line: &[u8]) -> Result<(), AuthError> {
+        let command = match Command::parse(line) {
+            Ok(command) => command,
+            Err(_) => {
+                let state = mem::replace(&mut self.state, State::WaitingForAuth);
+                return self.error(state, "Unknown command");
+            }
+        };
+        let unexpected = || AuthError::UnexpectedCommand {
+            line: String::from_utf8_lossy(line).into_owned(),
+        };
+
+        match (mem::replace(&mut self.state, State::WaitingForAuth), command) {
+            (State::WaitingForAuth, Command::Auth { mechanism: None, .. }) => self.reject()?,
+            (
+                State::WaitingForAuth,
+                Command::Auth {
+                    mechanism: Some(name),
+                    initial_response,
+                },
+            ) => {
+                let index = self.mechanisms.iter().position(|mechanism| mechanism.name() == name);
+                match index {
+                    Some(index) => {
+                        let step = self.mechanisms[index].start(initial_response.as_deref(), &self.credentials);
+                        self.step(index, step)?;
+                    }
+                    None => self.reject()?,
+                }
+            }
+            (State::WaitingForData(index), Command::Data(data)) => {
+                let step = self.mechanisms[index].data(&data, &self.credentials);
+                self.step(index, step)?;
+            }
+            (State::WaitingForAuth, Command::Error(_))
+            | (State::WaitingForData(_), Command::Cancel)
+            | (State::WaitingForData(_), Command::Error(_))
+            | (State::WaitingForBegin(_), Command::Cancel)
+            | (State::WaitingForBegin(_), Command::Error(_)) => self.reject()?,
+            (State::WaitingForBegin(_), Command::NegotiateUnixFd) if self.unix_fd => {
+                self.send(Command::AgreeUnixFd);
+                self.state = State::WaitingForBegin(true);
+            }
+            (State::WaitingForBegin(unix_fd), Command::NegotiateUnixFd) => {
+                self.error(State::WaitingForBegin(unix_fd), "Unix fd passing isn't supported")?;
+            }
+            (State::WaitingForBegin(unix_fd), Command::Begin) => {
+                self.state = State::Done(Authenticated {
+                    guid: self.guid.clone(),
+                    unix_fd,
+                });
+            }
+            (State::WaitingForAuth, Command::Begin) | (State::WaitingForData(_), Command::Begin) => {
+                return Err(unexpected());
+            }
+            (state, _) => self.error(state, "Unexpected command")?,
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::{AnonymousMechanism, Client, CookieSha1Mechanism, ExternalMechanism, Keyring, Mechanism};
+    use std::{env, fs};
+
+    fn new_server() -> Server {
+        let mut server = Server::new(Guid::generate().unwrap());
+        server.credentials(Credentials {
+            uid: Some(1000),
+            ..Credentials::default()
+        });
+        server
+    }
+
+    fn exchange(server: &mut Server, client: &[u8]) -> Vec<u8> {
+        assert_eq!(server.feed(client).unwrap(), client.len());
+        server.take_output()
+    }
+
+    /// Run the exchange between a client and a server until both are done.
+    fn authenticate(client: &mut Client, server: &mut Server) {
+        client.start().unwrap();
+        while !client.is_done() {
+            let output = client.take_output();
+            server.feed(&output).unwrap();
+            client.feed(&server.take_output()).unwrap();
+        }
+        server.feed(&client.take_output()).unwrap();
+        assert!(server.is_done());
+    }
+
+    #[test]
+    fn test_external() {
+        let mut server = new_server();
+        server.add_mechanism(ExternalMechanism::any_user());
+        server.add_mechanism(AnonymousMechanism);
+
+        assert_eq!(exchange(&mut server, b"\0AUTH\r\n"), b"REJECTED EXTERNAL ANONYMOUS\r\n");
+        assert_eq!(
+            exchange(&mut server, b"AUTH EXTERNAL 30\r\n"),
+            b"REJECTED EXTERNAL ANONYMOUS\r\n"
+        );
+        assert_eq!(exchange(&mut server, b"AUTH EXTERNAL\r\n"), b"DATA\r\n");
+        let ok = format!("OK {}\r\n", server.guid());
+        assert_eq!(exchange(&mut server, b"DATA\r\n"), ok.as_bytes());
+        assert_eq!(
+            exchange(&mut server, b"NEGOTIATE_UNIX_FD\r\n"),
+            b"ERROR Unix fd passing isn't supported\r\n"
+        );
+
+        // Messages following BEGIN are left to the caller.
+        assert_eq!(server.feed(b"BEGIN\r\nl\0\0\0").unwrap(), 7);
+        assert!(!server.authenticated().unwrap().unix_fd);
+    }
+
+    #[test]
+    fn test_failures() {
+        let mut server = new_server();
+        server.add_mechanism(ExternalMechanism::any_user());
+        server.max_failures(2);
+
+        assert!(matches!(
+            server.feed(b"AUTH\r\n"),
+            Err(AuthError::MissingCredentialsByte { value: b'A' })
+        ));
+        assert_eq!(exchange(&mut server, b"\0AUTH KERBEROS_V4\r\n"), b"REJECTED EXTERNAL\r\n");
+        assert!(matches!(
+            server.feed(b"AUTH EXTERNAL 30\r\n"),
+            Err(AuthError::TooManyFailures { failures: 2 })
+        ));
+
+        // Unknown commands, errors and listing the mechanisms count too.
+        for lines in &[&b"HELLO\r\n"[..], b"ERROR\r\n", b"AUTH\r\n"] {
+            let mut server = new_server();
+            server.max_failures(2);
+            assert_eq!(server.feed(b"\0").unwrap(), 1);
+            server.feed(lines).unwrap();
+            assert!(matches!(
+                server.feed(lines),
+                Err(AuthError::TooManyFailures { failures: 2 })
+            ));
+        }
+
+        let mut server = new_server();
+        assert!(matches!(
+            server.feed(b"\0BEGIN\r\n"),
+            Err(AuthError::UnexpectedCommand { .. })
+        ));
+    }
+
+    #[test]
+    fn test_client_server() {
+        let mut server = new_server();
+        server.add_mechanism(ExternalMechanism::any_user());
+        server.unix_fd(true);
+
+        let mut client = Client::new();
+        client.uid(1000);
+        client.negotiate_unix_fd(true);
+        authenticate(&mut client, &mut server);
+        assert_eq!(client.authenticated(), server.authenticated());
+        assert!(client.authenticated().unwrap().unix_fd);
+
+        // The client falls back on the mechanisms the server supports.
+        let mut server = new_server();
+        server.add_mechanism(AnonymousMechanism);
+        let mut client = Client::new();
+        client.uid(1000);
+        authenticate(&mut client, &mut server);
+        assert_eq!(client.mechanism(), Some(Mechanism::Anonymous));
+    }
+
+    #[test]
+    fn test_cookie_sha1() {
+        let dir = env::temp_dir().join(format!("rbus-server-test-{}", std::process::id()));
+        let keyring = Keyring::new(&dir);
+
+        let mut mechanism = CookieSha1Mechanism::new(keyring.clone());
+        mechanism.uid(1000);
+        let mut server = new_server();
+        server.add_mechanism(mechanism);
+
+        let mut client = Client::new();
+        client.uid(1000);
+        client.mechanisms(vec![Mechanism::DbusCookieSha1]);
+        client.keyring(keyring);
+        authenticate(&mut client, &mut server);
+
+        // A wrong digest is rejected.
+        let mut server = new_server();
+        let mut mechanism = CookieSha1Mechanism::new(Keyring::new(&dir));
+        mechanism.uid(1000);
+        server.add_mechanism(mechanism);
+        let output = exchange(&mut server, b"\0AUTH DBUS_COOKIE_SHA1 31303030\r\n");
+        assert!(output.starts_with(b"DATA "));
+        let response = Command::Data(b"abcd 0123".to_vec()).to_line();
+        assert_eq!(exchange(&mut server, &response), b"REJECTED DBUS_COOKIE_SHA1\r\n");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,520 @@
+use super::{
+    check_guid, encode, hello_call, into_reply, no_alternative, reader::MessageReader, reply_serial, socket, Handshake,
+    READ_BUFFER_SIZE,
+};
+use crate::{
+    address::Address,
+    auth::{self, Authenticated, Guid},
+    message::{MessageBuilder, RawMessage, SerialAllocator},
+    types::{DBusType, UnixFd},
+    Error, Result,
+};
+use async_io::Async;
+use futures::{
+    channel::{mpsc, oneshot},
+    ready, Stream,
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    io,
+    os::unix::net::UnixStream,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+/// A message waiting to be written by the driver.
+#[derive(Debug)]
+struct Outgoing {
+    data: Vec<u8>,
+    fds: Vec<UnixFd>,
+    sent: usize,
+    reply: Option<(u32, oneshot::Sender<Result<RawMessage>>)>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    requests: mpsc::UnboundedSender<Outgoing>,
+    /// The serials of the calls whose `ReplyFuture` was dropped.
+    cancels: mpsc::UnboundedSender<u32>,
+    authenticated: Authenticated,
+    serials: SerialAllocator,
+    unique_name: Mutex<Option<String>>,
+}
+
+/// An asynchronous connection, which can be cloned to be used from several
+/// tasks.
+///
+/// The reading and writing is done by the `Driver` returned along the
+/// connection, which must be spawned on an executor. The messages which
+/// aren't replies to calls made on the connection are sent to its
+/// `MessageStream`.
+#[derive(Debug, Clone)]
+pub struct AsyncConnection {
+    shared: Arc<Shared>,
+}
+
+/// The parts of a new asynchronous connection.
+pub type AsyncParts = (AsyncConnection, MessageStream, Driver);
+
+impl AsyncConnection {
+    /// Connect to the bus at `address` and register on it, `spawn` being
+    /// given the driver to run in the background.
+    pub async fn open<F>(address: &Address, spawn: F) -> Result<(AsyncConnection, MessageStream)>
+    where
+        F: FnOnce(Driver),
+    {
+        let (connection, messages, driver) = AsyncConnection::connect(address).await?;
+        spawn(driver);
+        connection.hello().await?;
+        Ok((connection, messages))
+    }
+
+    /// Connect to the session bus, see `open`.
+    pub async fn session<F>(spawn: F) -> Result<(AsyncConnection, MessageStream)>
+    where
+        F: FnOnce(Driver),
+    {
+        AsyncConnection::open(&Address::session()?, spawn).await
+    }
+
+    /// Connect to the system bus, see `open`.
+    pub async fn system<F>(spawn: F) -> Result<(AsyncConnection, MessageStream)>
+    where
+        F: FnOnce(Driver),
+    {
+        AsyncConnection::open(&Address::system()?, spawn).await
+    }
+
+    /// Connect to the first alternative of `address` which can be reached
+    /// and authenticate, without registering on a bus.
+    pub async fn connect(address: &Address) -> Result<AsyncParts> {
+        let mut last_error = None;
+        for server in address.alternatives() {
+            let parts = match socket::connect(server) {
+                Ok(socket) => AsyncConnection::client(socket).await,
+                Err(e) => Err(e),
+            };
+            match parts.and_then(|parts| check_guid(server, parts.0.guid()).map(|_| parts)) {
+                Ok(parts) => return Ok(parts),
+                Err(e) => last_error = Some(e),
+            }
+        }
+
+        Err(no_alternative(address, last_error))
+    }
+
+    /// Authenticate as a client on a connected socket.
+    pub async fn client(socket: UnixStream) -> Result<AsyncParts> {
+        let mut client = auth::Client::new();
+        client.negotiate_unix_fd(true);
+        client.start()?;
+        AsyncConnection::authenticate(socket, client).await
+    }
+
+    /// Authenticate the client connected to `socket` with `server`, which
+    /// gets the credentials of the socket.
+    pub async fn server(socket: UnixStream, mut server: auth::Server) -> Result<AsyncParts> {
+        #[cfg(target_os = "linux")]
+        server.credentials(auth::Credentials::from_socket(&socket)?);
+        server.unix_fd(true);
+        AsyncConnection::authenticate(socket, server).await
+    }
+
+    async fn authenticate<H: Handshake>(socket: UnixStream, mut handshake: H) -> Result<AsyncParts> {
+        let socket = Async::new(socket)?;
+        let mut reader = MessageReader::default();
+        let mut buffer = vec![0; READ_BUFFER_SIZE];
+
+        let authenticated = loop {
+            let output = handshake.take_output();
+            let mut sent = 0;
+            while sent < output.len() {
+                sent += socket
+                    .write_with(|socket| socket::send_some(socket, &output[sent..], &[]))
+                    .await?;
+            }
+            if let Some(authenticated) = handshake.authenticated() {
+                break authenticated.clone();
+            }
+
+            let (length, fds) = socket.read_with(|socket| socket::receive(socket, &mut buffer)).await?;
+            if length == 0 {
+                return Err(Error::ConnectionClosed);
+            }
+
+            // Bytes following the exchange are the first messages.
+            let used = handshake.feed(&buffer[..length])?;
+            reader.feed(&buffer[used..length], fds);
+        };
+
+        let (requests, requests_receiver) = mpsc::unbounded();
+        let (cancels, cancels_receiver) = mpsc::unbounded();
+        let (messages_sender, messages) = mpsc::unbounded();
+        let connection = AsyncConnection {
+            shared: Arc::new(Shared {
+                requests,
+                cancels,
+                authenticated,
+                serials: SerialAllocator::new(),
+                unique_name: Mutex::new(None),
+            }),
+        };
+        let driver = Driver {
+            socket,
+            reader,
+            buffer,
+            requests: requests_receiver,
+            requests_closed: false,
+            cancels: cancels_receiver,
+            outgoing: VecDeque::new(),
+            replies: HashMap::new(),
+            messages: messages_sender,
+        };
+
+        Ok((connection, MessageStream { receiver: messages }, driver))
+    }
+
+    /// Register on the bus, returning the unique name it assigned to this
+    /// connection.
+    pub async fn hello(&self) -> Result<String> {
+        let name = self.call(hello_call()?)?.await?.body::<String>()?;
+
+        *self.shared.unique_name.lock().unwrap() = Some(name.clone());
+        Ok(name)
+    }
+
+    /// The unique name assigned by the bus, once registered.
+    pub fn unique_name(&self) -> Option<String> {
+        self.shared.unique_name.lock().unwrap().clone()
+    }
+
+    /// The GUID of the server.
+    pub fn guid(&self) -> &Guid {
+        &self.shared.authenticated.guid
+    }
+
+    /// Whether unix fds can be sent along messages.
+    pub fn can_pass_unix_fds(&self) -> bool {
+        self.shared.authenticated.unix_fd
+    }
+
+    /// The allocator of the serials of the messages sent.
+    pub fn serials(&self) -> &SerialAllocator {
+        &self.shared.serials
+    }
+
+    /// Build and queue a message, returning its serial.
+    pub fn send<T: DBusType>(&self, message: MessageBuilder<T>) -> Result<u32> {
+        let message = RawMessage::from_message(&message.build(&self.shared.serials)?)?;
+        self.queue(&message, None)?;
+        Ok(message.header().serial)
+    }
+
+    /// Queue an already built message, sent as is.
+    pub fn send_raw(&self, message: &RawMessage) -> Result<()> {
+        self.queue(message, None)
+    }
+
+    /// Queue a method call, returning a future resolving to its reply. Error
+    /// replies are returned as `Error::Method`.
+    pub fn call<T: DBusType>(&self, message: MessageBuilder<T>) -> Result<ReplyFuture> {
+        let message = RawMessage::from_message(&message.build(&self.shared.serials)?)?;
+        let serial = message.header().serial;
+        let (sender, receiver) = oneshot::channel();
+        self.queue(&message, Some((serial, sender)))?;
+        Ok(ReplyFuture {
+            receiver,
+            serial,
+            cancel: Some(self.shared.cancels.clone()),
+        })
+    }
+
+    fn queue(&self, message: &RawMessage, reply: Option<(u32, oneshot::Sender<Result<RawMessage>>)>) -> Result<()> {
+        let (data, fds) = encode(message, self.can_pass_unix_fds())?;
+        let outgoing = Outgoing {
+            data,
+            fds,
+            sent: 0,
+            reply,
+        };
+
+        self.shared
+            .requests
+            .unbounded_send(outgoing)
+            .map_err(|_| Error::ConnectionClosed)
+    }
+}
+
+/// The reply to a method call, resolved by the driver.
+///
+/// Dropping it before the reply is received tells the driver to stop
+/// waiting for the reply.
+#[derive(Debug)]
+pub struct ReplyFuture {
+    receiver: oneshot::Receiver<Result<RawMessage>>,
+    serial: u32,
+    /// Taken once the reply is received.
+    cancel: Option<mpsc::UnboundedSender<u32>>,
+}
+
+impl Future for ReplyFuture {
+    type Output = Result<RawMessage>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<RawMessage>> {
+        let reply = ready!(Pin::new(&mut self.receiver).poll(cx));
+        self.cancel = None;
+
+        match reply {
+            Ok(reply) => Poll::Ready(reply),
+            // The driver stopped before the reply was received.
+            Err(_) => Poll::Ready(Err(Error::ConnectionClosed)),
+        }
+    }
+}
+
+impl Drop for ReplyFuture {
+    fn drop(&mut self) {
+        if let Some(cancel) = self.cancel.take() {
+            // The driver may already be gone.
+            let _ = cancel.unbounded_send(self.serial);
+        }
+    }
+}
+
+/// The messages received which aren't replies to calls made on the
+/// connection: method calls, signals and unexpected replies.
+#[derive(Debug)]
+pub struct MessageStream {
+    receiver: mpsc::UnboundedReceiver<RawMessage>,
+}
+
+impl Stream for MessageStream {
+    type Item = RawMessage;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<RawMessage>> {
+        Pin::new(&mut self.receiver).poll_next(cx)
+    }
+}
+
+/// The future reading and writing the messages of an `AsyncConnection`.
+///
+/// It completes once every handle of the connection is dropped and the
+/// queued messages are written, or fails when the connection is lost.
+#[must_use = "the connection doesn't make progress unless its driver is polled"]
+#[derive(Debug)]
+pub struct Driver {
+    socket: Async<UnixStream>,
+    reader: MessageReader,
+    buffer: Vec<u8>,
+    requests: mpsc::UnboundedReceiver<Outgoing>,
+    requests_closed: bool,
+    cancels: mpsc::UnboundedReceiver<u32>,
+    outgoing: VecDeque<Outgoing>,
+    replies: HashMap<u32, oneshot::Sender<Result<RawMessage>>>,
+    messages: mpsc::UnboundedSender<RawMessage>,
+}
+
+impl Driver {
+    fn poll_io(&mut self, cx: &mut Context) -> Poll<Result<()>> {
+        loop {
+            self.dispatch()?;
+            self.poll_requests(cx);
+            let written = self.poll_write(cx)?;
+
+            if written.is_ready() && self.requests_closed {
+                return Poll::Ready(Ok(()));
+            }
+
+            ready!(self.poll_read(cx))?;
+        }
+    }
+
+    /// Queue the messages sent by the connection handles, and forget the
+    /// calls whose reply isn't awaited anymore.
+    fn poll_requests(&mut self, cx: &mut Context) {
+        while !self.requests_closed {
+            match Pin::new(&mut self.requests).poll_next(cx) {
+                Poll::Ready(Some(mut outgoing)) => {
+                    match outgoing.reply.take() {
+                        // The call may be cancelled before its request is
+                        // received.
+                        Some((serial, sender)) if !sender.is_canceled() => {
+                            self.replies.insert(serial, sender);
+                        }
+                        _ => {}
+                    }
+                    self.outgoing.push_back(outgoing);
+                }
+                Poll::Ready(None) => self.requests_closed = true,
+                Poll::Pending => break,
+            }
+        }
+
+        while let Poll::Ready(Some(serial)) = Pin::new(&mut self.cancels).poll_next(cx) {
+            self.replies.remove(&serial);
+        }
+    }
+
+    /// Write the queued messages, until the socket would block.
+    fn poll_write(&mut self, cx: &mut Context) -> Poll<Result<()>> {
+        while let Some(outgoing) = self.outgoing.front_mut() {
+            ready!(self.socket.poll_writable(cx))?;
+
+            let fds: &[UnixFd] = if outgoing.sent == 0 { &outgoing.fds } else { &[] };
+            match socket::send_some(self.socket.get_ref(), &outgoing.data[outgoing.sent..], fds) {
+                Ok(length) => {
+                    outgoing.sent += length;
+                    if outgoing.sent == outgoing.data.len() {
+                        self.outgoing.pop_front();
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) => return Poll::Ready(Err(e.into())),
+            }
+        }
+
+        Poll::Ready(Ok(()))
+    }
+
+    /// Read the bytes available on the socket.
+    fn poll_read(&mut self, cx: &mut Context) -> Poll<Result<()>> {
+        ready!(self.socket.poll_readable(cx))?;
+
+        match socket::receive(self.socket.get_ref(), &mut self.buffer) {
+            Ok((0, _)) => Poll::Ready(Err(Error::ConnectionClosed)),
+            Ok((length, fds)) => {
+                self.reader.feed(&self.buffer[..length], fds);
+                Poll::Ready(Ok(()))
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Poll::Ready(Ok(())),
+            Err(e) => Poll::Ready(Err(e.into())),
+        }
+    }
+
+    /// Hand the received messages to the calls waiting for them, or to the
+    /// message stream.
+    fn dispatch(&mut self) -> Result<()> {
+        loop {
+            let message = match self.reader.next_message() {
+                Ok(Some(message)) => message,
+                Ok(None) => break,
+                Err(Error::InvalidReply { reply_serial, source }) => {
+                    if let Some(sender) = self.replies.remove(&reply_serial) {
+                        let _ = sender.send(Err(Error::InvalidReply { reply_serial, source }));
+                    }
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+
+            match reply_serial(message.header()).and_then(|serial| self.replies.remove(&serial)) {
+                Some(sender) => {
+                    let _ = sender.send(into_reply(message));
+                }
+                None => {
+                    // The stream may have been dropped by a connection only
+                    // making calls.
+                    let _ = self.messages.unbounded_send(message);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Future for Driver {
+    type Output = Result<()>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<()>> {
+        let driver = self.get_mut();
+        let result = ready!(driver.poll_io(cx));
+
+        // The pending calls won't be replied to anymore.
+        driver.replies.clear();
+        Poll::Ready(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::tests::{authenticate_peer, member, serve, test_call};
+    use futures::{
+        executor::block_on,
+        future::{self, Either},
+        StreamExt,
+    };
+    use std::thread;
+
+    #[test]
+    fn test_async_call() {
+        let (socket, peer) = UnixStream::pair().unwrap();
+        let peer = thread::spawn(move || serve(authenticate_peer(peer)));
+
+        block_on(async {
+            let (connection, mut messages, driver) = AsyncConnection::client(socket).await.unwrap();
+            let driver = thread::spawn(move || block_on(driver));
+
+            assert!(connection.can_pass_unix_fds());
+            assert_eq!(connection.hello().await.unwrap(), ":1.42");
+            assert_eq!(connection.unique_name().as_deref(), Some(":1.42"));
+
+            let reply = connection.call(test_call("Echo", "rbus")).unwrap().await.unwrap();
+            assert_eq!(reply.body::<String>().unwrap(), "rbus");
+            assert_eq!(member(&messages.next().await.unwrap()), "Echoing");
+
+            // Replies are matched with their call whatever order they are
+            // waited for in.
+            let first = connection.call(test_call("Echo", "first")).unwrap();
+            let second = connection.call(test_call("Echo", "second")).unwrap();
+            assert_eq!(second.await.unwrap().body::<String>().unwrap(), "second");
+            assert_eq!(first.await.unwrap().body::<String>().unwrap(), "first");
+
+            match connection.call(test_call("Fail", "")).unwrap().await {
+                Err(Error::Method { source }) => assert_eq!(source.message(), "Failed"),
+                result => panic!("Unexpected result: {:?}", result),
+            }
+            assert_err_matches!(
+                connection.call(test_call("Invalid", "")).unwrap().await,
+                Error::InvalidReply { .. }
+            );
+
+            drop(connection);
+            assert!(driver.join().unwrap().is_ok());
+        });
+
+        peer.join().unwrap();
+    }
+
+    #[test]
+    fn test_dropped_reply_future() {
+        let (socket, peer) = UnixStream::pair().unwrap();
+        let peer = thread::spawn(move || serve(authenticate_peer(peer)));
+
+        block_on(async {
+            let (connection, _messages, mut driver) = AsyncConnection::client(socket).await.unwrap();
+
+            // Dropped once the driver waits for the reply, and before.
+            let first = connection.call(test_call("Echo", "first")).unwrap();
+            assert!(futures::poll!(&mut driver).is_pending());
+            assert_eq!(driver.replies.len(), 1);
+            drop(first);
+            drop(connection.call(test_call("Echo", "second")).unwrap());
+
+            let third = connection.call(test_call("Echo", "third")).unwrap();
+            match future::select(&mut driver, third).await {
+                Either::Right((reply, _)) => assert_eq!(reply.unwrap().body::<String>().unwrap(), "third"),
+                Either::Left((result, _)) => panic!("Unexpected driver result: {:?}", result),
+            }
+            assert!(driver.replies.is_empty());
+
+            drop(connection);
+            assert!(driver.await.is_ok());
+        });
+
+        peer.join().unwrap();
+    }
+}
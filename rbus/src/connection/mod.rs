@@ -0,0 +1,461 @@
+//! Connections to a message bus or a peer, over unix sockets.
+
+use crate::{
+    address::{Address, ServerAddress},
+    auth::{self, AuthError, Authenticated, Guid},
+    marshal::Marshaller,
+    message::{
+        types::{BusName, Interface, Member},
+        Message, MessageBuilder, MessageHeader, MessageType, MethodError, RawMessage, SerialAllocator,
+    },
+    types::{DBusType, ObjectPath, UnixFd},
+    Error, Result,
+};
+use reader::MessageReader;
+use std::{collections::VecDeque, os::unix::net::UnixStream};
+
+#[cfg(feature = "async")]
+mod asynchronous;
+mod reader;
+mod socket;
+
+#[cfg(feature = "async")]
+pub use asynchronous::*;
+
+/// The well-known name of the message bus.
+pub const BUS_NAME: &str = "org.freedesktop.DBus";
+/// The object path of the message bus.
+pub const BUS_PATH: &str = "/org/freedesktop/DBus";
+/// The interface of the message bus methods.
+pub const BUS_INTERFACE: &str = "org.freedesktop.DBus";
+
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// A side of the authentication exchange.
+trait Handshake {
+    fn feed(&mut self, data: &[u8]) -> std::result::Result<usize, AuthError>;
+    fn take_output(&mut self) -> Vec<u8>;
+    fn authenticated(&self) -> Option<&Authenticated>;
+}
+
+impl Handshake for auth::Client {
+    fn feed(&mut self, data: &[u8]) -> std::result::Result<usize, AuthError> {
+        auth::Client::feed(self, data)
+    }
+
+    fn take_output(&mut self) -> Vec<u8> {
+        auth::Client::take_output(self)
+    }
+
+    fn authenticated(&self) -> Option<&Authenticated> {
+        auth::Client::authenticated(self)
+    }
+}
+
+impl Handshake for auth::Server {
+    fn feed(&mut self, data: &[u8]) -> std::result::Result<usize, AuthError> {
+        auth::Server::feed(self, data)
+    }
+
+    fn take_output(&mut self) -> Vec<u8> {
+        auth::Server::take_output(self)
+    }
+
+    fn authenticated(&self) -> Option<&Authenticated> {
+        auth::Server::authenticated(self)
+    }
+}
+
+/// A blocking connection.
+///
+/// Messages received while waiting for a method reply are queued and
+/// returned by the next calls to `receive`.
+#[derive(Debug)]
+pub struct Connection {
+    socket: UnixStream,
+    authenticated: Authenticated,
+    serials: SerialAllocator,
+    unique_name: Option<String>,
+    reader: MessageReader,
+    queue: VecDeque<RawMessage>,
+    buffer: Vec<u8>,
+}
+
+impl Connection {
+    /// Connect to the bus at `address`, and register on it.
+    pub fn open(address: &Address) -> Result<Connection> {
+        let mut connection = Connection::connect(address)?;
+        connection.hello()?;
+        Ok(connection)
+    }
+
+    /// Connect to the session bus.
+    pub fn session() -> Result<Connection> {
+        Connection::open(&Address::session()?)
+    }
+
+    /// Connect to the system bus.
+    pub fn system() -> Result<Connection> {
+        Connection::open(&Address::system()?)
+    }
+
+    /// Connect to the first alternative of `address` which can be reached
+    /// and authenticate, without registering on a bus.
+    pub fn connect(address: &Address) -> Result<Connection> {
+        let mut last_error = None;
+        for server in address.alternatives() {
+            let connection = socket::connect(server).and_then(Connection::client);
+            match connection.and_then(|connection| check_guid(server, connection.guid()).map(|_| connection)) {
+                Ok(connection) => return Ok(connection),
+                Err(e) => last_error = Some(e),
+            }
+        }
+
+        Err(no_alternative(address, last_error))
+    }
+
+    /// Authenticate as a client on a connected socket.
+    pub fn client(socket: UnixStream) -> Result<Connection> {
+        let mut client = auth::Client::new();
+        client.negotiate_unix_fd(true);
+        client.start()?;
+        Connection::authenticate(socket, client)
+    }
+
+    /// Authenticate the client connected to `socket` with `server`, which
+    /// gets the credentials of the socket.
+    pub fn server(socket: UnixStream, mut server: auth::Server) -> Result<Connection> {
+        #[cfg(target_os = "linux")]
+        server.credentials(auth::Credentials::from_socket(&socket)?);
+        server.unix_fd(true);
+        Connection::authenticate(socket, server)
+    }
+
+    fn authenticate<H: Handshake>(socket: UnixStream, mut handshake: H) -> Result<Connection> {
+        let mut reader = MessageReader::default();
+        let mut buffer = vec![0; READ_BUFFER_SIZE];
+
+        let authenticated = loop {
+            socket::send(&socket, &handshake.take_output(), &[])?;
+            if let Some(authenticated) = handshake.authenticated() {
+                break authenticated.clone();
+            }
+
+            let (length, fds) = socket::receive(&socket, &mut buffer)?;
+            if length == 0 {
+                return Err(Error::ConnectionClosed);
+            }
+
+            // Bytes following the exchange are the first messages.
+            let used = handshake.feed(&buffer[..length])?;
+            reader.feed(&buffer[used..length], fds);
+        };
+
+        Ok(Connection {
+            socket,
+            authenticated,
+            serials: SerialAllocator::new(),
+            unique_name: None,
+            reader,
+            queue: VecDeque::new(),
+            buffer,
+        })
+    }
+
+    /// Register on the bus, returning the unique name it assigned to this
+    /// connection.
+    pub fn hello(&mut self) -> Result<String> {
+        let name = self.call(hello_call()?)?.body::<String>()?;
+
+        self.unique_name = Some(name.clone());
+        Ok(name)
+    }
+
+    /// The unique name assigned by the bus, once registered.
+    pub fn unique_name(&self) -> Option<&str> {
+        self.unique_name.as_deref()
+    }
+
+    /// The GUID of the server.
+    pub fn guid(&self) -> &Guid {
+        &self.authenticated.guid
+    }
+
+    /// Whether unix fds can be sent along messages.
+    pub fn can_pass_unix_fds(&self) -> bool {
+        self.authenticated.unix_fd
+    }
+
+    /// The allocator of the serials of the messages sent.
+    pub fn serials(&self) -> &SerialAllocator {
+        &self.serials
+    }
+
+    pub fn socket(&self) -> &UnixStream {
+        &self.socket
+    }
+
+    /// Build and send a message, returning its serial.
+    pub fn send<T: DBusType>(&mut self, message: MessageBuilder<T>) -> Result<u32> {
+        let message = RawMessage::from_message(&message.build(&self.serials)?)?;
+        self.send_raw(&message)?;
+        Ok(message.header().serial)
+    }
+
+    /// Send an already built message, as is.
+    pub fn send_raw(&mut self, message: &RawMessage) -> Result<()> {
+        let (data, fds) = encode(message, self.can_pass_unix_fds())?;
+        socket::send(&self.socket, &data, &fds)?;
+        Ok(())
+    }
+
+    /// Return the next message, blocking until one is received.
+    ///
+    /// Replies which can't be decoded are returned as `Error::InvalidReply`,
+    /// the connection can still be used afterwards.
+    pub fn receive(&mut self) -> Result<RawMessage> {
+        match self.queue.pop_front() {
+            Some(message) => Ok(message),
+            None => self.read_message(),
+        }
+    }
+
+    /// Send a method call and block until its reply is received, error
+    /// replies being returned as `Error::Method`.
+    ///
+    /// Invalid replies to other calls received meanwhile are dropped.
+    pub fn call<T: DBusType>(&mut self, message: MessageBuilder<T>) -> Result<RawMessage> {
+        let serial = self.send(message)?;
+
+        loop {
+            let message = match self.read_message() {
+                Ok(message) => message,
+                Err(Error::InvalidReply { reply_serial, .. }) if reply_serial != serial => continue,
+                Err(e) => return Err(e),
+            };
+            if reply_serial(message.header()) == Some(serial) {
+                return into_reply(message);
+            }
+            self.queue.push_back(message);
+        }
+    }
+
+    fn read_message(&mut self) -> Result<RawMessage> {
+        loop {
+            if let Some(message) = self.reader.next_message()? {
+                return Ok(message);
+            }
+
+            let (length, fds) = socket::receive(&self.socket, &mut self.buffer)?;
+            if length == 0 {
+                return Err(Error::ConnectionClosed);
+            }
+            self.reader.feed(&self.buffer[..length], fds);
+        }
+    }
+}
+
+/// Check the GUID of a server is the one expected by its address.
+fn check_guid(server: &ServerAddress, guid: &Guid) -> Result<()> {
+    match &server.guid {
+        Some(expected) if expected.as_str() != guid.as_str() => Err(Error::GuidMismatch {
+            expected: expected.clone(),
+            found: guid.to_string(),
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Return the error to report when no alternative of `address` could be
+/// connected to.
+fn no_alternative(address: &Address, last_error: Option<Error>) -> Error {
+    last_error.unwrap_or_else(|| Error::UnsupportedTransport {
+        address: address.to_string(),
+    })
+}
+
+fn hello_call() -> Result<MessageBuilder<()>> {
+    Ok(Message::method_call(
+        Some(BusName::new(BUS_NAME)?),
+        ObjectPath::new(BUS_PATH)?,
+        Some(Interface::new(BUS_INTERFACE)?),
+        Member::new("Hello")?,
+        (),
+    ))
+}
+
+/// Encode a message to send, along with its fds.
+fn encode(message: &RawMessage, unix_fd: bool) -> Result<(Vec<u8>, Vec<UnixFd>)> {
+    if !message.fds().is_empty() && !unix_fd {
+        return Err(Error::UnixFdNotNegotiated);
+    }
+
+    let mut marshaller = Marshaller::new(Vec::new(), message.endianness());
+    message.encode(&mut marshaller)?;
+    let fds = marshaller.take_fds();
+    Ok((marshaller.into_inner(), fds))
+}
+
+/// Return the serial of the call a message replies to, if it is a reply.
+fn reply_serial(header: &MessageHeader) -> Option<u32> {
+    match header.ty {
+        MessageType::MethodReturn | MessageType::Error => header.reply_serial(),
+        _ => None,
+    }
+}
+
+/// Turn error replies into `Error::Method`.
+fn into_reply(message: RawMessage) -> Result<RawMessage> {
+    match message.header().ty {
+        MessageType::Error => Err(MethodError::from_message(&message)?.into()),
+        _ => Ok(message),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        auth::{ExternalMechanism, Server},
+        marshal::Endianness,
+        message::{types::ErrorName, Flags, HeaderField},
+    };
+    use std::{
+        fs::File,
+        io::{Read, Write},
+        os::unix::io::{AsRawFd, FromRawFd},
+        thread,
+    };
+
+    pub(super) fn authenticate_peer(socket: UnixStream) -> Connection {
+        let mut auth = Server::new(Guid::generate().unwrap());
+        auth.add_mechanism(ExternalMechanism::new());
+        Connection::server(socket, auth).unwrap()
+    }
+
+    fn pair() -> (UnixStream, thread::JoinHandle<Connection>) {
+        let (client, server) = UnixStream::pair().unwrap();
+        (client, thread::spawn(move || authenticate_peer(server)))
+    }
+
+    /// Reply to the calls of the test client until it disconnects.
+    pub(super) fn serve(mut server: Connection) {
+        loop {
+            let call = match server.receive() {
+                Ok(call) => call,
+                Err(_) => break,
+            };
+
+            if member(&call) == "Invalid" {
+                server.send_raw(&invalid_reply(&call, server.serials())).unwrap();
+                continue;
+            }
+
+            match member(&call) {
+                "Hello" => server.send(Message::method_return(&call, String::from(":1.42"))),
+                "Echo" => {
+                    let signal = Message::signal(
+                        ObjectPath::new("/org/rbus/Test").unwrap(),
+                        Interface::new("org.rbus.Test").unwrap(),
+                        Member::new("Echoing").unwrap(),
+                        (),
+                    );
+                    server.send(signal).unwrap();
+                    server.send(Message::method_return(&call, call.body::<String>().unwrap()))
+                }
+                _ => server.send(Message::error(
+                    &call,
+                    ErrorName::new("org.rbus.Error.Failed").unwrap(),
+                    "Failed",
+                )),
+            }
+            .unwrap();
+        }
+    }
+
+    /// An error reply without an error name.
+    fn invalid_reply(call: &RawMessage, serials: &SerialAllocator) -> RawMessage {
+        let header = MessageHeader {
+            endianness: Endianness::native(),
+            ty: MessageType::Error,
+            flags: Flags::empty(),
+            version: 1,
+            body_size: 0,
+            serial: serials.next().get(),
+            fields: vec![HeaderField::ReplySerial(call.header().serial)],
+        };
+        RawMessage::new(header, Vec::new(), Vec::new())
+    }
+
+    pub(super) fn member(message: &RawMessage) -> &str {
+        message.header().member().map(AsRef::as_ref).unwrap_or_default()
+    }
+
+    pub(super) fn test_call(member: &str, data: &str) -> MessageBuilder<String> {
+        Message::method_call(
+            None,
+            ObjectPath::new("/org/rbus/Test").unwrap(),
+            None,
+            Member::new(member).unwrap(),
+            data.into(),
+        )
+    }
+
+    #[test]
+    fn test_call_reply() {
+        let (socket, server) = pair();
+        let server = thread::spawn(move || serve(server.join().unwrap()));
+
+        let mut connection = Connection::client(socket).unwrap();
+        assert!(connection.can_pass_unix_fds());
+        assert_eq!(connection.hello().unwrap(), ":1.42");
+        assert_eq!(connection.unique_name(), Some(":1.42"));
+
+        let reply = connection.call(test_call("Echo", "rbus")).unwrap();
+        assert_eq!(reply.body::<String>().unwrap(), "rbus");
+        // The signal sent before the reply was queued.
+        assert_eq!(member(&connection.receive().unwrap()), "Echoing");
+
+        match connection.call(test_call("Fail", "")) {
+            Err(Error::Method { source }) => {
+                assert_eq!(AsRef::<str>::as_ref(source.name()), "org.rbus.Error.Failed");
+                assert_eq!(source.message(), "Failed");
+            }
+            result => panic!("Unexpected result: {:?}", result),
+        }
+
+        // The call fails instead of waiting for another reply.
+        assert_err_matches!(connection.call(test_call("Invalid", "")), Error::InvalidReply { .. });
+        assert_eq!(connection.call(test_call("Echo", "again")).unwrap().body::<String>().unwrap(), "again");
+
+        drop(connection);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_unix_fds() {
+        let (socket, server) = pair();
+        let mut connection = Connection::client(socket).unwrap();
+        let mut server = server.join().unwrap();
+
+        let (mut local, remote) = UnixStream::pair().unwrap();
+        let call = Message::method_call(
+            None,
+            ObjectPath::new("/org/rbus/Test").unwrap(),
+            None,
+            Member::new("Fd").unwrap(),
+            UnixFd::new(remote),
+        );
+        connection.send(call).unwrap();
+
+        let message = server.receive().unwrap();
+        assert_eq!(message.fds().len(), 1);
+        let fd = message.body::<UnixFd>().unwrap();
+        let mut file = unsafe { File::from_raw_fd(libc::dup(fd.as_raw_fd())) };
+        file.write_all(b"rbus").unwrap();
+
+        let mut data = [0; 4];
+        local.read_exact(&mut data).unwrap();
+        assert_eq!(&data, b"rbus");
+    }
+}
@@ -0,0 +1,104 @@
+use super::reply_serial;
+use crate::{
+    marshal::{Endianness, Marshaller},
+    message::{Frame, FrameReader, MessageHeader, RawMessage},
+    types::{DBusType, UnixFd},
+    Error, Result,
+};
+use std::collections::VecDeque;
+
+/// Assemble the messages received on a connection from the bytes and fds
+/// read from its socket.
+#[derive(Debug, Default)]
+pub(crate) struct MessageReader {
+    frames: FrameReader,
+    fds: VecDeque<UnixFd>,
+}
+
+impl MessageReader {
+    pub fn feed(&mut self, data: &[u8], fds: Vec<UnixFd>) {
+        self.frames.feed(data);
+        self.fds.extend(fds);
+    }
+
+    /// Return the next complete message, along with the fds it refers to.
+    ///
+    /// Messages whose header can be decoded but which are otherwise invalid
+    /// are skipped, their fds being closed. Invalid replies are returned as
+    /// `Error::InvalidReply` instead, so that the call waiting for them fails,
+    /// the reader can still be used afterwards. The other errors are fatal:
+    /// the fds received afterwards can't be matched with their messages
+    /// anymore.
+    pub fn next_message(&mut self) -> Result<Option<RawMessage>> {
+        loop {
+            let data = match self.frames.next_frame()? {
+                Frame::Complete(data) => data,
+                Frame::Incomplete { .. } => return Ok(None),
+            };
+
+            let mut marshaller = Marshaller::from_slice(&data, Endianness::native());
+            let header = MessageHeader::decode(&mut marshaller)?;
+
+            // The fds are taken before the rest of the message is checked, so
+            // that they don't end up attached to the next message.
+            let count = header.unix_fds().unwrap_or(0) as usize;
+            if count > self.fds.len() {
+                return Err(Error::InvalidUnixFd {
+                    index: count as u32 - 1,
+                    count: self.fds.len(),
+                });
+            }
+            marshaller.set_fds(self.fds.drain(..count).collect());
+
+            let reply_serial = reply_serial(&header);
+            match RawMessage::decode_after_header(header, &mut marshaller) {
+                Ok(message) => return Ok(Some(message)),
+                Err(error) => {
+                    if let Some(reply_serial) = reply_serial {
+                        return Err(Error::InvalidReply {
+                            reply_serial,
+                            source: Box::new(error),
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{Flags, HeaderField, MessageType};
+    use std::fs::File;
+
+    fn reply(fields: Vec<HeaderField>) -> Vec<u8> {
+        let header = MessageHeader {
+            endianness: Endianness::native(),
+            ty: MessageType::MethodReturn,
+            flags: Flags::empty(),
+            version: 1,
+            body_size: 0,
+            serial: 1,
+            fields,
+        };
+        RawMessage::new(header, Vec::new(), Vec::new()).to_bytes().unwrap()
+    }
+
+    #[test]
+    fn test_skip_invalid_message() {
+        let invalid = reply(vec![HeaderField::UnixFds(1)]);
+        let valid = reply(vec![HeaderField::ReplySerial(1), HeaderField::UnixFds(1)]);
+        let first = UnixFd::new(File::open("/dev/null").unwrap());
+        let second = UnixFd::new(File::open("/dev/null").unwrap());
+
+        let mut reader = MessageReader::default();
+        reader.feed(&invalid, vec![first]);
+        reader.feed(&valid, vec![second.clone()]);
+        let message = reader.next_message().unwrap().unwrap();
+        assert_eq!(message.header().reply_serial(), Some(1));
+        assert_eq!(message.fds(), &[second][..]);
+        assert!(reader.next_message().unwrap().is_none());
+        assert!(reader.fds.is_empty());
+    }
+}
@@ -0,0 +1,175 @@
+use crate::{
+    address::{ServerAddress, Transport, UnixTransport},
+    types::UnixFd,
+    Error, Result,
+};
+use std::{
+    io, mem,
+    os::unix::{
+        io::{AsRawFd, FromRawFd, RawFd},
+        net::UnixStream,
+    },
+    ptr,
+};
+
+/// The maximum number of fds the kernel passes along a single `sendmsg`.
+const MAX_FDS: usize = 253;
+
+/// Connect to the unix socket of `address`.
+pub(crate) fn connect(address: &ServerAddress) -> Result<UnixStream> {
+    match &address.transport {
+        Transport::Unix(UnixTransport::Path(path)) => Ok(UnixStream::connect(path)?),
+        Transport::Unix(UnixTransport::Abstract(name)) => Ok(connect_abstract(name)?),
+        _ => Err(Error::UnsupportedTransport {
+            address: address.to_string(),
+        }),
+    }
+}
+
+/// Connect to a socket of the Linux abstract namespace.
+fn connect_abstract(name: &[u8]) -> io::Result<UnixStream> {
+    let mut address: libc::sockaddr_un = unsafe { mem::zeroed() };
+    address.sun_family = libc::AF_UNIX as libc::sa_family_t;
+    // The name follows the NUL byte starting the path.
+    if name.len() >= address.sun_path.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Abstract socket name too long",
+        ));
+    }
+    for (dest, &byte) in address.sun_path[1..].iter_mut().zip(name) {
+        *dest = byte as libc::c_char;
+    }
+    let length = mem::size_of::<libc::sa_family_t>() + 1 + name.len();
+
+    let fd = unsafe { libc::socket(libc::AF_UNIX, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let stream = unsafe { UnixStream::from_raw_fd(fd) };
+
+    let result = unsafe {
+        libc::connect(
+            fd,
+            &address as *const libc::sockaddr_un as *const libc::sockaddr,
+            length as libc::socklen_t,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(stream)
+}
+
+/// Return a buffer suitably aligned for a control message holding `count`
+/// fds, along with the length of that message.
+fn control_buffer(count: usize) -> (Vec<u64>, usize) {
+    let space = unsafe { libc::CMSG_SPACE((count * mem::size_of::<RawFd>()) as u32) } as usize;
+    (vec![0; space / mem::size_of::<u64>() + 1], space)
+}
+
+/// Send `data` along with `fds`, which are sent with the first bytes.
+pub(crate) fn send(socket: &UnixStream, data: &[u8], fds: &[UnixFd]) -> io::Result<()> {
+    let mut sent = send_some(socket, data, fds)?;
+    while sent < data.len() {
+        sent += send_some(socket, &data[sent..], &[])?;
+    }
+
+    Ok(())
+}
+
+/// Send the first bytes of `data` along with `fds` in a single `sendmsg`,
+/// returning how many bytes were sent.
+pub(crate) fn send_some(socket: &UnixStream, data: &[u8], fds: &[UnixFd]) -> io::Result<usize> {
+    if fds.len() > MAX_FDS {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Too many fds"));
+    }
+
+    let mut iov = libc::iovec {
+        iov_base: data.as_ptr() as *mut libc::c_void,
+        iov_len: data.len(),
+    };
+    let mut message: libc::msghdr = unsafe { mem::zeroed() };
+    message.msg_iov = &mut iov;
+    message.msg_iovlen = 1;
+
+    let (mut control, control_length) = control_buffer(fds.len());
+    if !fds.is_empty() {
+        message.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        message.msg_controllen = control_length as _;
+        unsafe {
+            let header = libc::CMSG_FIRSTHDR(&message);
+            (*header).cmsg_level = libc::SOL_SOCKET;
+            (*header).cmsg_type = libc::SCM_RIGHTS;
+            (*header).cmsg_len = libc::CMSG_LEN((fds.len() * mem::size_of::<RawFd>()) as u32) as _;
+            let data = libc::CMSG_DATA(header) as *mut RawFd;
+            for (index, fd) in fds.iter().enumerate() {
+                ptr::write_unaligned(data.add(index), fd.as_raw_fd());
+            }
+        }
+    }
+
+    loop {
+        let result = unsafe { libc::sendmsg(socket.as_raw_fd(), &message, libc::MSG_NOSIGNAL) };
+        if result >= 0 {
+            return Ok(result as usize);
+        }
+
+        let error = io::Error::last_os_error();
+        if error.kind() != io::ErrorKind::Interrupted {
+            return Err(error);
+        }
+    }
+}
+
+/// Receive bytes into `buffer`, returning how many were read and the fds
+/// received along them. Zero bytes read means the peer closed the socket.
+pub(crate) fn receive(socket: &UnixStream, buffer: &mut [u8]) -> io::Result<(usize, Vec<UnixFd>)> {
+    let mut iov = libc::iovec {
+        iov_base: buffer.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buffer.len(),
+    };
+    let (mut control, control_length) = control_buffer(MAX_FDS);
+    let mut message: libc::msghdr = unsafe { mem::zeroed() };
+    message.msg_iov = &mut iov;
+    message.msg_iovlen = 1;
+    message.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    message.msg_controllen = control_length as _;
+
+    let length = loop {
+        let result = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut message, libc::MSG_CMSG_CLOEXEC) };
+        if result >= 0 {
+            break result as usize;
+        }
+
+        let error = io::Error::last_os_error();
+        if error.kind() != io::ErrorKind::Interrupted {
+            return Err(error);
+        }
+    };
+
+    let mut fds = Vec::new();
+    unsafe {
+        let mut header = libc::CMSG_FIRSTHDR(&message);
+        while !header.is_null() {
+            if (*header).cmsg_level == libc::SOL_SOCKET && (*header).cmsg_type == libc::SCM_RIGHTS {
+                let count = ((*header).cmsg_len as usize - libc::CMSG_LEN(0) as usize) / mem::size_of::<RawFd>();
+                let data = libc::CMSG_DATA(header) as *const RawFd;
+                for index in 0..count {
+                    fds.push(UnixFd::from_raw_fd(ptr::read_unaligned(data.add(index))));
+                }
+            }
+            header = libc::CMSG_NXTHDR(&message, header);
+        }
+    }
+
+    if message.msg_flags & libc::MSG_CTRUNC != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Received fds were truncated",
+        ));
+    }
+
+    Ok((length, fds))
+}
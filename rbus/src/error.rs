@@ -1,4 +1,12 @@
-use crate::types::{ObjectPathError, SignatureError};
+use crate::{
+    address::AddressError,
+    auth::AuthError,
+    message::{
+        types::{BusNameError, InterfaceError, MemberError},
+        MethodError,
+    },
+    types::{ObjectPathError, SignatureError},
+};
 use custom_error::custom_error;
 use std::io;
 
@@ -10,16 +18,136 @@ custom_error! {
             = "I/O error: {source}",
         FromUtf8 { source: std::string::FromUtf8Error }
             = "UTF-8 decoding error: {source}",
+        Utf8 { source: std::str::Utf8Error }
+            = "UTF-8 decoding error: {source}",
         Signature { source: SignatureError }
             = "Signature error: {source}",
         ObjectPath { source: ObjectPathError }
             = "Object path error: {source}",
+        Interface { source: InterfaceError }
+            = "Interface name error: {source}",
+        BusName { source: BusNameError }
+            = "Bus name error: {source}",
+        Member { source: MemberError }
+            = "Member name error: {source}",
+        Address { source: AddressError }
+            = "Address error: {source}",
+        Auth { source: AuthError }
+            = "Authentication error: {source}",
+        Method { source: MethodError }
+            = "Method error: {source}",
+        UnsupportedTransport { address: String }
+            = "Unsupported transport: {address}",
+        GuidMismatch { expected: String, found: String }
+            = "Expected the server GUID {expected}, found {found}",
+        ConnectionClosed
+            = "Connection closed",
+        InvalidReply { reply_serial: u32, source: Box<Error> }
+            = "Invalid reply to the call {reply_serial}: {source}",
+        UnixFdNotNegotiated
+            = "Unix fds can't be sent on this connection",
         InvalidVariant { value: u64 }
             = "Invalid variant value: {value}",
         InvalidEndianness { value: u8 }
             = "Invalid endianness value: {value}",
+        InvalidMessageType
+            = "Messages of type INVALID can't be sent",
+        MissingHeaderField { message_type: String, field: String }
+            = "{message_type} messages require a {field} header field",
+        DuplicateHeaderField { field: String }
+            = "Duplicate {field} header field",
+        SignatureMismatch { expected: String, found: String }
+            = "Signature mismatch: expected `{expected}`, found `{found}`",
+        ArrayTooLong { length: usize }
+            = "Array of {length} bytes exceeds the maximum array length",
+        MessageTooLong { length: usize }
+            = "Message of at least {length} bytes exceeds the maximum message length",
+        ArrayTooDeep
+            = "Arrays are nested too deep",
+        StructTooDeep
+            = "Structs are nested too deep",
+        EmptyStruct
+            = "Structs must have at least one field",
+        NestingTooDeep
+            = "Containers are nested too deep",
+        SignatureTooLong { length: usize }
+            = "Signature of {length} bytes exceeds the maximum signature length",
+        LengthOverflow { length: usize, remaining: usize }
+            = "Length of {length} bytes exceeds the {remaining} bytes left in the input",
+        ArrayLengthMismatch { end: usize, position: usize }
+            = "Array elements end at offset {position} instead of the array end at offset {end}",
+        ArrayElementSizeMismatch { length: usize, size: usize }
+            = "Array length {length} isn't a multiple of its element size {size}",
+        FixedArrayLengthMismatch { expected: usize, found: usize }
+            = "Expected an array of {expected} elements, found {found}",
+        BodySizeMismatch { body_size: usize, position: usize }
+            = "Message body ends at offset {position} instead of its size of {body_size} bytes",
+        ZeroValue { ty: String }
+            = "Expected a non-zero value for {ty}",
+        NonZeroPadding { position: usize }
+            = "Non-zero padding byte at offset {position}",
+        InvalidBoolean { value: u32 }
+            = "Invalid boolean value: {value}",
+        MissingNulTerminator { position: usize }
+            = "Missing NUL terminator at offset {position}",
+        InteriorNul { position: usize }
+            = "Interior NUL byte at offset {position}",
+        InvalidUnixFd { index: u32, count: usize }
+            = "Unix fd index {index} is out of the {count} fds sent along the message",
+        UnixFdCountMismatch { expected: u32, found: usize }
+            = "The message declares {expected} unix fds, {found} were sent along it",
+        UnexpectedFds { count: usize }
+            = "Raw messages can't be encoded along the {count} fds the marshaller already holds",
+        UnsentFds { count: usize }
+            = "The message refers to {count} fds, a marshaller must be used to send them",
+        TrailingData { position: usize }
+            = "Trailing data after the message at offset {position}",
+        Decode { path: String, offset: usize, source: Box<Error> }
+            = "{path} at offset {offset}: {source}",
         Custom { message: String }
             = "{message}",
         Unknown
             = "Unknown error",
 }
+
+impl Error {
+    /// Return the error without the decoding context added to it.
+    pub fn root(&self) -> &Error {
+        match self {
+            Error::Decode { source, .. } => source.root(),
+            error => error,
+        }
+    }
+
+    /// Wrap an error which happened while decoding a value of signature
+    /// `signature` starting at `offset`, unless it already has a context.
+    pub(crate) fn in_value(self, signature: &str, offset: usize) -> Error {
+        match self {
+            Error::Decode { .. } => self,
+            error => Error::Decode {
+                path: signature.into(),
+                offset,
+                source: Box::new(error),
+            },
+        }
+    }
+
+    /// Add `segment` in front of the path of a decoding error.
+    pub(crate) fn in_path(self, segment: &str, offset: usize) -> Error {
+        match self {
+            Error::Decode { path, offset, source } => {
+                let separator = if path.starts_with('[') { "" } else { "." };
+                Error::Decode {
+                    path: format!("{}{}{}", segment, separator, path),
+                    offset,
+                    source,
+                }
+            }
+            error => Error::Decode {
+                path: segment.into(),
+                offset,
+                source: Box::new(error),
+            },
+        }
+    }
+}
@@ -0,0 +1,282 @@
+use super::{align, alignment, fields, fixed_size, offset_size, unix_fd_unsupported};
+use crate::{
+    marshal::{Endianness, MAX_TOTAL_DEPTH},
+    types::{BasicType, ObjectPath, Signature, SignatureType, Value},
+    Error, Result,
+};
+use byteordered::ByteOrdered;
+
+/// Deserialize a value of type `ty` from the GVariant format, `data` must
+/// contain exactly one serialized value.
+///
+/// Data that isn't in normal form is read as the default value of its type,
+/// as required by the specification.
+pub fn decode(data: &[u8], ty: &SignatureType, endianness: Endianness) -> Result<Value> {
+    Decoder { endianness, depth: 0 }.read(data, ty)
+}
+
+/// Return the value used in place of non-normal data of type `ty`.
+fn default_value(ty: &SignatureType) -> Result<Value> {
+    let value = match ty {
+        SignatureType::Basic(ty) => match ty {
+            BasicType::Byte => Value::Byte(0),
+            BasicType::Boolean => Value::Boolean(false),
+            BasicType::Int16 => Value::Int16(0),
+            BasicType::UInt16 => Value::UInt16(0),
+            BasicType::Int32 => Value::Int32(0),
+            BasicType::UInt32 => Value::UInt32(0),
+            BasicType::Int64 => Value::Int64(0),
+            BasicType::UInt64 => Value::UInt64(0),
+            BasicType::Double => Value::Double(0.0),
+            BasicType::UnixFd => return Err(unix_fd_unsupported()),
+            BasicType::String => Value::String(String::new()),
+            BasicType::ObjectPath => Value::ObjectPath(ObjectPath::new("/")?),
+            BasicType::Signature => Value::Signature(Signature::new("")?),
+        },
+        SignatureType::Array(element) => match **element {
+            SignatureType::DictEntry(key, ref value) => Value::Dict {
+                key,
+                value: (**value).clone(),
+                entries: Vec::new(),
+            },
+            ref element => Value::Array {
+                ty: element.clone(),
+                values: Vec::new(),
+            },
+        },
+        SignatureType::Struct(_) | SignatureType::DictEntry(..) => Value::Struct(
+            fields(ty)
+                .expect("Struct types have fields")
+                .iter()
+                .map(default_value)
+                .collect::<Result<_>>()?,
+        ),
+        SignatureType::Variant => Value::Variant(Box::new(Value::Struct(Vec::new()))),
+        SignatureType::Maybe(element) => Value::Maybe {
+            ty: (**element).clone(),
+            value: None,
+        },
+    };
+
+    Ok(value)
+}
+
+/// Read a little-endian framing offset of `size` bytes at `pos`.
+fn read_offset(data: &[u8], pos: usize, size: usize) -> Option<usize> {
+    let bytes = data.get(pos..pos.checked_add(size)?)?;
+    Some(bytes.iter().rev().fold(0, |offset, &byte| (offset << 8) | byte as usize))
+}
+
+struct Decoder {
+    endianness: Endianness,
+    /// The number of containers being decoded, variants and maybes included.
+    depth: usize,
+}
+
+impl Decoder {
+    fn io<'a>(&self, data: &'a [u8]) -> ByteOrdered<&'a [u8], Endianness> {
+        ByteOrdered::runtime(data, self.endianness)
+    }
+
+    /// Read a value of type `ty`, failing if containers are nested more than
+    /// `MAX_TOTAL_DEPTH` deep.
+    fn read(&mut self, data: &[u8], ty: &SignatureType) -> Result<Value> {
+        if let SignatureType::Basic(_) = ty {
+            return self.read_value(data, ty);
+        }
+
+        self.depth += 1;
+        if self.depth > MAX_TOTAL_DEPTH {
+            return Err(Error::NestingTooDeep);
+        }
+        let value = self.read_value(data, ty);
+        self.depth -= 1;
+
+        value
+    }
+
+    fn read_value(&mut self, data: &[u8], ty: &SignatureType) -> Result<Value> {
+        if let Some(size) = fixed_size(ty) {
+            if data.len() != size {
+                return default_value(ty);
+            }
+        }
+
+        let value = match ty {
+            SignatureType::Basic(ty) => self.read_basic(data, *ty)?,
+            SignatureType::Array(element) => {
+                let elements = self.split_array(data, element);
+                match **element {
+                    SignatureType::DictEntry(key, ref value) => Value::Dict {
+                        key,
+                        value: (**value).clone(),
+                        entries: elements
+                            .into_iter()
+                            .map(|entry| match self.read(entry, element)? {
+                                Value::Struct(mut fields) => {
+                                    let value = fields.pop().expect("Dict entries have two fields");
+                                    let key = fields.pop().expect("Dict entries have two fields");
+                                    Ok((key, value))
+                                }
+                                _ => unreachable!("Dict entries are decoded as structs"),
+                            })
+                            .collect::<Result<_>>()?,
+                    },
+                    ref element => Value::Array {
+                        ty: element.clone(),
+                        values: elements
+                            .into_iter()
+                            .map(|value| self.read(value, element))
+                            .collect::<Result<_>>()?,
+                    },
+                }
+            }
+            SignatureType::Struct(_) | SignatureType::DictEntry(..) => {
+                let fields = fields(ty).expect("Struct types have fields");
+                let values = self
+                    .split_struct(data, &fields)
+                    .into_iter()
+                    .zip(fields.iter())
+                    .map(|(value, ty)| self.read(value, ty))
+                    .collect::<Result<_>>()?;
+
+                Value::Struct(values)
+            }
+            SignatureType::Variant => {
+                // A missing separator or an invalid type make a unit variant.
+                let split = data.iter().rposition(|&byte| byte == 0).and_then(|separator| {
+                    let signature = std::str::from_utf8(&data[separator + 1..]).ok()?;
+                    let ty = SignatureType::parse_gvariant(signature).ok()?;
+                    Some((&data[..separator], ty))
+                });
+
+                match split {
+                    Some((data, ty)) => Value::Variant(Box::new(self.read(data, &ty)?)),
+                    None => default_value(ty)?,
+                }
+            }
+            SignatureType::Maybe(element) => {
+                let value = match (data.split_last(), fixed_size(element)) {
+                    (Some(_), Some(size)) if data.len() == size => Some(self.read(data, element)?),
+                    (Some((0, data)), None) => Some(self.read(data, element)?),
+                    _ => None,
+                };
+
+                Value::Maybe {
+                    ty: (**element).clone(),
+                    value: value.map(Box::new),
+                }
+            }
+        };
+
+        Ok(value)
+    }
+
+    fn read_basic(&self, data: &[u8], ty: BasicType) -> Result<Value> {
+        let value = match ty {
+            BasicType::Byte => Value::Byte(data[0]),
+            BasicType::Boolean => Value::Boolean(data[0] != 0),
+            BasicType::Int16 => Value::Int16(self.io(data).read_i16()?),
+            BasicType::UInt16 => Value::UInt16(self.io(data).read_u16()?),
+            BasicType::Int32 => Value::Int32(self.io(data).read_i32()?),
+            BasicType::UInt32 => Value::UInt32(self.io(data).read_u32()?),
+            BasicType::Int64 => Value::Int64(self.io(data).read_i64()?),
+            BasicType::UInt64 => Value::UInt64(self.io(data).read_u64()?),
+            BasicType::Double => Value::Double(self.io(data).read_f64()?),
+            BasicType::UnixFd => return Err(unix_fd_unsupported()),
+            BasicType::String => match read_str(data) {
+                Some(value) => Value::String(value.into()),
+                None => return default_value(&SignatureType::Basic(ty)),
+            },
+            BasicType::ObjectPath => match read_str(data).and_then(|value| ObjectPath::new(value).ok()) {
+                Some(value) => Value::ObjectPath(value),
+                None => return default_value(&SignatureType::Basic(ty)),
+            },
+            BasicType::Signature => match read_str(data).and_then(|value| Signature::new(value).ok()) {
+                Some(value) => Value::Signature(value),
+                None => return default_value(&SignatureType::Basic(ty)),
+            },
+        };
+
+        Ok(value)
+    }
+
+    /// Split an array into its elements, an array with invalid framing
+    /// offsets is empty.
+    fn split_array<'a>(&self, data: &'a [u8], element: &SignatureType) -> Vec<&'a [u8]> {
+        if let Some(size) = fixed_size(element) {
+            if data.len() % size > 0 {
+                return Vec::new();
+            }
+            return data.chunks(size).collect();
+        }
+
+        if data.is_empty() {
+            return Vec::new();
+        }
+
+        // The last framing offset is the end of the last element, which is
+        // where the framing offsets start.
+        let size = offset_size(data.len());
+        let offsets_start = match read_offset(data, data.len().saturating_sub(size), size) {
+            Some(offsets_start) if offsets_start <= data.len() && (data.len() - offsets_start) % size == 0 => {
+                offsets_start
+            }
+            _ => return Vec::new(),
+        };
+
+        let mut elements = Vec::new();
+        let mut start = 0;
+        for pos in (offsets_start..data.len()).step_by(size) {
+            let end = read_offset(data, pos, size).unwrap_or(0);
+            start = align(start, alignment(element));
+            // Elements out of bounds are empty, and read as default values.
+            match data.get(start..end) {
+                Some(value) if end <= offsets_start => elements.push(value),
+                _ => elements.push(&[]),
+            }
+            start = end;
+        }
+
+        elements
+    }
+
+    /// Split a struct into its fields, fields out of bounds are empty.
+    fn split_struct<'a>(&self, data: &'a [u8], fields: &[SignatureType]) -> Vec<&'a [u8]> {
+        let size = offset_size(data.len());
+        // Framing offsets are read backwards from the end of the struct.
+        let mut offsets_start = Some(data.len());
+
+        let mut values = Vec::with_capacity(fields.len());
+        let mut start = 0;
+        for (index, field) in fields.iter().enumerate() {
+            start = align(start, alignment(field));
+            let end = match fixed_size(field) {
+                Some(field_size) => Some(start + field_size),
+                None if index + 1 == fields.len() => offsets_start,
+                None => {
+                    offsets_start = offsets_start.and_then(|offsets_start| offsets_start.checked_sub(size));
+                    offsets_start.and_then(|offsets_start| read_offset(data, offsets_start, size))
+                }
+            };
+
+            match (end, offsets_start) {
+                (Some(end), Some(offsets_start)) if start <= end && end <= offsets_start => {
+                    values.push(&data[start..end]);
+                    start = end;
+                }
+                _ => values.push(&[]),
+            }
+        }
+
+        values
+    }
+}
+
+/// Read a NUL terminated string, which must not contain other NUL bytes.
+fn read_str(data: &[u8]) -> Option<&str> {
+    match data.split_last() {
+        Some((0, data)) if !data.contains(&0) => std::str::from_utf8(data).ok(),
+        _ => None,
+    }
+}
@@ -0,0 +1,166 @@
+use super::{align, alignment, fixed_size, offset_size, unix_fd_unsupported};
+use crate::{
+    marshal::Endianness,
+    types::{SignatureType, Value},
+    Error, Result,
+};
+use byteordered::ByteOrdered;
+
+/// Serialize a value in the GVariant format.
+pub fn encode(value: &Value, endianness: Endianness) -> Result<Vec<u8>> {
+    let mut encoder = Encoder {
+        data: Vec::new(),
+        endianness,
+    };
+    encoder.write(value)?;
+
+    Ok(encoder.data)
+}
+
+fn type_mismatch(what: &str, ty: &SignatureType) -> Error {
+    Error::Custom {
+        message: format!("{} doesn't match the type `{}`", what, ty),
+    }
+}
+
+struct Encoder {
+    data: Vec<u8>,
+    endianness: Endianness,
+}
+
+impl Encoder {
+    fn io(&mut self) -> ByteOrdered<&mut Vec<u8>, Endianness> {
+        ByteOrdered::runtime(&mut self.data, self.endianness)
+    }
+
+    fn pad(&mut self, alignment: usize) {
+        let length = align(self.data.len(), alignment);
+        self.data.resize(length, 0);
+    }
+
+    fn write(&mut self, value: &Value) -> Result<()> {
+        let ty = value.signature_type();
+        self.pad(alignment(&ty));
+
+        match value {
+            Value::Byte(value) => self.data.push(*value),
+            Value::Boolean(value) => self.data.push(*value as u8),
+            Value::Int16(value) => self.io().write_i16(*value)?,
+            Value::UInt16(value) => self.io().write_u16(*value)?,
+            Value::Int32(value) => self.io().write_i32(*value)?,
+            Value::UInt32(value) => self.io().write_u32(*value)?,
+            Value::Int64(value) => self.io().write_i64(*value)?,
+            Value::UInt64(value) => self.io().write_u64(*value)?,
+            Value::Double(value) => self.io().write_f64(*value)?,
+            Value::UnixFd(_) => return Err(unix_fd_unsupported()),
+            Value::String(value) => self.write_str(value),
+            Value::ObjectPath(value) => self.write_str(value),
+            Value::Signature(value) => self.write_str(value.as_str()),
+            Value::Array { ty, values } => self.write_array(ty, values.len(), |encoder, index| {
+                let value = &values[index];
+                if value.signature_type() != *ty {
+                    return Err(type_mismatch("Array element", ty));
+                }
+                encoder.write(value)
+            })?,
+            Value::Dict { key, value, entries } => {
+                let entry = SignatureType::DictEntry(*key, Box::new(value.clone()));
+                self.write_array(&entry, entries.len(), |encoder, index| {
+                    let (entry_key, entry_value) = &entries[index];
+                    let key_matches = entry_key.signature_type() == SignatureType::Basic(*key);
+                    if !key_matches || entry_value.signature_type() != *value {
+                        return Err(type_mismatch("Dict entry", &entry));
+                    }
+                    encoder.write_struct(&entry, &[entry_key, entry_value])
+                })?
+            }
+            Value::Struct(fields) => self.write_struct(&ty, &fields.iter().collect::<Vec<_>>())?,
+            Value::Variant(value) => {
+                self.write(value)?;
+                self.data.push(0);
+                self.data.extend_from_slice(value.signature_type().to_string().as_bytes());
+            }
+            Value::Maybe { ty, value } => {
+                if let Some(value) = value {
+                    if value.signature_type() != *ty {
+                        return Err(type_mismatch("Maybe value", ty));
+                    }
+                    self.write(value)?;
+                    if fixed_size(ty).is_none() {
+                        self.data.push(0);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_str(&mut self, value: &str) {
+        self.data.extend_from_slice(value.as_bytes());
+        self.data.push(0);
+    }
+
+    /// Write `length` elements of type `element` with `f`, followed by
+    /// their framing offsets if they aren't fixed size.
+    fn write_array<F>(&mut self, element: &SignatureType, length: usize, mut f: F) -> Result<()>
+    where
+        F: FnMut(&mut Encoder, usize) -> Result<()>,
+    {
+        let start = self.data.len();
+        let fixed = fixed_size(element).is_some();
+
+        let mut offsets = Vec::new();
+        for index in 0..length {
+            self.pad(alignment(element));
+            f(self, index)?;
+            if !fixed {
+                offsets.push(self.data.len() - start);
+            }
+        }
+
+        self.write_offsets(start, &offsets);
+        Ok(())
+    }
+
+    fn write_struct(&mut self, ty: &SignatureType, fields: &[&Value]) -> Result<()> {
+        let start = self.data.len();
+
+        let mut offsets = Vec::new();
+        for (index, field) in fields.iter().enumerate() {
+            self.write(field)?;
+            if fixed_size(&field.signature_type()).is_none() && index + 1 < fields.len() {
+                offsets.push(self.data.len() - start);
+            }
+        }
+
+        if fixed_size(ty).is_some() {
+            self.pad(alignment(ty));
+            if self.data.len() == start {
+                self.data.push(0);
+            }
+        } else {
+            // Struct framing offsets are stored in reverse order.
+            offsets.reverse();
+            self.write_offsets(start, &offsets);
+        }
+
+        Ok(())
+    }
+
+    /// Append the framing offsets of the container starting at `start`,
+    /// using the smallest offset size that can address the whole container.
+    fn write_offsets(&mut self, start: usize, offsets: &[usize]) {
+        let body = self.data.len() - start;
+        let size = [1, 2, 4, 8]
+            .iter()
+            .cloned()
+            .find(|&size| offset_size(body + offsets.len() * size) == size)
+            .unwrap_or(8);
+
+        for &offset in offsets.iter() {
+            let offset = (offset as u64).to_le_bytes();
+            self.data.extend_from_slice(&offset[..size]);
+        }
+    }
+}
@@ -0,0 +1,270 @@
+//! GVariant serialization format, as used by GLib, dconf and ostree.
+//!
+//! GVariant shares its type system with D-Bus, plus maybe types (`m`), but
+//! lays values out differently: strings have no length prefix, variable
+//! sized containers end with framing offsets instead of starting with a
+//! length and alignment depends on the contained types. Framing offsets are
+//! always little-endian, the endianness only applies to numbers.
+pub use decoder::decode;
+pub use encoder::encode;
+
+use crate::{
+    marshal::{Endianness, Marshaller},
+    types::{parse_signature, BasicType, DBusType, SignatureType, Value},
+    Error, Result,
+};
+
+mod decoder;
+mod encoder;
+
+/// Serialize a value in the GVariant format.
+///
+/// The value goes through its D-Bus encoding and a dynamic `Value` first,
+/// so this is meant for data files rather than hot paths. Packed tuples are
+/// laid out as a GVariant tuple.
+pub fn to_bytes<T: DBusType>(value: &T, endianness: Endianness) -> Result<Vec<u8>> {
+    let ty = signature_type::<T>()?;
+
+    let mut marshaller = Marshaller::new(Vec::new(), endianness);
+    marshaller.write_value(value)?;
+    let data = marshaller.into_inner();
+
+    let mut marshaller = Marshaller::new(data.as_slice(), endianness);
+    encode(&Value::decode(&mut marshaller, &ty)?, endianness)
+}
+
+/// Deserialize a value from the GVariant format, see `to_bytes`.
+pub fn from_bytes<T: DBusType>(data: &[u8], endianness: Endianness) -> Result<T> {
+    let ty = signature_type::<T>()?;
+    let value = decode(data, &ty, endianness)?;
+
+    let mut marshaller = Marshaller::new(Vec::new(), endianness);
+    value.encode(&mut marshaller)?;
+    let data = marshaller.into_inner();
+
+    Marshaller::new(data.as_slice(), endianness).read_value()
+}
+
+fn signature_type<T: DBusType>() -> Result<SignatureType> {
+    let mut types = parse_signature(&T::signature())?;
+    if types.len() == 1 {
+        Ok(types.remove(0))
+    } else {
+        Ok(SignatureType::Struct(types))
+    }
+}
+
+pub(crate) fn maybe_unsupported() -> Error {
+    Error::Custom {
+        message: "Maybe types only exist in the GVariant format".into(),
+    }
+}
+
+/// Handles index the fds sent along a message, there are none here.
+fn unix_fd_unsupported() -> Error {
+    Error::Custom {
+        message: "Unix fds can't be stored in GVariant data".into(),
+    }
+}
+
+#[inline]
+fn align(offset: usize, alignment: usize) -> usize {
+    (offset + alignment - 1) & !(alignment - 1)
+}
+
+fn basic_size(ty: BasicType) -> Option<usize> {
+    match ty {
+        BasicType::Byte | BasicType::Boolean => Some(1),
+        BasicType::Int16 | BasicType::UInt16 => Some(2),
+        BasicType::Int32 | BasicType::UInt32 | BasicType::UnixFd => Some(4),
+        BasicType::Int64 | BasicType::UInt64 | BasicType::Double => Some(8),
+        BasicType::String | BasicType::ObjectPath | BasicType::Signature => None,
+    }
+}
+
+/// Return the fields of a struct or dict entry type.
+fn fields(ty: &SignatureType) -> Option<Vec<SignatureType>> {
+    match ty {
+        SignatureType::Struct(fields) => Some(fields.clone()),
+        SignatureType::DictEntry(key, value) => Some(vec![SignatureType::Basic(*key), (**value).clone()]),
+        _ => None,
+    }
+}
+
+/// Return the GVariant alignment of a type, which differs from its D-Bus
+/// alignment.
+fn alignment(ty: &SignatureType) -> usize {
+    match ty {
+        SignatureType::Basic(ty) => basic_size(*ty).unwrap_or(1),
+        SignatureType::Array(element) | SignatureType::Maybe(element) => alignment(element),
+        SignatureType::Struct(_) | SignatureType::DictEntry(..) => {
+            fields(ty).unwrap_or_default().iter().map(alignment).max().unwrap_or(1)
+        }
+        SignatureType::Variant => 8,
+    }
+}
+
+/// Return the size of a type if all its values have the same size.
+fn fixed_size(ty: &SignatureType) -> Option<usize> {
+    match ty {
+        SignatureType::Basic(ty) => basic_size(*ty),
+        SignatureType::Struct(_) | SignatureType::DictEntry(..) => {
+            let mut size = 0;
+            for field in fields(ty).unwrap_or_default().iter() {
+                size = align(size, alignment(field)) + fixed_size(field)?;
+            }
+
+            // The unit type still takes a byte.
+            Some(align(size, alignment(ty)).max(1))
+        }
+        SignatureType::Array(_) | SignatureType::Maybe(_) | SignatureType::Variant => None,
+    }
+}
+
+/// Return the size of the framing offsets in a container of `size` bytes.
+fn offset_size(size: usize) -> usize {
+    if size <= 0xff {
+        1
+    } else if size <= 0xffff {
+        2
+    } else if size as u64 <= 0xffff_ffff {
+        4
+    } else {
+        8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{marshal::MAX_TOTAL_DEPTH, types::ObjectPath};
+
+    fn roundtrip(value: Value) {
+        for &endianness in &[Endianness::Little, Endianness::Big] {
+            let data = encode(&value, endianness).unwrap();
+            assert_eq!(decode(&data, &value.signature_type(), endianness).unwrap(), value);
+        }
+    }
+
+    fn strings(values: &[&str]) -> Value {
+        Value::Array {
+            ty: SignatureType::Basic(BasicType::String),
+            values: values.iter().cloned().map(Value::from).collect(),
+        }
+    }
+
+    #[test]
+    fn test_encode_spec_examples() {
+        let data = encode(&strings(&["i", "can", "has", "strings?"]), Endianness::Little).unwrap();
+        assert_eq!(data, b"i\0can\0has\0strings?\0\x02\x06\x0a\x13");
+
+        let pair = |s: &str, i: i32| Value::Struct(vec![Value::from(s), Value::from(i)]);
+        let value = Value::Array {
+            ty: SignatureType::parse_gvariant("(si)").unwrap(),
+            values: vec![pair("hi", -2), pair("bye", -1)],
+        };
+        let data = encode(&value, Endianness::Little).unwrap();
+        assert_eq!(
+            data,
+            b"hi\0\0\xfe\xff\xff\xff\x03\0\0\0bye\0\xff\xff\xff\xff\x04\x09\x15".to_vec()
+        );
+
+        let value = Value::Maybe {
+            ty: SignatureType::Basic(BasicType::String),
+            value: Some(Box::new(Value::from("hello world"))),
+        };
+        let data = encode(&value, Endianness::Little).unwrap();
+        assert_eq!(data, b"hello world\0\0");
+    }
+
+    #[test]
+    fn test_roundtrip_values() {
+        roundtrip(strings(&[]));
+        roundtrip(Value::Struct(vec![
+            Value::from(1u8),
+            Value::from(ObjectPath::new("/a").unwrap()),
+            Value::from(3u64),
+            Value::Array {
+                ty: SignatureType::Basic(BasicType::UInt16),
+                values: vec![Value::from(4u16), Value::from(5u16)],
+            },
+        ]));
+        roundtrip(Value::Dict {
+            key: BasicType::String,
+            value: SignatureType::Variant,
+            entries: vec![
+                (Value::from("a"), Value::Variant(Box::new(Value::from(1i64)))),
+                (Value::from("b"), Value::Variant(Box::new(strings(&["c"])))),
+            ],
+        });
+        roundtrip(Value::Maybe {
+            ty: SignatureType::Basic(BasicType::UInt32),
+            value: None,
+        });
+        roundtrip(Value::Array {
+            ty: SignatureType::parse_gvariant("mu").unwrap(),
+            values: vec![
+                Value::Maybe {
+                    ty: SignatureType::Basic(BasicType::UInt32),
+                    value: Some(Box::new(Value::from(7u32))),
+                },
+                Value::Maybe {
+                    ty: SignatureType::Basic(BasicType::UInt32),
+                    value: None,
+                },
+            ],
+        });
+    }
+
+    #[test]
+    fn test_typed_roundtrip() {
+        let value = (String::from("foo"), -1i32);
+        let data = to_bytes(&value, Endianness::Little).unwrap();
+        assert_eq!(data, b"foo\0\xff\xff\xff\xff\x04");
+        assert_eq!(from_bytes::<(String, i32)>(&data, Endianness::Little).unwrap(), value);
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        // Data that isn't in normal form is read as the default value.
+        let decode = |data: &[u8], ty: &str| {
+            let ty = SignatureType::parse_gvariant(ty).unwrap();
+            decode(data, &ty, Endianness::Little)
+        };
+        assert_eq!(decode(b"a\0\x05", "as").unwrap(), strings(&[]));
+        assert_eq!(decode(b"a", "s").unwrap(), Value::from(""));
+        assert_eq!(decode(b"\0\0", "u").unwrap(), Value::from(0u32));
+        assert_eq!(decode(b"\x02", "b").unwrap(), Value::from(true));
+        assert_eq!(decode(b"a\0b\0\x02\x09\x04", "as").unwrap(), strings(&["a", "", ""]));
+        assert_eq!(
+            decode(b"\x01\0\0", "(yu)").unwrap(),
+            Value::Struct(vec![Value::from(0u8), Value::from(0u32)])
+        );
+        assert_eq!(decode(b"\x01\0", "v").unwrap(), Value::Variant(Box::new(Value::Struct(vec![]))));
+        assert_eq!(
+            decode(b"a", "ms").unwrap(),
+            Value::Maybe {
+                ty: SignatureType::Basic(BasicType::String),
+                value: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_decode_depth_limit() {
+        // Variants nested `depth` times around a byte.
+        let nested = |depth: usize| {
+            let mut data = b"\x01\0y".to_vec();
+            for _ in 1..depth {
+                data.extend_from_slice(b"\0v");
+            }
+            data
+        };
+
+        assert_ok!(decode(&nested(MAX_TOTAL_DEPTH), &SignatureType::Variant, Endianness::Little));
+        assert_err_matches!(
+            decode(&nested(MAX_TOTAL_DEPTH + 1), &SignatureType::Variant, Endianness::Little),
+            Error::NestingTooDeep
+        );
+    }
+}
@@ -3,7 +3,14 @@ pub use error::*;
 #[macro_use]
 pub(crate) mod utils;
 
+pub mod address;
+pub mod auth;
+pub mod connection;
 mod error;
+#[cfg(feature = "gvariant")]
+pub mod gvariant;
 pub mod marshal;
 pub mod message;
+#[cfg(feature = "serde")]
+pub mod serde;
 pub mod types;
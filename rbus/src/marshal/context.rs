@@ -0,0 +1,87 @@
+use crate::{
+    types::{MAX_ARRAY_DEPTH, MAX_STRUCT_DEPTH},
+    Error, Result,
+};
+use byteordered::Endianness;
+
+/// The maximum total nesting depth while decoding, variants included.
+pub const MAX_TOTAL_DEPTH: usize = 64;
+
+/// How strictly decoded data is checked against its canonical encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Validation {
+    /// Reject non-zero padding, booleans other than 0 and 1, strings with a
+    /// missing NUL terminator or an interior NUL, and invalid UTF-8.
+    Strict,
+    /// Accept anything which can still be decoded: padding and terminators
+    /// are skipped, booleans are true when non-zero and invalid UTF-8 is
+    /// replaced.
+    Lenient,
+}
+
+/// State shared by every nested encode and decode of a single message.
+///
+/// The absolute offset is tracked by the marshaller cursor itself, nested
+/// marshallers are created at the offset they will be written at so
+/// alignment is always computed relative to the start of the message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Context {
+    pub endianness: Endianness,
+    pub array_depth: usize,
+    pub struct_depth: usize,
+    pub variant_depth: usize,
+    pub validation: Validation,
+    /// The absolute offset the input ends at, when it is known, so lengths
+    /// can be checked before allocating anything.
+    pub input_end: Option<usize>,
+}
+
+impl Context {
+    pub fn new(endianness: Endianness) -> Context {
+        Context {
+            endianness,
+            array_depth: 0,
+            struct_depth: 0,
+            variant_depth: 0,
+            validation: Validation::Strict,
+            input_end: None,
+        }
+    }
+
+    fn check_total_depth(&self) -> Result<()> {
+        if self.array_depth + self.struct_depth + self.variant_depth > MAX_TOTAL_DEPTH {
+            return Err(Error::NestingTooDeep);
+        }
+        Ok(())
+    }
+
+    /// Enter a nested array, failing if it is nested too deep.
+    pub fn enter_array(&mut self) -> Result<()> {
+        self.array_depth += 1;
+        if self.array_depth > MAX_ARRAY_DEPTH {
+            return Err(Error::ArrayTooDeep);
+        }
+        self.check_total_depth()
+    }
+
+    /// Enter a nested struct, failing if it is nested too deep.
+    pub fn enter_struct(&mut self) -> Result<()> {
+        self.struct_depth += 1;
+        if self.struct_depth > MAX_STRUCT_DEPTH {
+            return Err(Error::StructTooDeep);
+        }
+        self.check_total_depth()
+    }
+
+    /// Enter a nested variant, failing if it is nested too deep.
+    pub fn enter_variant(&mut self) -> Result<()> {
+        self.variant_depth += 1;
+        self.check_total_depth()
+    }
+}
+
+impl Default for Context {
+    fn default() -> Context {
+        Context::new(Endianness::native())
+    }
+}
@@ -8,10 +8,16 @@ pub struct Cursor<T> {
 
 impl<T> Cursor<T> {
     pub fn new(inner: T) -> Cursor<T> {
+        Cursor::with_position(inner, 0)
+    }
+
+    /// Create a cursor whose read and write positions start at `position`
+    /// instead of zero.
+    pub fn with_position(inner: T, position: usize) -> Cursor<T> {
         Cursor {
             inner,
-            write_pos: 0,
-            read_pos: 0,
+            write_pos: position,
+            read_pos: position,
         }
     }
 
@@ -67,6 +73,22 @@ impl<T> Cursor<T> {
     }
 }
 
+impl<'a> Cursor<&'a [u8]> {
+    /// Borrow the next `length` bytes of the underlying slice, advancing the
+    /// read position past them.
+    pub fn read_slice(&mut self, length: usize) -> io::Result<&'a [u8]> {
+        let inner: &'a [u8] = self.inner;
+        if inner.len() < length {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        let (data, rest) = inner.split_at(length);
+        self.inner = rest;
+        self.read_pos = self.read_pos.wrapping_add(length);
+        Ok(data)
+    }
+}
+
 impl<T> io::Write for Cursor<T>
 where
     T: io::Write,
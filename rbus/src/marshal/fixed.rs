@@ -0,0 +1,46 @@
+use std::{mem, slice};
+
+/// The number of values byte swapped or read at once when an array can't be
+/// handled in a single copy.
+pub(super) const CHUNK_LENGTH: usize = 16 * 1024;
+
+/// A basic type encoded as its memory representation, in the marshaller
+/// endianness.
+///
+/// # Safety
+///
+/// Implementors must not have any padding and must be valid for any bit
+/// pattern, so arrays of them can be copied from and to raw bytes.
+pub unsafe trait FixedSize: Copy + Default {
+    fn swap_bytes(self) -> Self;
+}
+
+macro_rules! impl_fixed_size {
+    ($($ty:ty),*) => {
+        $(
+        unsafe impl FixedSize for $ty {
+            #[inline]
+            fn swap_bytes(self) -> $ty {
+                <$ty>::swap_bytes(self)
+            }
+        }
+        )*
+    }
+}
+
+impl_fixed_size!(u8, i16, u16, i32, u32, i64, u64);
+
+unsafe impl FixedSize for f64 {
+    #[inline]
+    fn swap_bytes(self) -> f64 {
+        f64::from_bits(self.to_bits().swap_bytes())
+    }
+}
+
+pub(super) fn as_bytes<T: FixedSize>(values: &[T]) -> &[u8] {
+    unsafe { slice::from_raw_parts(values.as_ptr() as *const u8, mem::size_of_val(values)) }
+}
+
+pub(super) fn as_bytes_mut<T: FixedSize>(values: &mut [T]) -> &mut [u8] {
+    unsafe { slice::from_raw_parts_mut(values.as_mut_ptr() as *mut u8, mem::size_of_val(values)) }
+}
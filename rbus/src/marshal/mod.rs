@@ -1,33 +1,112 @@
-use crate::{types::DBusType, Result};
+use crate::{
+    types::{DBusBorrowType, DBusType, Signature, UnixFd, Value},
+    Error, Result,
+};
 use byteordered::ByteOrdered;
 pub use byteordered::Endianness;
+pub use context::{Context, Validation, MAX_TOTAL_DEPTH};
 pub use cursor::Cursor;
+pub use fixed::FixedSize;
 use std::{
     io::{self, Read, Write},
+    mem,
     ops::Deref,
+    str,
 };
 
+mod context;
 mod cursor;
+mod fixed;
+
+/// The maximum length of an array, in bytes.
+pub const MAX_ARRAY_LENGTH: usize = 64 * 1024 * 1024;
+/// The maximum length of a message, header included, in bytes.
+pub const MAX_MESSAGE_LENGTH: usize = 128 * 1024 * 1024;
+
+#[inline]
+fn padding(offset: usize, alignment: u8) -> usize {
+    let alignment = alignment as usize;
+    (alignment - (offset % alignment)) % alignment
+}
+
+/// Check that a string read at `position` has a NUL terminator and no
+/// interior NUL.
+fn check_string(data: &[u8], terminator: u8, position: usize) -> Result<()> {
+    if let Some(index) = data.iter().position(|&byte| byte == 0) {
+        return Err(Error::InteriorNul {
+            position: position + index,
+        });
+    }
+    if terminator != 0 {
+        return Err(Error::MissingNulTerminator {
+            position: position + data.len(),
+        });
+    }
+
+    Ok(())
+}
 
 pub struct Marshaller<T> {
     inner: Cursor<T>,
-    pub endianness: Endianness,
+    context: Context,
+    /// The Unix fds sent along the message, which encoded `UnixFd` values
+    /// are indexes into.
+    fds: Vec<UnixFd>,
 }
 
 impl<T> Marshaller<T> {
     pub fn new(inner: T, endianness: Endianness) -> Marshaller<T> {
-        Marshaller {
-            inner: Cursor::new(inner),
-            endianness,
-        }
+        Marshaller::with_context(inner, Context::new(endianness), 0)
     }
 
     pub fn new_native(inner: T) -> Marshaller<T> {
         Marshaller::new(inner, Endianness::native())
     }
 
+    /// Create a marshaller sharing the given context, whose first byte is
+    /// located at `offset` from the start of the message.
+    pub fn with_context(inner: T, context: Context, offset: usize) -> Marshaller<T> {
+        Marshaller {
+            inner: Cursor::with_position(inner, offset),
+            context,
+            fds: Vec::new(),
+        }
+    }
+
     pub fn io(&mut self) -> ByteOrdered<&mut Cursor<T>, Endianness> {
-        ByteOrdered::runtime(&mut self.inner, self.endianness)
+        ByteOrdered::runtime(&mut self.inner, self.context.endianness)
+    }
+
+    pub fn context(&self) -> &Context {
+        &self.context
+    }
+
+    pub fn context_mut(&mut self) -> &mut Context {
+        &mut self.context
+    }
+
+    pub fn endianness(&self) -> Endianness {
+        self.context.endianness
+    }
+
+    pub fn set_endianness(&mut self, endianness: Endianness) {
+        self.context.endianness = endianness;
+    }
+
+    pub fn set_validation(&mut self, validation: Validation) {
+        self.context.validation = validation;
+    }
+
+    pub fn is_strict(&self) -> bool {
+        self.context.validation == Validation::Strict
+    }
+
+    pub fn write_position(&self) -> usize {
+        self.inner.write_position()
+    }
+
+    pub fn read_position(&self) -> usize {
+        self.inner.read_position()
     }
 
     pub fn into_inner(self) -> T {
@@ -37,6 +116,41 @@ impl<T> Marshaller<T> {
     pub fn get_ref(&self) -> &T {
         self.inner.get_ref()
     }
+
+    pub fn fds(&self) -> &[UnixFd] {
+        &self.fds
+    }
+
+    /// Set the fds received along the message, before decoding it.
+    pub fn set_fds(&mut self, fds: Vec<UnixFd>) {
+        self.fds = fds;
+    }
+
+    /// Take the fds collected while encoding, to send them along the message.
+    pub fn take_fds(&mut self) -> Vec<UnixFd> {
+        mem::take(&mut self.fds)
+    }
+
+    /// Add a fd to the fd list if it isn't there yet, and return its index.
+    pub fn push_fd(&mut self, fd: &UnixFd) -> u32 {
+        let index = match self.fds.iter().position(|other| other == fd) {
+            Some(index) => index,
+            None => {
+                self.fds.push(fd.clone());
+                self.fds.len() - 1
+            }
+        };
+
+        index as u32
+    }
+
+    /// Return the fd at `index` in the fd list.
+    pub fn fd(&self, index: u32) -> Result<UnixFd> {
+        self.fds.get(index as usize).cloned().ok_or(Error::InvalidUnixFd {
+            index,
+            count: self.fds.len(),
+        })
+    }
 }
 
 impl<T> Deref for Marshaller<T> {
@@ -52,26 +166,574 @@ where
     T: Write,
 {
     pub fn write_padding(&mut self, alignment: u8) -> io::Result<()> {
-        let padding = vec![0; self.inner.read_padding(alignment as usize)];
+        let padding = vec![0; self.inner.write_padding(alignment as usize)];
         self.inner.write_all(&padding)
     }
 
+    /// Encode a value, after aligning the output to its alignment.
     pub fn write_value<U: DBusType>(&mut self, value: &U) -> Result<()> {
+        self.write_padding(U::alignment())?;
         value.encode(self)
     }
+
+    pub fn write_values(&mut self, values: &[Value]) -> Result<()> {
+        for value in values.iter() {
+            value.encode(self)?;
+        }
+        Ok(())
+    }
+
+    /// Encode an array whose elements are aligned to `alignment`.
+    ///
+    /// The elements are written by `f` in a nested marshaller located where
+    /// the array data will be, so their padding is the same as if they had
+    /// been written in place.
+    pub fn write_array<F>(&mut self, alignment: u8, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut Marshaller<Vec<u8>>) -> Result<()>,
+    {
+        let mut inner = self.begin_array(alignment);
+        f(&mut inner)?;
+        self.end_array(alignment, inner)
+    }
+
+    /// Create the nested marshaller the elements of an array will be written
+    /// to, see `write_array`.
+    pub fn begin_array(&mut self, alignment: u8) -> Marshaller<Vec<u8>> {
+        let length_pos = self.write_position() + padding(self.write_position(), 4);
+        let data_pos = length_pos + 4 + padding(length_pos + 4, alignment);
+
+        let mut context = self.context;
+        context.array_depth += 1;
+
+        let mut inner = Marshaller::with_context(Vec::new(), context, data_pos);
+        inner.fds = self.take_fds();
+        inner
+    }
+
+    /// Write an array whose elements were written to a marshaller created by
+    /// `begin_array`.
+    pub fn end_array(&mut self, alignment: u8, mut inner: Marshaller<Vec<u8>>) -> Result<()> {
+        self.fds = inner.take_fds();
+        let data = inner.into_inner();
+
+        self.write_padding(4)?;
+        self.io().write_u32(data.len() as u32)?;
+        self.write_padding(alignment)?;
+        self.io().write_all(&data)?;
+        Ok(())
+    }
+
+    /// Encode an array of fixed size basic types at once, their bytes are
+    /// copied as is when the endianness is the native one.
+    pub fn write_fixed_array<U: FixedSize>(&mut self, values: &[U]) -> Result<()> {
+        self.write_padding(4)?;
+        self.io().write_u32(mem::size_of_val(values) as u32)?;
+        self.write_padding(mem::size_of::<U>() as u8)?;
+
+        if self.endianness() == Endianness::native() {
+            self.inner.write_all(fixed::as_bytes(values))?;
+        } else {
+            let mut buffer = Vec::with_capacity(values.len().min(fixed::CHUNK_LENGTH));
+            for chunk in values.chunks(fixed::CHUNK_LENGTH) {
+                buffer.clear();
+                buffer.extend(chunk.iter().map(|value| value.swap_bytes()));
+                self.inner.write_all(fixed::as_bytes(&buffer))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Encode a struct, aligned to 8 bytes, whose fields are written by `f`.
+    pub fn write_struct<F>(&mut self, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut Self) -> Result<()>,
+    {
+        self.write_padding(8)?;
+
+        self.context.struct_depth += 1;
+        let res = f(self);
+        self.context.struct_depth -= 1;
+
+        res
+    }
 }
 
 impl<T> Marshaller<T>
 where
     T: Read,
 {
-    pub fn read_padding(&mut self, alignment: u8) -> io::Result<()> {
-        let mut padding = Vec::with_capacity(self.inner.read_padding(alignment as usize));
-        unsafe { padding.set_len(padding.capacity()) }
-        self.inner.read_exact(&mut padding)
+    /// Skip the padding up to `alignment`, which must be zero in strict mode.
+    pub fn read_padding(&mut self, alignment: u8) -> Result<()> {
+        let position = self.read_position();
+        let mut padding = [0; 8];
+        let padding = &mut padding[..self.inner.read_padding(alignment as usize)];
+        self.inner.read_exact(padding)?;
+
+        if self.is_strict() {
+            if let Some(index) = padding.iter().position(|&byte| byte != 0) {
+                return Err(Error::NonZeroPadding {
+                    position: position + index,
+                });
+            }
+        }
+
+        Ok(())
     }
 
+    /// Decode a value, after skipping the padding up to its alignment.
+    ///
+    /// Errors get the signature of the value and its offset as context,
+    /// unless an inner value already added its own.
     pub fn read_value<U: DBusType>(&mut self) -> Result<U> {
-        U::decode(self)
+        let start = self.read_position();
+        self.read_padding(U::alignment())
+            .map_err(|err| err.in_value(&U::signature(), start))?;
+
+        let offset = self.read_position();
+        U::decode(self).map_err(|err| err.in_value(&U::signature(), offset))
+    }
+
+    /// Decode a value like `read_value`, adding `name` to the path of its
+    /// errors.
+    pub fn read_field<U: DBusType>(&mut self, name: &str) -> Result<U> {
+        let offset = self.read_position();
+        self.read_value().map_err(|err| err.in_path(name, offset))
+    }
+
+    /// Decode one dynamically typed value for each single complete type of
+    /// the given signature.
+    pub fn read_values(&mut self, signature: &Signature) -> Result<Vec<Value>> {
+        signature.types().iter().map(|ty| Value::decode(self, ty)).collect()
+    }
+
+    /// Check that `length` more bytes can be read without going past the
+    /// maximum message length or the end of the input, if it is known.
+    pub fn check_length(&self, length: usize) -> Result<()> {
+        let end = self.read_position().saturating_add(length);
+        if end > MAX_MESSAGE_LENGTH {
+            return Err(Error::MessageTooLong { length: end });
+        }
+
+        if let Some(input_end) = self.context.input_end {
+            if end > input_end {
+                let remaining = input_end.saturating_sub(self.read_position());
+                return Err(Error::LengthOverflow { length, remaining });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read `length` bytes, which are checked with `check_length` first.
+    ///
+    /// The buffer grows with the data actually read, so a length prefix alone
+    /// can't make it allocate.
+    pub fn read_bytes(&mut self, length: usize) -> Result<Vec<u8>> {
+        self.check_length(length)?;
+
+        let mut data = Vec::new();
+        (&mut self.inner).take(length as u64).read_to_end(&mut data)?;
+        if data.len() != length {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+
+        Ok(data)
+    }
+
+    /// Read a string of `length` bytes followed by its NUL terminator.
+    pub fn read_string(&mut self, length: usize) -> Result<String> {
+        let position = self.read_position();
+        let data = self.read_bytes(length)?;
+        let terminator = self.io().read_u8()?;
+
+        if self.is_strict() {
+            check_string(&data, terminator, position)?;
+            return Ok(String::from_utf8(data)?);
+        }
+
+        match String::from_utf8(data) {
+            Ok(value) => Ok(value),
+            Err(err) => Ok(String::from_utf8_lossy(err.as_bytes()).into_owned()),
+        }
+    }
+
+    /// Decode an array whose elements are aligned to `alignment`, calling `f`
+    /// until the whole array has been consumed.
+    pub fn read_array<F>(&mut self, alignment: u8, mut f: F) -> Result<()>
+    where
+        F: FnMut(&mut Self) -> Result<()>,
+    {
+        let end = self.begin_read_array(alignment)?;
+        let mut index = 0;
+        while self.read_position() < end {
+            let offset = self.read_position();
+            f(self).map_err(|err| err.in_path(&format!("[{}]", index), offset))?;
+            index += 1;
+        }
+        self.end_read_array(end)
+    }
+
+    /// Read an array length and the padding up to its first element, and
+    /// return the position where the array ends.
+    ///
+    /// The length and nesting depth are checked against the spec limits, the
+    /// array must then be finished with `end_read_array`.
+    pub fn begin_read_array(&mut self, alignment: u8) -> Result<usize> {
+        self.read_padding(4)?;
+        let length = self.io().read_u32()? as usize;
+        if length > MAX_ARRAY_LENGTH {
+            return Err(Error::ArrayTooLong { length });
+        }
+
+        self.read_padding(alignment)?;
+        self.check_length(length)?;
+        self.context.enter_array()?;
+
+        Ok(self.read_position() + length)
+    }
+
+    /// Finish an array started with `begin_read_array`.
+    pub fn end_read_array(&mut self, end: usize) -> Result<()> {
+        self.context.array_depth -= 1;
+
+        if self.read_position() != end {
+            return Err(Error::ArrayLengthMismatch {
+                end,
+                position: self.read_position(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Decode an array of fixed size basic types at once, see
+    /// `write_fixed_array`.
+    pub fn read_fixed_array<U: FixedSize>(&mut self) -> Result<Vec<U>> {
+        let size = mem::size_of::<U>();
+        let end = self.begin_read_array(size as u8)?;
+        let length = end - self.read_position();
+        if length % size > 0 {
+            return Err(Error::ArrayElementSizeMismatch { length, size });
+        }
+
+        // Unless the input length is known, the values are read in chunks so
+        // a length prefix alone can't make it allocate.
+        let count = length / size;
+        let chunk_length = match self.context.input_end {
+            Some(_) => count,
+            None => fixed::CHUNK_LENGTH,
+        };
+
+        let mut values = Vec::new();
+        while values.len() < count {
+            let start = values.len();
+            values.resize(start + chunk_length.min(count - start), U::default());
+            self.inner.read_exact(fixed::as_bytes_mut(&mut values[start..]))?;
+        }
+
+        if self.endianness() != Endianness::native() {
+            for value in values.iter_mut() {
+                *value = value.swap_bytes();
+            }
+        }
+
+        self.end_read_array(end)?;
+        Ok(values)
+    }
+
+    /// Decode a struct, aligned to 8 bytes, whose fields are read by `f`.
+    pub fn read_struct<F, U>(&mut self, f: F) -> Result<U>
+    where
+        F: FnOnce(&mut Self) -> Result<U>,
+    {
+        self.read_padding(8)?;
+
+        self.context.enter_struct()?;
+        let res = f(self);
+        self.context.struct_depth -= 1;
+
+        res
+    }
+
+    /// Decode the value of a variant with `f`, checking the nesting depth.
+    pub fn read_variant<F, U>(&mut self, f: F) -> Result<U>
+    where
+        F: FnOnce(&mut Self) -> Result<U>,
+    {
+        let offset = self.read_position();
+        self.context.enter_variant()?;
+        let res = f(self);
+        self.context.variant_depth -= 1;
+
+        res.map_err(|err| err.in_path("<variant>", offset))
+    }
+}
+
+impl<'de> Marshaller<&'de [u8]> {
+    /// Create a marshaller reading from a slice, whose length is used to
+    /// reject length prefixes going past its end.
+    pub fn from_slice(data: &'de [u8], endianness: Endianness) -> Marshaller<&'de [u8]> {
+        let mut marshaller = Marshaller::new(data, endianness);
+        marshaller.context.input_end = Some(data.len());
+        marshaller
+    }
+
+    /// Borrow the next `length` bytes of the input.
+    pub fn read_slice(&mut self, length: usize) -> Result<&'de [u8]> {
+        Ok(self.inner.read_slice(length)?)
+    }
+
+    /// Borrow a string of `length` bytes followed by its NUL terminator, it
+    /// must be valid UTF-8 even in lenient mode.
+    pub fn read_str(&mut self, length: usize) -> Result<&'de str> {
+        let position = self.read_position();
+        let data = self.read_slice(length)?;
+        let terminator = self.read_slice(1)?[0];
+
+        if self.is_strict() {
+            check_string(data, terminator, position)?;
+        }
+
+        Ok(str::from_utf8(data)?)
+    }
+
+    /// Decode a value borrowing from the input, after skipping the padding
+    /// up to its alignment.
+    pub fn read_borrowed<U: DBusBorrowType<'de>>(&mut self) -> Result<U> {
+        let start = self.read_position();
+        self.read_padding(U::borrow_alignment())
+            .map_err(|err| err.in_value(&U::borrow_signature(), start))?;
+
+        let offset = self.read_position();
+        U::decode_borrowed(self).map_err(|err| err.in_value(&U::borrow_signature(), offset))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{
+        BasicType, DBusType, SignatureType, Variant, MAX_ARRAY_DEPTH, MAX_SIGNATURE_LENGTH, MAX_STRUCT_DEPTH,
+    };
+    use std::convert::TryFrom;
+
+    fn encode_result<T: DBusType>(value: &T) -> Result<Vec<u8>> {
+        let mut marshaller = Marshaller::new(Vec::new(), Endianness::Little);
+        marshaller.write_value(value)?;
+        Ok(marshaller.into_inner())
+    }
+
+    fn encode<T: DBusType>(value: &T) -> Vec<u8> {
+        let mut marshaller = Marshaller::new(Vec::new(), Endianness::Little);
+        marshaller.write_value(value).unwrap();
+        marshaller.into_inner()
+    }
+
+    fn decode<T: DBusType>(bytes: &[u8]) -> T {
+        let mut marshaller = Marshaller::new(bytes, Endianness::Little);
+        let value = marshaller.read_value().unwrap();
+        assert!(marshaller.is_empty());
+        value
+    }
+
+    #[test]
+    fn test_array_of_u64() {
+        let bytes: &[u8] = &[
+            8, 0, 0, 0, // Array size in bytes = 8
+            0, 0, 0, 0, // Padding to the first element
+            2, 0, 0, 0, 0, 0, 0, 0, // First element
+        ];
+
+        assert_eq!(encode(&vec![2u64]), bytes);
+        assert_eq!(decode::<Vec<u64>>(bytes), vec![2u64]);
+    }
+
+    #[test]
+    fn test_array_of_variants_unaligned() {
+        let value = (7u32, 8u32, vec![Variant::new(5u64)]);
+        let bytes: &[u8] = &[
+            7, 0, 0, 0, // First u32
+            8, 0, 0, 0, // Second u32
+            12, 0, 0, 0, // Array size in bytes = 12
+            1, b't', 0, // Variant signature
+            0, // Padding to the absolute 8-bytes boundary
+            5, 0, 0, 0, 0, 0, 0, 0, // Variant value
+        ];
+
+        assert_eq!(encode(&value), bytes);
+        assert_eq!(decode::<(u32, u32, Vec<Variant>)>(bytes), value);
+    }
+
+    #[test]
+    fn test_packed_fields_alignment() {
+        let value = (1u8, 2u32, 3u16, 4u64);
+        let bytes: &[u8] = &[
+            1, 0, 0, 0, // Byte + padding
+            2, 0, 0, 0, // u32
+            3, 0, 0, 0, 0, 0, 0, 0, // u16 + padding
+            4, 0, 0, 0, 0, 0, 0, 0, // u64
+        ];
+
+        assert_eq!(encode(&value), bytes);
+        assert_eq!(decode::<(u8, u32, u16, u64)>(bytes), value);
+    }
+
+    fn nest<F: Fn(Value) -> Value>(depth: usize, wrap: F) -> Value {
+        (0..depth).fold(Value::Byte(0), |value, _| wrap(value))
+    }
+
+    fn decode_value(value: &Value) -> Result<Value> {
+        let mut marshaller = Marshaller::new(Vec::new(), Endianness::Little);
+        value.encode(&mut marshaller).unwrap();
+        let data = marshaller.into_inner();
+
+        Value::decode(&mut Marshaller::from_slice(&data, Endianness::Little), &value.signature_type())
+    }
+
+    #[test]
+    fn test_length_limits() {
+        let bytes: &[u8] = &[0x04, 0, 0, 0x01, 0, 0, 0, 0];
+        let mut marshaller = Marshaller::new(bytes, Endianness::Big);
+        assert_err_matches!(marshaller.read_value::<Vec<u8>>(), Error::ArrayTooLong { .. });
+
+        let bytes: &[u8] = &[0xff, 0xff, 0xff, 0xf0, b'a', 0];
+        let mut marshaller = Marshaller::new(bytes, Endianness::Big);
+        assert_err_matches!(marshaller.read_value::<String>(), Error::MessageTooLong { .. });
+
+        let bytes: &[u8] = &[0, 0, 0x10, 0, b'a', 0];
+        let mut marshaller = Marshaller::from_slice(bytes, Endianness::Big);
+        assert_err_matches!(
+            marshaller.read_value::<String>(),
+            Error::LengthOverflow {
+                length: 0x1000,
+                remaining: 2
+            }
+        );
+
+        let bytes: &[u8] = &[6, 0, 0, 0, 2, 0, 0, 0, b'a', b'b', 0];
+        let mut marshaller = Marshaller::new(bytes, Endianness::Little);
+        assert_err_matches!(
+            marshaller.read_value::<Vec<String>>(),
+            Error::ArrayLengthMismatch { end: 10, position: 11 }
+        );
+
+        let ty = SignatureType::Array(Box::new(SignatureType::Struct(vec![
+            SignatureType::Basic(BasicType::Byte);
+            MAX_SIGNATURE_LENGTH
+        ])));
+        assert_err!(Signature::try_from(ty.clone()));
+        assert_err_matches!(
+            encode_result(&Variant(Value::Array { ty, values: vec![] })),
+            Error::Signature { .. }
+        );
+    }
+
+    #[test]
+    fn test_nesting_limits() {
+        let array = |value: Value| Value::Array {
+            ty: value.signature_type(),
+            values: vec![value],
+        };
+        assert_ok!(decode_value(&nest(MAX_ARRAY_DEPTH, array)));
+        assert_err_matches!(decode_value(&nest(MAX_ARRAY_DEPTH + 1, array)), Error::ArrayTooDeep);
+
+        let structure = |value: Value| Value::Struct(vec![value]);
+        assert_ok!(decode_value(&nest(MAX_STRUCT_DEPTH, structure)));
+        assert_err_matches!(decode_value(&nest(MAX_STRUCT_DEPTH + 1, structure)), Error::StructTooDeep);
+
+        // Dict entries count as structs too.
+        let dict = |value: Value| Value::Dict {
+            key: BasicType::String,
+            value: SignatureType::Struct(vec![value.signature_type()]),
+            entries: vec![(Value::from("a"), Value::Struct(vec![value]))],
+        };
+        assert_ok!(decode_value(&nest(MAX_STRUCT_DEPTH / 2, dict)));
+        assert_err_matches!(decode_value(&nest(MAX_STRUCT_DEPTH / 2 + 1, dict)), Error::StructTooDeep);
+
+        let variant = |value: Value| Value::Variant(Box::new(value));
+        assert_ok!(decode_value(&nest(MAX_TOTAL_DEPTH, variant)));
+        assert_err_matches!(decode_value(&nest(MAX_TOTAL_DEPTH + 1, variant)), Error::NestingTooDeep);
+    }
+
+    #[test]
+    fn test_strict_validation() {
+        let mut marshaller = Marshaller::new(&[1u8, 0, 1, 0, 2, 0, 0, 0][..], Endianness::Little);
+        assert_err_matches!(
+            marshaller.read_value::<(u8, u32)>(),
+            Error::NonZeroPadding { position: 2 }
+        );
+
+        let mut marshaller = Marshaller::new(&[2u8, 0, 0, 0][..], Endianness::Little);
+        assert_err_matches!(marshaller.read_value::<bool>(), Error::InvalidBoolean { value: 2 });
+
+        let mut marshaller = Marshaller::new(&[2u8, 0, 0, 0, b'a', b'b', b'c'][..], Endianness::Little);
+        assert_err_matches!(
+            marshaller.read_value::<String>(),
+            Error::MissingNulTerminator { position: 6 }
+        );
+
+        let mut marshaller = Marshaller::new(&[2u8, 0, 0, 0, b'a', 0, 0][..], Endianness::Little);
+        assert_err_matches!(marshaller.read_value::<String>(), Error::InteriorNul { position: 5 });
+
+        let mut marshaller = Marshaller::new(&[2u8, 0, 0, 0, 0xc3, 0x28, 0][..], Endianness::Little);
+        assert_err_matches!(marshaller.read_value::<String>(), Error::FromUtf8 { .. });
+    }
+
+    #[test]
+    fn test_lenient_validation() {
+        let bytes: &[u8] = &[
+            1, 0xff, 0, 0, // Byte + non-zero padding
+            2, 0, 0, 0, // Non canonical boolean
+            2, 0, 0, 0, 0xc3, 0x28, b'x', // Invalid UTF-8 without terminator
+        ];
+
+        let mut marshaller = Marshaller::new(bytes, Endianness::Little);
+        marshaller.set_validation(Validation::Lenient);
+        let value = marshaller.read_value::<(u8, bool, String)>().unwrap();
+        assert_eq!(value, (1, true, "\u{fffd}(".to_string()));
+        assert!(marshaller.is_empty());
+    }
+
+    #[test]
+    fn test_unix_fds() {
+        let file = UnixFd::new(std::fs::File::open("/dev/null").unwrap());
+        let other = UnixFd::new(std::fs::File::open("/dev/null").unwrap());
+        let value = (file.clone(), vec![other.clone(), file.clone()]);
+
+        let mut marshaller = Marshaller::new(Vec::new(), Endianness::Little);
+        marshaller.write_value(&value).unwrap();
+        let fds = marshaller.take_fds();
+        let data = marshaller.into_inner();
+        assert_eq!(fds, vec![file.clone(), other.clone()]);
+        assert_eq!(data, &[0, 0, 0, 0, 8, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0]);
+
+        let mut marshaller = Marshaller::new(data.as_slice(), Endianness::Little);
+        marshaller.set_fds(fds);
+        assert_eq!(marshaller.read_value::<(UnixFd, Vec<UnixFd>)>().unwrap(), value);
+
+        let mut marshaller = Marshaller::new(data.as_slice(), Endianness::Little);
+        assert_err_matches!(
+            marshaller.read_value::<(UnixFd, Vec<UnixFd>)>(),
+            Error::InvalidUnixFd { index: 0, count: 0 }
+        );
+    }
+
+    #[test]
+    fn test_struct_fields_alignment() {
+        #[derive(Debug, PartialEq, DBusType)]
+        #[dbus(module = "crate")]
+        struct Entry(u8, u32);
+
+        let value = (1u8, Entry(2, 3));
+        let bytes: &[u8] = &[
+            1, 0, 0, 0, 0, 0, 0, 0, // Byte + padding to the struct
+            2, 0, 0, 0, // Struct byte + padding
+            3, 0, 0, 0, // Struct u32
+        ];
+
+        assert_eq!(encode(&value), bytes);
+        assert_eq!(decode::<(u8, Entry)>(bytes), value);
     }
 }
@@ -1,5 +1,4 @@
 use super::*;
-use crate::{error::*, types::*};
 use byteordered::Endianness;
 use std::num::NonZeroU32;
 
@@ -8,7 +7,7 @@ pub struct MessageBuilder<T: DBusType> {
     ty: MessageType,
     flags: Flags,
     version: u8,
-    serial: NonZeroU32,
+    serial: Option<NonZeroU32>,
     fields: Vec<HeaderField>,
     data: T,
 }
@@ -20,31 +19,40 @@ impl<T: DBusType> MessageBuilder<T> {
             ty: MessageType::Invalid,
             flags: Flags::empty(),
             version: 1,
-            serial: rand::random(),
+            serial: None,
             fields: Vec::new(),
             data,
         }
     }
 
+    pub fn endianness(&mut self, endianness: Endianness) {
+        self.endianness = endianness;
+    }
+
     pub fn message_type(&mut self, ty: MessageType) {
         self.ty = ty;
     }
 
-    pub fn add_field(&mut self, field: HeaderField) {
-        self.fields.push(field);
+    pub fn flags(&mut self, flags: Flags) {
+        self.flags = flags;
     }
 
-    pub fn add_signature(&mut self) {
-        let signature = Signature::new(T::signature()).unwrap();
-        self.add_field(HeaderField::Signature(signature));
+    pub fn add_field(&mut self, field: HeaderField) {
+        self.fields.push(field);
     }
 
-    pub fn serial(&mut self) -> u32 {
-        self.serial.get()
+    /// Use `serial` instead of one taken from the allocator given to `build`.
+    pub fn serial(&mut self, serial: NonZeroU32) {
+        self.serial = Some(serial);
     }
 
-    pub fn build(self) -> Result<Message<T>> {
-        let body = self.encode_data()?;
+    /// Build the message, taking its serial from `serials` unless one was
+    /// set. The body size, signature and fd count fields come from the body
+    /// and replace the ones added by hand.
+    ///
+    /// Fails if the header lacks the fields required by the message type.
+    pub fn build(self, serials: &SerialAllocator) -> Result<Message<T>> {
+        let (body, fds) = self.encode_data()?;
         let body_size = body.len();
 
         let header = MessageHeader {
@@ -53,9 +61,10 @@ impl<T: DBusType> MessageBuilder<T> {
             flags: self.flags,
             version: self.version,
             body_size: body_size as u32,
-            serial: self.serial.get(),
-            fields: self.fields,
+            serial: self.serial.unwrap_or_else(|| serials.next()).get(),
+            fields: with_body_fields(&self.fields, T::signature(), fds)?,
         };
+        header.check_fields()?;
 
         Ok(Message {
             header,
@@ -63,11 +72,13 @@ impl<T: DBusType> MessageBuilder<T> {
         })
     }
 
-    fn encode_data(&self) -> Result<Vec<u8>> {
+    /// Encode the body, returning it along with the number of fds it refers to.
+    fn encode_data(&self) -> Result<(Vec<u8>, usize)> {
         use crate::marshal::Marshaller;
 
-        let mut marshaller = Marshaller::new_native(Vec::new());
-        self.data.encode(&mut marshaller)?;
-        Ok(marshaller.into_inner())
+        let mut marshaller = Marshaller::new(Vec::new(), self.endianness);
+        marshaller.write_value(&self.data)?;
+        let fds = marshaller.take_fds().len();
+        Ok((marshaller.into_inner(), fds))
     }
 }
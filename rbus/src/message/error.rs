@@ -0,0 +1,241 @@
+use super::{types::ErrorName, Message, MessageBuilder, MessageHeader, MessageType, RawMessage};
+use crate::{types::Value, Error, Result};
+pub use rbus_derive::DBusError;
+use std::fmt;
+
+/// An error returned by a method, as carried by an error reply: its name and
+/// a message, which is the first argument of the reply when it's a string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MethodError {
+    name: ErrorName,
+    message: String,
+}
+
+impl MethodError {
+    pub fn new<S: Into<String>>(name: ErrorName, message: S) -> MethodError {
+        MethodError {
+            name,
+            message: message.into(),
+        }
+    }
+
+    pub fn name(&self) -> &ErrorName {
+        &self.name
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Read the error carried by the error reply `message`.
+    pub fn from_message(message: &RawMessage) -> Result<MethodError> {
+        let header = message.header();
+        let name = match (header.ty, header.error_name()) {
+            (MessageType::Error, Some(name)) => name.clone(),
+            _ => {
+                return Err(Error::Custom {
+                    message: "Not an error reply".into(),
+                });
+            }
+        };
+
+        let message = if message.signature().starts_with('s') {
+            match message.body_values()?.into_iter().next() {
+                Some(Value::String(message)) => message,
+                _ => String::new(),
+            }
+        } else {
+            String::new()
+        };
+
+        Ok(MethodError { name, message })
+    }
+
+    /// Convert the error to `E`, if `E` has a variant with the same name.
+    pub fn to_error<E: DBusError>(&self) -> Option<E> {
+        E::from_method_error(self)
+    }
+
+    /// Start the error reply to `call`.
+    pub fn reply<C: AsRef<MessageHeader>>(&self, call: &C) -> MessageBuilder<String> {
+        Message::error(call, self.name.clone(), self.message.clone())
+    }
+}
+
+impl fmt::Display for MethodError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name: &str = self.name.as_ref();
+        if self.message.is_empty() {
+            write!(f, "{}", name)
+        } else {
+            write!(f, "{}: {}", name, self.message)
+        }
+    }
+}
+
+impl std::error::Error for MethodError {}
+
+/// An error which can be sent and received as a D-Bus error reply.
+///
+/// It can be derived for enums whose variants are unit variants or hold the
+/// error message. Variants are named after the `interface` given to the
+/// enum, or by their own `name`.
+pub trait DBusError: Sized {
+    fn to_method_error(&self) -> MethodError;
+
+    /// Return the error with the name of `error`, if any.
+    fn from_method_error(error: &MethodError) -> Option<Self>;
+
+    /// Start the error reply to `call`.
+    fn reply<C: AsRef<MessageHeader>>(&self, call: &C) -> MessageBuilder<String> {
+        self.to_method_error().reply(call)
+    }
+}
+
+impl DBusError for MethodError {
+    fn to_method_error(&self) -> MethodError {
+        self.clone()
+    }
+
+    fn from_method_error(error: &MethodError) -> Option<MethodError> {
+        Some(error.clone())
+    }
+}
+
+/// The errors of the `org.freedesktop.DBus.Error` interface.
+#[derive(Debug, Clone, PartialEq, Eq, DBusError)]
+#[dbus(module = "crate", interface = "org.freedesktop.DBus.Error")]
+pub enum StandardError {
+    Failed(String),
+    NoMemory(String),
+    ServiceUnknown(String),
+    NameHasNoOwner(String),
+    NoReply(String),
+    IOError(String),
+    BadAddress(String),
+    NotSupported(String),
+    LimitsExceeded(String),
+    AccessDenied(String),
+    AuthFailed(String),
+    NoServer(String),
+    Timeout(String),
+    NoNetwork(String),
+    AddressInUse(String),
+    Disconnected(String),
+    InvalidArgs(String),
+    FileNotFound(String),
+    FileExists(String),
+    UnknownMethod(String),
+    UnknownObject(String),
+    UnknownInterface(String),
+    UnknownProperty(String),
+    PropertyReadOnly(String),
+    TimedOut(String),
+    MatchRuleNotFound(String),
+    MatchRuleInvalid(String),
+    #[dbus(name = "org.freedesktop.DBus.Error.Spawn.ExecFailed")]
+    SpawnExecFailed(String),
+    #[dbus(name = "org.freedesktop.DBus.Error.Spawn.ForkFailed")]
+    SpawnForkFailed(String),
+    #[dbus(name = "org.freedesktop.DBus.Error.Spawn.ChildExited")]
+    SpawnChildExited(String),
+    #[dbus(name = "org.freedesktop.DBus.Error.Spawn.ChildSignaled")]
+    SpawnChildSignaled(String),
+    #[dbus(name = "org.freedesktop.DBus.Error.Spawn.Failed")]
+    SpawnFailed(String),
+    #[dbus(name = "org.freedesktop.DBus.Error.Spawn.SetupFailed")]
+    SpawnSetupFailed(String),
+    #[dbus(name = "org.freedesktop.DBus.Error.Spawn.ConfigInvalid")]
+    SpawnConfigInvalid(String),
+    #[dbus(name = "org.freedesktop.DBus.Error.Spawn.ServiceNotValid")]
+    SpawnServiceNotValid(String),
+    #[dbus(name = "org.freedesktop.DBus.Error.Spawn.ServiceNotFound")]
+    SpawnServiceNotFound(String),
+    #[dbus(name = "org.freedesktop.DBus.Error.Spawn.PermissionsInvalid")]
+    SpawnPermissionsInvalid(String),
+    #[dbus(name = "org.freedesktop.DBus.Error.Spawn.FileInvalid")]
+    SpawnFileInvalid(String),
+    #[dbus(name = "org.freedesktop.DBus.Error.Spawn.NoMemory")]
+    SpawnNoMemory(String),
+    UnixProcessIdUnknown(String),
+    InvalidSignature(String),
+    InvalidFileContent(String),
+    SELinuxSecurityContextUnknown(String),
+    AdtAuditDataUnknown(String),
+    ObjectPathInUse(String),
+    InconsistentMessage(String),
+    InteractiveAuthorizationRequired(String),
+    NotContainer(String),
+}
+
+impl From<StandardError> for MethodError {
+    fn from(error: StandardError) -> MethodError {
+        error.to_method_error()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        message::{types::Member, SerialAllocator},
+        types::ObjectPath,
+    };
+
+    #[derive(Debug, PartialEq, DBusError)]
+    #[dbus(module = "crate", interface = "org.example.Error")]
+    enum ExampleError {
+        NotFound(String),
+        #[dbus(name = "org.example.OtherError.Busy")]
+        Busy,
+    }
+
+    #[test]
+    fn test_derive_dbus_error() {
+        let error = ExampleError::NotFound("Nothing here".into()).to_method_error();
+        assert_eq!(error.name().as_ref(), "org.example.Error.NotFound");
+        assert_eq!(error.message(), "Nothing here");
+        assert_eq!(error.to_error(), Some(ExampleError::NotFound("Nothing here".into())));
+        assert_eq!(error.to_error::<StandardError>(), None);
+
+        let error = ExampleError::Busy.to_method_error();
+        assert_eq!(error.name().as_ref(), "org.example.OtherError.Busy");
+        assert_eq!(error.to_error(), Some(ExampleError::Busy));
+
+        let error = StandardError::SpawnExecFailed("No such file".into()).to_method_error();
+        assert_eq!(error.name().as_ref(), "org.freedesktop.DBus.Error.Spawn.ExecFailed");
+        assert_eq!(
+            error.to_string(),
+            "org.freedesktop.DBus.Error.Spawn.ExecFailed: No such file"
+        );
+    }
+
+    #[test]
+    fn test_method_error_reply() {
+        let serials = SerialAllocator::new();
+        let call = Message::method_call(
+            None,
+            ObjectPath::new("/").unwrap(),
+            None,
+            Member::new("Frobnicate").unwrap(),
+            (),
+        )
+        .build(&serials)
+        .unwrap();
+
+        let reply = StandardError::UnknownMethod("No Frobnicate method".into())
+            .reply(&call)
+            .build(&serials)
+            .unwrap();
+        assert_eq!(reply.header().reply_serial(), Some(call.header().serial));
+
+        let raw = RawMessage::from_message(&reply).unwrap();
+        let error = MethodError::from_message(&raw).unwrap();
+        assert_eq!(
+            error.to_error(),
+            Some(StandardError::UnknownMethod("No Frobnicate method".into()))
+        );
+
+        assert_err!(MethodError::from_message(&RawMessage::from_message(&call).unwrap()));
+    }
+}
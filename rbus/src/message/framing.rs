@@ -0,0 +1,179 @@
+use crate::{
+    marshal::{MAX_ARRAY_LENGTH, MAX_MESSAGE_LENGTH},
+    Error, Result,
+};
+use std::convert::TryInto;
+
+/// The length of the fixed part of a message header, up to and including
+/// the length of the header fields array.
+pub const FIXED_HEADER_LENGTH: usize = 16;
+
+/// The progress of a `FrameReader`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Frame {
+    /// At least `needed` more bytes are required to go further.
+    Incomplete { needed: usize },
+    /// A whole message, header and body.
+    Complete(Vec<u8>),
+}
+
+/// Return the total length of the message starting with the fixed header
+/// `header`.
+///
+/// Only the endianness, the header fields length and the body size are
+/// read, the rest of the message is left for the decoder to check.
+pub fn frame_length(header: &[u8; FIXED_HEADER_LENGTH]) -> Result<usize> {
+    let read_u32 = |offset: usize| {
+        let bytes = header[offset..offset + 4].try_into().expect("The fixed header has 16 bytes");
+        match header[0] {
+            b'l' => Ok(u32::from_le_bytes(bytes) as usize),
+            b'B' => Ok(u32::from_be_bytes(bytes) as usize),
+            value => Err(Error::InvalidEndianness { value }),
+        }
+    };
+
+    let body_size = read_u32(4)?;
+    let fields_length = read_u32(12)?;
+    if fields_length > MAX_ARRAY_LENGTH {
+        return Err(Error::ArrayTooLong { length: fields_length });
+    }
+
+    // The body starts on an 8-byte boundary after the header fields.
+    let header_length = (FIXED_HEADER_LENGTH + fields_length + 7) & !7;
+    let length = header_length + body_size;
+    if length > MAX_MESSAGE_LENGTH {
+        return Err(Error::MessageTooLong { length });
+    }
+
+    Ok(length)
+}
+
+/// Split a stream of bytes into messages, for transports which receive
+/// data in arbitrary chunks: received bytes are given to `feed` and
+/// `next_frame` is called until it returns `Frame::Incomplete`.
+#[derive(Debug, Default)]
+pub struct FrameReader {
+    buffer: Vec<u8>,
+    /// The length of the message at the start of the buffer, once known.
+    length: Option<usize>,
+}
+
+impl FrameReader {
+    pub fn new() -> FrameReader {
+        FrameReader::default()
+    }
+
+    /// Append received bytes.
+    pub fn feed(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// Return the bytes received which aren't part of a complete message yet.
+    pub fn buffered(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    /// Return the number of bytes still needed to complete the next frame,
+    /// or to read its length.
+    pub fn needed(&self) -> usize {
+        let length = self.length.unwrap_or(FIXED_HEADER_LENGTH);
+        length.saturating_sub(self.buffer.len())
+    }
+
+    /// Return the next complete message, or how many more bytes are needed.
+    ///
+    /// Errors mean the stream is corrupt, since message boundaries can't be
+    /// found anymore.
+    pub fn next_frame(&mut self) -> Result<Frame> {
+        let length = match self.length {
+            Some(length) => length,
+            None if self.buffer.len() < FIXED_HEADER_LENGTH => {
+                return Ok(Frame::Incomplete { needed: self.needed() });
+            }
+            None => {
+                let header = self.buffer[..FIXED_HEADER_LENGTH].try_into().expect("The header is buffered");
+                let length = frame_length(header)?;
+                self.length = Some(length);
+                length
+            }
+        };
+
+        if self.buffer.len() < length {
+            return Ok(Frame::Incomplete { needed: self.needed() });
+        }
+
+        let rest = self.buffer.split_off(length);
+        let frame = std::mem::replace(&mut self.buffer, rest);
+        self.length = None;
+
+        Ok(Frame::Complete(frame))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MESSAGE: &[u8] = &[
+        b'B', 2, 0, 1, // Endianness, type, flags, version
+        0, 0, 0, 4, // Body size
+        0, 0, 0, 1, // Serial
+        0, 0, 0, 0xf, // Header fields length
+        5, 1, b'u', 0, 0xab, 0xcd, 0xef, 0x12, // ReplySerial
+        8, 1, b'g', 0, 1, b'u', 0, // Signature
+        0, // Padding to the body
+        0, 0, 0, 42, // Body
+    ];
+
+    #[test]
+    fn test_frame_length() {
+        let mut header: [u8; FIXED_HEADER_LENGTH] = MESSAGE[..FIXED_HEADER_LENGTH].try_into().unwrap();
+        assert_eq!(frame_length(&header).unwrap(), MESSAGE.len());
+
+        header[0] = b'x';
+        assert_err_matches!(frame_length(&header), Error::InvalidEndianness { value: b'x' });
+
+        header[0] = b'l';
+        header[12..16].copy_from_slice(&[0, 0, 0, 0x10]);
+        assert_err_matches!(frame_length(&header), Error::ArrayTooLong { .. });
+    }
+
+    #[test]
+    fn test_frame_reader_partial() {
+        let mut reader = FrameReader::new();
+        assert_eq!(reader.next_frame().unwrap(), Frame::Incomplete { needed: 16 });
+
+        for (index, byte) in MESSAGE[..MESSAGE.len() - 1].iter().enumerate() {
+            reader.feed(&[*byte]);
+            let needed = if index + 1 < FIXED_HEADER_LENGTH {
+                FIXED_HEADER_LENGTH - index - 1
+            } else {
+                MESSAGE.len() - index - 1
+            };
+            assert_eq!(reader.next_frame().unwrap(), Frame::Incomplete { needed });
+        }
+
+        reader.feed(&MESSAGE[MESSAGE.len() - 1..]);
+        assert_eq!(reader.next_frame().unwrap(), Frame::Complete(MESSAGE.to_vec()));
+        assert!(reader.buffered().is_empty());
+    }
+
+    #[test]
+    fn test_frame_reader_multiple() {
+        let mut data = MESSAGE.to_vec();
+        data.extend_from_slice(MESSAGE);
+        data.extend_from_slice(&MESSAGE[..20]);
+
+        let mut reader = FrameReader::new();
+        reader.feed(&data);
+        assert_eq!(reader.next_frame().unwrap(), Frame::Complete(MESSAGE.to_vec()));
+        assert_eq!(reader.next_frame().unwrap(), Frame::Complete(MESSAGE.to_vec()));
+        assert_eq!(
+            reader.next_frame().unwrap(),
+            Frame::Incomplete {
+                needed: MESSAGE.len() - 20
+            }
+        );
+        assert_eq!(reader.buffered(), &MESSAGE[..20]);
+    }
+}
@@ -1,10 +1,12 @@
 use super::types::*;
 use crate::{
     marshal::Marshaller,
-    types::{impl_type, DBusType, ObjectPath, Signature},
+    types::{impl_type, DBusType, ObjectPath, Signature, SignatureType, Value, Variant},
+    Error, Result,
 };
 use bitflags::bitflags;
 use byteordered::Endianness;
+use std::io;
 
 impl_type! {
     #[dbus(proxy(u8))]
@@ -18,8 +20,6 @@ impl_type! {
         }
 
         decode(marshaller) {
-            use crate::Error;
-
             let value = marshaller.io().read_u8()?;
             match value {
                 b'l' => Ok(Endianness::Little),
@@ -30,11 +30,6 @@ impl_type! {
     }
 }
 
-#[allow(clippy::trivially_copy_pass_by_ref)]
-fn mutate_marshaller<T>(endianness: &Endianness, marshaller: &mut Marshaller<T>) {
-    marshaller.endianness = *endianness;
-}
-
 #[derive(Debug, Clone, Copy, PartialEq, Eq, DBusType)]
 #[dbus(module = "crate")]
 #[repr(u8)]
@@ -57,11 +52,10 @@ bitflags! {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, DBusType)]
-#[dbus(module = "crate", index(u8))]
+/// A message header field, encoded as its code and a variant holding its
+/// value.
+#[derive(Debug, Clone, PartialEq)]
 pub enum HeaderField {
-    #[doc(hidden)]
-    Invalid,
     Path(ObjectPath),
     Interface(Interface),
     Member(Member),
@@ -71,12 +65,99 @@ pub enum HeaderField {
     Sender(String),
     Signature(Signature),
     UnixFds(u32),
+    /// A field this implementation doesn't know about, kept so it can be
+    /// ignored or forwarded as is.
+    Unknown {
+        code: u8,
+        value: Variant,
+    },
+}
+
+/// Encode the value of a known field, along with its signature.
+fn write_field<U, Inner>(marshaller: &mut Marshaller<Inner>, value: &U) -> Result<()>
+where
+    U: DBusType,
+    Inner: io::Write,
+{
+    marshaller.write_value(&Signature::new(U::signature())?)?;
+    marshaller.write_value(value)
+}
+
+/// Decode the value of a known field, whose signature must match its type.
+fn read_field<U, Inner>(marshaller: &mut Marshaller<Inner>, signature: &Signature) -> Result<U>
+where
+    U: DBusType,
+    Inner: io::Read,
+{
+    let expected = U::signature();
+    if signature.as_str() != expected {
+        return Err(Error::SignatureMismatch {
+            expected,
+            found: signature.as_str().into(),
+        });
+    }
+
+    marshaller.read_variant(|marshaller| marshaller.read_value())
+}
+
+impl_type! {
+    #[dbus(align = 8, module = crate)]
+    HeaderField: 'r' {
+        signature() {
+            "(yv)".into()
+        }
+
+        encode(marshaller) {
+            marshaller.write_struct(|marshaller| {
+                marshaller.write_value(&self.code())?;
+                match self {
+                    HeaderField::Path(value) => write_field(marshaller, value),
+                    HeaderField::Interface(value) => write_field(marshaller, value),
+                    HeaderField::Member(value) => write_field(marshaller, value),
+                    HeaderField::ErrorName(value) => write_field(marshaller, value),
+                    HeaderField::ReplySerial(value) => write_field(marshaller, value),
+                    HeaderField::Destination(value) => write_field(marshaller, value),
+                    HeaderField::Sender(value) => write_field(marshaller, value),
+                    HeaderField::Signature(value) => write_field(marshaller, value),
+                    HeaderField::UnixFds(value) => write_field(marshaller, value),
+                    HeaderField::Unknown { value, .. } => marshaller.write_value(value),
+                }
+            })
+        }
+
+        decode(marshaller) {
+            marshaller.read_struct(|marshaller| {
+                let code = marshaller.read_value::<u8>()?;
+                let signature = marshaller.read_value::<Signature>()?;
+                match code {
+                    0 => Err(Error::InvalidVariant { value: 0 }),
+                    1 => read_field(marshaller, &signature).map(HeaderField::Path),
+                    2 => read_field(marshaller, &signature).map(HeaderField::Interface),
+                    3 => read_field(marshaller, &signature).map(HeaderField::Member),
+                    4 => read_field(marshaller, &signature).map(HeaderField::ErrorName),
+                    5 => read_field(marshaller, &signature).map(HeaderField::ReplySerial),
+                    6 => read_field(marshaller, &signature).map(HeaderField::Destination),
+                    7 => read_field(marshaller, &signature).map(HeaderField::Sender),
+                    8 => read_field(marshaller, &signature).map(HeaderField::Signature),
+                    9 => read_field(marshaller, &signature).map(HeaderField::UnixFds),
+                    code => {
+                        let ty = SignatureType::parse(signature.as_str())?;
+                        let value = marshaller.read_variant(|marshaller| Value::decode(marshaller, &ty))?;
+                        Ok(HeaderField::Unknown {
+                            code,
+                            value: Variant(value),
+                        })
+                    }
+                }
+            })
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, DBusType)]
 #[dbus(packed, module = "crate", size(align = 8))]
 pub struct MessageHeader {
-    #[dbus(mutate_marshaller = "mutate_marshaller")]
+    #[dbus(endianness)]
     pub endianness: Endianness,
     pub ty: MessageType,
     pub flags: Flags,
@@ -86,10 +167,123 @@ pub struct MessageHeader {
     pub fields: Vec<HeaderField>,
 }
 
+impl AsRef<MessageHeader> for MessageHeader {
+    fn as_ref(&self) -> &MessageHeader {
+        self
+    }
+}
+
+impl HeaderField {
+    /// The code identifying the field in the header.
+    pub fn code(&self) -> u8 {
+        match self {
+            HeaderField::Path(_) => 1,
+            HeaderField::Interface(_) => 2,
+            HeaderField::Member(_) => 3,
+            HeaderField::ErrorName(_) => 4,
+            HeaderField::ReplySerial(_) => 5,
+            HeaderField::Destination(_) => 6,
+            HeaderField::Sender(_) => 7,
+            HeaderField::Signature(_) => 8,
+            HeaderField::UnixFds(_) => 9,
+            HeaderField::Unknown { code, .. } => *code,
+        }
+    }
+}
+
+/// The names of the header fields in the specification, by code.
+const FIELD_NAMES: [&str; 10] = [
+    "INVALID",
+    "PATH",
+    "INTERFACE",
+    "MEMBER",
+    "ERROR_NAME",
+    "REPLY_SERIAL",
+    "DESTINATION",
+    "SENDER",
+    "SIGNATURE",
+    "UNIX_FDS",
+];
+
+fn field_name(code: u8) -> String {
+    match FIELD_NAMES.get(code as usize) {
+        Some(name) => (*name).into(),
+        None => format!("unknown field {}", code),
+    }
+}
+
+macro_rules! field_accessors {
+    ($($(#[$attr:meta])* $name:ident: $variant:ident -> $ty:ty = |$value:ident| $get:expr;)*) => {
+        $(
+        $(#[$attr])*
+        pub fn $name(&self) -> Option<$ty> {
+            self.fields.iter().find_map(|field| match field {
+                HeaderField::$variant($value) => Some($get),
+                _ => None,
+            })
+        }
+        )*
+    };
+}
+
+impl MessageHeader {
+    field_accessors! {
+        /// The object the call is sent to or the signal emitted from.
+        path: Path -> &ObjectPath = |value| value;
+        interface: Interface -> &Interface = |value| value;
+        member: Member -> &Member = |value| value;
+        error_name: ErrorName -> &ErrorName = |value| value;
+        /// The serial of the message this one replies to.
+        reply_serial: ReplySerial -> u32 = |value| *value;
+        destination: Destination -> &str = |value| value.as_str();
+        sender: Sender -> &str = |value| value.as_str();
+        /// The body signature, missing if the body is empty.
+        body_signature: Signature -> &Signature = |value| value;
+        /// The number of fds sent along the message.
+        unix_fds: UnixFds -> u32 = |value| *value;
+    }
+
+    /// Return the field with code `code`.
+    pub fn field(&self, code: u8) -> Option<&HeaderField> {
+        self.fields.iter().find(|field| field.code() == code)
+    }
+
+    /// Check that the header fields are unique and that the ones required by
+    /// the message type are present.
+    pub fn check_fields(&self) -> Result<()> {
+        for (index, field) in self.fields.iter().enumerate() {
+            if self.fields[..index].iter().any(|other| other.code() == field.code()) {
+                return Err(Error::DuplicateHeaderField {
+                    field: field_name(field.code()),
+                });
+            }
+        }
+
+        let required: &[u8] = match self.ty {
+            MessageType::Invalid => return Err(Error::InvalidMessageType),
+            MessageType::MethodCall => &[1, 3],
+            MessageType::MethodReturn => &[5],
+            MessageType::Error => &[4, 5],
+            MessageType::Signal => &[1, 2, 3],
+        };
+
+        for &code in required {
+            if self.field(code).is_none() {
+                return Err(Error::MissingHeaderField {
+                    message_type: format!("{:?}", self.ty),
+                    field: field_name(code),
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::types::*;
+    use crate::{marshal::Marshaller, types::*};
 
     #[test]
     fn test_dbus_header_decode_be() {
@@ -132,4 +326,58 @@ mod tests {
         assert_eq!(header, value);
         assert_eq!(rest.len(), 0);
     }
+
+    #[test]
+    fn test_header_fields() {
+        let bytes: &[u8] = &[
+            0, 0, 0, 0x22, // Array size in bytes = 34
+            0, 0, 0, 0, // 8-bytes boundary
+            5, 1, b'u', 0, 0, 0, 0, 3, // ReplySerial
+            42, 1, b's', 0, 0, 0, 0, 2, b'o', b'k', 0, // Unknown field
+            0, 0, 0, 0, 0, // 8-bytes boundary
+            7, 1, b's', 0, 0, 0, 0, 1, b'x', 0, // Sender
+        ];
+
+        let mut marshaller = Marshaller::new(bytes, Endianness::Big);
+        let fields = Vec::<HeaderField>::decode(&mut marshaller).unwrap();
+        assert!(marshaller.is_empty());
+
+        let unknown = HeaderField::Unknown {
+            code: 42,
+            value: Variant::new("ok"),
+        };
+        assert_eq!(fields[1], unknown);
+
+        let mut header = MessageHeader {
+            endianness: Endianness::Big,
+            ty: MessageType::MethodReturn,
+            flags: Flags::empty(),
+            version: 1,
+            body_size: 0,
+            serial: 1,
+            fields,
+        };
+        assert_eq!(header.reply_serial(), Some(3));
+        assert_eq!(header.sender(), Some("x"));
+        assert_eq!(header.member(), None);
+        assert_eq!(header.field(42), Some(&unknown));
+        header.check_fields().unwrap();
+
+        let mut marshaller = Marshaller::new(Vec::new(), Endianness::Big);
+        header.fields.encode(&mut marshaller).unwrap();
+        assert_eq!(marshaller.into_inner(), bytes);
+
+        header.fields.push(HeaderField::ReplySerial(4));
+        assert_err_matches!(header.check_fields(), Error::DuplicateHeaderField { .. });
+    }
+
+    #[test]
+    fn test_header_field_signature_mismatch() {
+        let bytes: &[u8] = &[
+            5, 1, b's', 0, 0, 0, 0, 1, b'x', 0, // ReplySerial holding a string
+        ];
+
+        let mut marshaller = Marshaller::new(bytes, Endianness::Big);
+        assert_err_matches!(HeaderField::decode(&mut marshaller), Error::SignatureMismatch { .. });
+    }
 }
@@ -1,13 +1,25 @@
-use crate::types::DBusType;
+use crate::{
+    marshal::{Endianness, Marshaller},
+    types::{impl_type, DBusType, ObjectPath, Signature},
+    Error, Result,
+};
 pub use builder::*;
+pub use error::*;
+pub use framing::*;
 pub use header::*;
+pub use raw::*;
+pub use serial::*;
+use types::{BusName, ErrorName, Interface, Member};
 
 mod builder;
+mod error;
+mod framing;
 mod header;
+mod raw;
+mod serial;
 pub mod types;
 
-#[derive(Debug, Clone, DBusType)]
-#[dbus(module = "crate")]
+#[derive(Debug, Clone)]
 pub struct Message<T: DBusType> {
     header: MessageHeader,
     data: T,
@@ -18,4 +30,431 @@ impl<T: DBusType> Message<T> {
     pub fn new(data: T) -> MessageBuilder<T> {
         MessageBuilder::new(data)
     }
+
+    /// Start a call of `member` on the object at `path`.
+    pub fn method_call(
+        destination: Option<BusName>,
+        path: ObjectPath,
+        interface: Option<Interface>,
+        member: Member,
+        data: T,
+    ) -> MessageBuilder<T> {
+        let mut builder = MessageBuilder::new(data);
+        builder.message_type(MessageType::MethodCall);
+        builder.add_field(HeaderField::Path(path));
+        if let Some(interface) = interface {
+            builder.add_field(HeaderField::Interface(interface));
+        }
+        builder.add_field(HeaderField::Member(member));
+        if let Some(destination) = destination {
+            builder.add_field(HeaderField::Destination(destination.as_ref().into()));
+        }
+        builder
+    }
+
+    /// Start the emission of the signal `member` from the object at `path`.
+    pub fn signal(path: ObjectPath, interface: Interface, member: Member, data: T) -> MessageBuilder<T> {
+        let mut builder = MessageBuilder::new(data);
+        builder.message_type(MessageType::Signal);
+        builder.add_field(HeaderField::Path(path));
+        builder.add_field(HeaderField::Interface(interface));
+        builder.add_field(HeaderField::Member(member));
+        builder
+    }
+
+    /// Start a reply to `call`, sent back to its sender.
+    pub fn method_return<C: AsRef<MessageHeader>>(call: &C, data: T) -> MessageBuilder<T> {
+        reply(call.as_ref(), MessageType::MethodReturn, data)
+    }
+}
+
+impl Message<String> {
+    /// Start an error reply to `call`, with `text` as its message.
+    pub fn error<C: AsRef<MessageHeader>>(
+        call: &C,
+        name: ErrorName,
+        text: impl Into<String>,
+    ) -> MessageBuilder<String> {
+        let mut builder = reply(call.as_ref(), MessageType::Error, text.into());
+        builder.add_field(HeaderField::ErrorName(name));
+        builder
+    }
+}
+
+impl<T: DBusType> Message<T> {
+    pub fn header(&self) -> &MessageHeader {
+        &self.header
+    }
+
+    pub fn data(&self) -> &T {
+        &self.data
+    }
+
+    pub fn into_data(self) -> T {
+        self.data
+    }
+
+    /// Serialize the message in its header endianness.
+    ///
+    /// Messages whose body refers to fds can't be serialized this way, a
+    /// `Marshaller` must be used to send the fds along.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut marshaller = Marshaller::new(Vec::new(), self.header.endianness);
+        self.encode(&mut marshaller)?;
+        if !marshaller.fds().is_empty() {
+            return Err(Error::UnsentFds {
+                count: marshaller.fds().len(),
+            });
+        }
+
+        Ok(marshaller.into_inner())
+    }
+
+    /// Deserialize a whole message, which must not refer to any fd.
+    pub fn from_bytes(data: &[u8]) -> Result<Message<T>> {
+        let mut marshaller = Marshaller::from_slice(data, Endianness::native());
+        let message = Message::decode(&mut marshaller)?;
+        if !marshaller.is_empty() {
+            return Err(Error::TrailingData {
+                position: marshaller.read_position(),
+            });
+        }
+
+        Ok(message)
+    }
+}
+
+impl<T: DBusType> AsRef<MessageHeader> for Message<T> {
+    fn as_ref(&self) -> &MessageHeader {
+        &self.header
+    }
+}
+
+/// Start a message of type `ty` replying to the message with header `call`.
+fn reply<T: DBusType>(call: &MessageHeader, ty: MessageType, data: T) -> MessageBuilder<T> {
+    let mut builder = MessageBuilder::new(data);
+    builder.message_type(ty);
+    builder.add_field(HeaderField::ReplySerial(call.serial));
+    if let Some(sender) = call.sender() {
+        builder.add_field(HeaderField::Destination(sender.into()));
+    }
+    builder
+}
+
+/// Return `fields` with the fields describing the body replaced by the ones
+/// of a body of signature `signature` referring to `fds` fds.
+fn with_body_fields(fields: &[HeaderField], signature: String, fds: usize) -> Result<Vec<HeaderField>> {
+    let mut fields = fields
+        .iter()
+        .filter(|field| !matches!(field, HeaderField::Signature(_) | HeaderField::UnixFds(_)))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    // An empty body has no signature field.
+    if !signature.is_empty() {
+        fields.push(HeaderField::Signature(Signature::new(signature)?));
+    }
+    if fds > 0 {
+        fields.push(HeaderField::UnixFds(fds as u32));
+    }
+
+    Ok(fields)
+}
+
+/// Return the body signature of `header`, which is empty if missing.
+fn body_signature(header: &MessageHeader) -> &str {
+    header.body_signature().map_or("", Signature::as_str)
+}
+
+fn check_signature(header: &MessageHeader, expected: String) -> Result<()> {
+    let found = body_signature(header);
+    if found != expected {
+        return Err(Error::SignatureMismatch {
+            expected,
+            found: found.into(),
+        });
+    }
+
+    Ok(())
+}
+
+// A message is its header, padded to 8 bytes, followed by its body. The
+// body size, signature and fd count in the header always come from the
+// body.
+impl_type! {
+    #[dbus(align = 8, module = crate)]
+    impl<T: DBusType> Message<T>: 'r' {
+        signature() {
+            format!("{}{}", MessageHeader::signature(), T::signature())
+        }
+
+        encode(marshaller) {
+            use std::io::Write;
+
+            // The body starts on an 8-byte boundary, so it has the same
+            // padding when encoded on its own. Its fd indexes refer to the
+            // fds sent along this message only, which are appended to the
+            // ones the marshaller already holds.
+            let mut body = Marshaller::new(Vec::new(), self.header.endianness);
+            body.write_value(&self.data)?;
+            let body_fds = body.take_fds();
+            let body = body.into_inner();
+
+            let mut header = self.header.clone();
+            header.body_size = body.len() as u32;
+            header.fields = with_body_fields(&header.fields, T::signature(), body_fds.len())?;
+
+            let mut fds = marshaller.take_fds();
+            fds.extend(body_fds);
+            marshaller.set_fds(fds);
+
+            header.encode(marshaller)?;
+            marshaller.write_padding(8)?;
+            marshaller.io().write_all(&body)?;
+            Ok(())
+        }
+
+        decode(marshaller) {
+            let header = marshaller.read_field::<MessageHeader>("header")?;
+            header.check_fields()?;
+            check_signature(&header, T::signature())?;
+            marshaller.read_padding(8)?;
+
+            let body_size = header.body_size as usize;
+            marshaller.check_length(body_size)?;
+            let end = marshaller.read_position() + body_size;
+
+            // Keep the body from being read past its size.
+            let input_end = marshaller.context().input_end;
+            marshaller.context_mut().input_end = Some(input_end.map_or(end, |input_end| input_end.min(end)));
+            let data = marshaller.read_field::<T>("body");
+            marshaller.context_mut().input_end = input_end;
+            let data = data?;
+
+            if marshaller.read_position() != end {
+                return Err(Error::BodySizeMismatch {
+                    body_size,
+                    position: marshaller.read_position(),
+                });
+            }
+
+            Ok(Message { header, data })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::UnixFd;
+    use std::fs::File;
+
+    #[test]
+    fn test_builder_counts_unix_fds() {
+        let serials = SerialAllocator::new();
+        let fd = UnixFd::new(File::open("/dev/null").unwrap());
+        let mut builder = Message::new((fd.clone(), fd));
+        builder.message_type(MessageType::MethodReturn);
+        builder.add_field(HeaderField::ReplySerial(1));
+        builder.add_field(HeaderField::UnixFds(5));
+        let message = builder.build(&serials).unwrap();
+        assert_eq!(
+            message.header.fields,
+            vec![
+                HeaderField::ReplySerial(1),
+                HeaderField::Signature(Signature::new("hh").unwrap()),
+                HeaderField::UnixFds(1)
+            ]
+        );
+
+        let mut builder = Message::new(());
+        builder.message_type(MessageType::MethodReturn);
+        builder.add_field(HeaderField::ReplySerial(1));
+        let message = builder.build(&serials).unwrap();
+        assert_eq!(message.header.fields, vec![HeaderField::ReplySerial(1)]);
+    }
+
+    #[test]
+    fn test_encode_counts_body_fds_only() {
+        let serials = SerialAllocator::new();
+        let fd = UnixFd::new(File::open("/dev/null").unwrap());
+        let mut builder = Message::new(fd.clone());
+        builder.message_type(MessageType::MethodReturn);
+        builder.add_field(HeaderField::ReplySerial(1));
+        let message = builder.build(&serials).unwrap();
+
+        let mut marshaller = Marshaller::new(Vec::new(), Endianness::Little);
+        marshaller.push_fd(&UnixFd::new(File::open("/dev/null").unwrap()));
+        message.encode(&mut marshaller).unwrap();
+        let mut fds = marshaller.take_fds();
+        assert_eq!(fds.len(), 2);
+
+        let data = marshaller.into_inner();
+        let mut marshaller = Marshaller::new(data.as_slice(), Endianness::Little);
+        marshaller.set_fds(fds.split_off(1));
+        let decoded = Message::<UnixFd>::decode(&mut marshaller).unwrap();
+        assert!(decoded.header.fields.contains(&HeaderField::UnixFds(1)));
+        assert_eq!(decoded.data, fd);
+
+        // The fds would be lost.
+        assert_err_matches!(message.to_bytes(), Error::UnsentFds { count: 1 });
+    }
+
+    #[test]
+    fn test_builder_required_fields() {
+        let serials = SerialAllocator::new();
+        assert_err_matches!(Message::new(()).build(&serials), Error::InvalidMessageType);
+
+        let mut builder = Message::new(());
+        builder.message_type(MessageType::MethodCall);
+        builder.add_field(HeaderField::Path(ObjectPath::new("/").unwrap()));
+        assert_err_matches!(builder.build(&serials), Error::MissingHeaderField { .. });
+
+        let mut builder = Message::new(());
+        builder.message_type(MessageType::Error);
+        builder.add_field(HeaderField::ReplySerial(1));
+        assert_err_matches!(builder.build(&serials), Error::MissingHeaderField { .. });
+    }
+
+    #[test]
+    fn test_message_constructors() {
+        let serials = SerialAllocator::new();
+        let call = Message::method_call(
+            Some(BusName::new("org.example.Service").unwrap()),
+            ObjectPath::new("/org/example").unwrap(),
+            None,
+            Member::new("Ping").unwrap(),
+            42u32,
+        )
+        .build(&serials)
+        .unwrap();
+        assert_eq!(call.header.ty, MessageType::MethodCall);
+        assert_eq!(call.header.serial, 1);
+        assert!(call.header.fields.contains(&HeaderField::Destination("org.example.Service".into())));
+
+        let mut call = call;
+        call.header.fields.push(HeaderField::Sender(":1.42".into()));
+
+        let reply = Message::method_return(&call, "pong").build(&serials).unwrap();
+        assert_eq!(reply.header.ty, MessageType::MethodReturn);
+        assert_eq!(reply.header.serial, 2);
+        assert_eq!(
+            reply.header.fields,
+            vec![
+                HeaderField::ReplySerial(call.header.serial),
+                HeaderField::Destination(":1.42".into()),
+                HeaderField::Signature(Signature::new("s").unwrap()),
+            ]
+        );
+
+        let error = Message::error(&call, ErrorName::new("org.example.Error").unwrap(), "Failed")
+            .build(&serials)
+            .unwrap();
+        assert_eq!(error.header.ty, MessageType::Error);
+        assert!(error
+            .header
+            .fields
+            .contains(&HeaderField::ErrorName(ErrorName::new("org.example.Error").unwrap())));
+        assert_eq!(error.data(), "Failed");
+
+        let signal = Message::signal(
+            ObjectPath::new("/org/example").unwrap(),
+            Interface::new("org.example.Signals").unwrap(),
+            Member::new("Changed").unwrap(),
+            (),
+        )
+        .build(&serials)
+        .unwrap();
+        assert_eq!(signal.header.ty, MessageType::Signal);
+        assert_eq!(signal.header.fields.len(), 3);
+    }
+
+    fn reply(endianness: Endianness) -> Message<(u32, String)> {
+        Message {
+            header: MessageHeader {
+                endianness,
+                ty: MessageType::MethodReturn,
+                flags: Flags::NO_AUTO_START,
+                version: 1,
+                body_size: 0,
+                serial: 0x12345678,
+                fields: vec![HeaderField::ReplySerial(0xabcdef12)],
+            },
+            data: (42, "a".into()),
+        }
+    }
+
+    #[test]
+    fn test_message_encode() {
+        let bytes: &[u8] = &[
+            b'B', 2, 0x2, 1, // Big endian reply, no auto-starting, version 1
+            0, 0, 0, 10, // Body size
+            0x12, 0x34, 0x56, 0x78, // Serial
+            0, 0, 0, 0x10, // Header fields length
+            5, 1, b'u', 0, 0xab, 0xcd, 0xef, 0x12, // ReplySerial
+            8, 1, b'g', 0, 2, b'u', b's', 0, // Signature
+            0, 0, 0, 42, // Body u32
+            0, 0, 0, 1, b'a', 0, // Body string
+        ];
+
+        assert_eq!(reply(Endianness::Big).to_bytes().unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_message_roundtrip() {
+        for &endianness in &[Endianness::Little, Endianness::Big] {
+            let data = reply(endianness).to_bytes().unwrap();
+            let message = Message::<(u32, String)>::from_bytes(&data).unwrap();
+
+            assert_eq!(message.header.endianness, endianness);
+            assert_eq!(message.header.body_size, 10);
+            assert_eq!(message.data, (42, "a".into()));
+            assert_eq!(data.len() % 8, 10 % 8);
+        }
+    }
+
+    #[test]
+    fn test_message_signature_mismatch() {
+        let data = reply(Endianness::Little).to_bytes().unwrap();
+        assert_err_matches!(Message::<u32>::from_bytes(&data), Error::SignatureMismatch { .. });
+
+        let mut data = data;
+        data[4] = 4; // Body size
+        assert_err!(Message::<(u32, String)>::from_bytes(&data));
+
+        data[4] = 12;
+        data.extend_from_slice(&[0, 0]);
+        assert_err_matches!(
+            Message::<(u32, String)>::from_bytes(&data),
+            Error::BodySizeMismatch { body_size: 12, .. }
+        );
+    }
+
+    #[test]
+    fn test_message_decode_error_path() {
+        let call = Message::method_call(
+            Some(BusName::new("org.example.Service").unwrap()),
+            ObjectPath::new("/").unwrap(),
+            None,
+            Member::new("Ping").unwrap(),
+            (),
+        )
+        .build(&SerialAllocator::new())
+        .unwrap();
+
+        let mut data = call.to_bytes().unwrap();
+        let offset = data.windows(7).position(|window| window == b"Service").unwrap();
+        data[offset] = 0xff;
+
+        let err = Message::<()>::from_bytes(&data).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "header.fields[2].<variant>.s at offset {}: UTF-8 decoding error: {}",
+                offset - 16,
+                String::from_utf8(data[offset - 12..offset + 7].to_vec()).unwrap_err()
+            )
+        );
+        assert!(matches!(err.root(), Error::FromUtf8 { .. }));
+    }
 }
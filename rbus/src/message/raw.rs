@@ -0,0 +1,284 @@
+use super::{body_signature, check_signature, with_body_fields, Message, MessageHeader};
+use crate::{
+    marshal::{Endianness, Marshaller},
+    types::{DBusType, Signature, UnixFd, Value},
+    Error, Result,
+};
+use std::io;
+
+/// A message whose body is kept encoded, to be decoded once its type is
+/// known or forwarded as is.
+#[derive(Debug, Clone)]
+pub struct RawMessage {
+    header: MessageHeader,
+    body: Vec<u8>,
+    fds: Vec<UnixFd>,
+}
+
+impl AsRef<MessageHeader> for RawMessage {
+    fn as_ref(&self) -> &MessageHeader {
+        &self.header
+    }
+}
+
+impl RawMessage {
+    /// Create a message from a header and a body encoded in the header
+    /// endianness, whose fds are `fds`.
+    ///
+    /// The body size, signature and fd count fields of the header are
+    /// expected to describe the body.
+    pub fn new(header: MessageHeader, body: Vec<u8>, fds: Vec<UnixFd>) -> RawMessage {
+        RawMessage { header, body, fds }
+    }
+
+    /// Encode the body of a typed message.
+    pub fn from_message<T: DBusType>(message: &Message<T>) -> Result<RawMessage> {
+        let mut marshaller = Marshaller::new(Vec::new(), message.header.endianness);
+        marshaller.write_value(&message.data)?;
+        let fds = marshaller.take_fds();
+        let body = marshaller.into_inner();
+
+        let mut header = message.header.clone();
+        header.body_size = body.len() as u32;
+        header.fields = with_body_fields(&header.fields, T::signature(), fds.len())?;
+
+        Ok(RawMessage { header, body, fds })
+    }
+
+    pub fn header(&self) -> &MessageHeader {
+        &self.header
+    }
+
+    pub fn endianness(&self) -> Endianness {
+        self.header.endianness
+    }
+
+    /// Return the body signature, which is empty for messages without body.
+    pub fn signature(&self) -> &str {
+        body_signature(&self.header)
+    }
+
+    /// Return the encoded body.
+    pub fn body_bytes(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// Return the fds the body refers to.
+    pub fn fds(&self) -> &[UnixFd] {
+        &self.fds
+    }
+
+    fn body_marshaller(&self) -> Marshaller<&[u8]> {
+        let mut marshaller = Marshaller::from_slice(&self.body, self.endianness());
+        marshaller.set_fds(self.fds.clone());
+        marshaller
+    }
+
+    /// Decode the body, whose signature must be the one of `T`.
+    pub fn body<T: DBusType>(&self) -> Result<T> {
+        check_signature(&self.header, T::signature())?;
+
+        let mut marshaller = self.body_marshaller();
+        let data = marshaller.read_value()?;
+        check_empty(&marshaller)?;
+        Ok(data)
+    }
+
+    /// Decode the body as one dynamically typed value per complete type of
+    /// its signature.
+    pub fn body_values(&self) -> Result<Vec<Value>> {
+        let signature = Signature::new(self.signature())?;
+
+        let mut marshaller = self.body_marshaller();
+        let values = marshaller.read_values(&signature)?;
+        check_empty(&marshaller)?;
+        Ok(values)
+    }
+
+    pub fn into_message<T: DBusType>(self) -> Result<Message<T>> {
+        let data = self.body()?;
+        Ok(Message {
+            header: self.header,
+            data,
+        })
+    }
+
+    /// Encode the message, the body is copied as is.
+    ///
+    /// The body fds become the marshaller fds, which must not refer to any
+    /// other fd.
+    pub fn encode<Inner>(&self, marshaller: &mut Marshaller<Inner>) -> Result<()>
+    where
+        Inner: io::Write,
+    {
+        use std::io::Write;
+
+        if !marshaller.fds().is_empty() {
+            return Err(Error::UnexpectedFds {
+                count: marshaller.fds().len(),
+            });
+        }
+        marshaller.set_fds(self.fds.clone());
+
+        let mut header = self.header.clone();
+        header.body_size = self.body.len() as u32;
+
+        header.encode(marshaller)?;
+        marshaller.write_padding(8)?;
+        marshaller.io().write_all(&self.body)?;
+        Ok(())
+    }
+
+    /// Decode a message without decoding its body, the fds received along
+    /// the message must already be set in the marshaller.
+    pub fn decode<Inner>(marshaller: &mut Marshaller<Inner>) -> Result<RawMessage>
+    where
+        Inner: io::Read,
+    {
+        let header = MessageHeader::decode(marshaller)?;
+        RawMessage::decode_after_header(header, marshaller)
+    }
+
+    /// Decode the rest of a message whose header was already decoded from
+    /// `marshaller`, which must hold exactly the fds its header declares.
+    pub(crate) fn decode_after_header<Inner>(
+        header: MessageHeader,
+        marshaller: &mut Marshaller<Inner>,
+    ) -> Result<RawMessage>
+    where
+        Inner: io::Read,
+    {
+        header.check_fields()?;
+        let expected = header.unix_fds().unwrap_or(0);
+        if expected as usize != marshaller.fds().len() {
+            return Err(Error::UnixFdCountMismatch {
+                expected,
+                found: marshaller.fds().len(),
+            });
+        }
+
+        marshaller.read_padding(8)?;
+        let body = marshaller.read_bytes(header.body_size as usize)?;
+
+        Ok(RawMessage {
+            header,
+            body,
+            fds: marshaller.fds().to_vec(),
+        })
+    }
+
+    /// Serialize the message, without its fds.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut marshaller = Marshaller::new(Vec::new(), self.endianness());
+        self.encode(&mut marshaller)?;
+        Ok(marshaller.into_inner())
+    }
+
+    /// Deserialize a whole message, which must not refer to any fd.
+    pub fn from_bytes(data: &[u8]) -> Result<RawMessage> {
+        let mut marshaller = Marshaller::from_slice(data, Endianness::native());
+        let message = RawMessage::decode(&mut marshaller)?;
+        check_empty(&marshaller)?;
+        Ok(message)
+    }
+}
+
+fn check_empty(marshaller: &Marshaller<&[u8]>) -> Result<()> {
+    if !marshaller.is_empty() {
+        return Err(Error::TrailingData {
+            position: marshaller.read_position(),
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        message::{
+            types::{Interface, Member},
+            Flags, HeaderField, MessageType,
+        },
+        types::ObjectPath,
+    };
+
+    fn message(endianness: Endianness) -> Message<(u32, Vec<String>)> {
+        Message {
+            header: MessageHeader {
+                endianness,
+                ty: MessageType::Signal,
+                flags: Flags::empty(),
+                version: 1,
+                body_size: 0,
+                serial: 7,
+                fields: vec![
+                    HeaderField::Path(ObjectPath::new("/org/example").unwrap()),
+                    HeaderField::Interface(Interface::new("org.example.Signals").unwrap()),
+                    HeaderField::Member(Member::new("Changed").unwrap()),
+                ],
+            },
+            data: (3, vec!["a".into(), "b".into()]),
+        }
+    }
+
+    #[test]
+    fn test_raw_message_body() {
+        for &endianness in &[Endianness::Little, Endianness::Big] {
+            let data = message(endianness).to_bytes().unwrap();
+            let raw = RawMessage::from_bytes(&data).unwrap();
+
+            assert_eq!(raw.endianness(), endianness);
+            assert_eq!(raw.signature(), "uas");
+            assert_eq!(raw.body::<(u32, Vec<String>)>().unwrap(), (3, vec!["a".into(), "b".into()]));
+            assert_err_matches!(raw.body::<u32>(), Error::SignatureMismatch { .. });
+            assert_eq!(
+                raw.body_values().unwrap(),
+                vec![
+                    Value::from(3u32),
+                    Value::Array {
+                        ty: crate::types::SignatureType::parse("s").unwrap(),
+                        values: vec![Value::from("a"), Value::from("b")],
+                    },
+                ]
+            );
+
+            assert_eq!(raw.to_bytes().unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn test_raw_message_from_message() {
+        let message = message(Endianness::Big);
+        let raw = RawMessage::from_message(&message).unwrap();
+        assert_eq!(raw.to_bytes().unwrap(), message.to_bytes().unwrap());
+        assert_eq!(raw.header().body_size as usize, raw.body_bytes().len());
+
+        let message = raw.into_message::<(u32, Vec<String>)>().unwrap();
+        assert_eq!(message.data().0, 3);
+    }
+
+    #[test]
+    fn test_raw_message_invalid() {
+        let mut data = message(Endianness::Little).to_bytes().unwrap();
+        let length = data.len();
+        data.push(0);
+        match RawMessage::from_bytes(&data) {
+            Err(Error::TrailingData { position }) => assert_eq!(position, length),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+
+        let mut raw = RawMessage::from_message(&message(Endianness::Little)).unwrap();
+        raw.header.fields.push(HeaderField::UnixFds(1));
+        let data = raw.to_bytes().unwrap();
+        assert_err_matches!(
+            RawMessage::from_bytes(&data),
+            Error::UnixFdCountMismatch { expected: 1, found: 0 }
+        );
+
+        let mut marshaller = Marshaller::new(Vec::new(), Endianness::Little);
+        marshaller.set_fds(vec![UnixFd::new(std::fs::File::open("/dev/null").unwrap())]);
+        assert_err_matches!(raw.encode(&mut marshaller), Error::UnexpectedFds { count: 1 });
+    }
+}
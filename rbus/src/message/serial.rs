@@ -0,0 +1,79 @@
+use std::{
+    num::NonZeroU32,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+/// Hands out the serials of the messages sent on a connection.
+///
+/// Serials increase monotonically from the first one and wrap around to 1
+/// after `u32::MAX`, so they only collide with calls which stayed in flight
+/// for 2³² messages.
+#[derive(Debug)]
+pub struct SerialAllocator {
+    next: AtomicU32,
+}
+
+impl SerialAllocator {
+    pub fn new() -> SerialAllocator {
+        SerialAllocator::starting_at(NonZeroU32::new(1).unwrap())
+    }
+
+    /// Create an allocator whose first serial is `serial`.
+    pub fn starting_at(serial: NonZeroU32) -> SerialAllocator {
+        SerialAllocator {
+            next: AtomicU32::new(serial.get()),
+        }
+    }
+
+    /// Return a serial which wasn't returned by the last 2³² - 1 calls.
+    pub fn next(&self) -> NonZeroU32 {
+        let serial = self
+            .next
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |serial| {
+                Some(serial.checked_add(1).unwrap_or(1))
+            })
+            .unwrap();
+        NonZeroU32::new(serial).unwrap()
+    }
+}
+
+impl Default for SerialAllocator {
+    fn default() -> SerialAllocator {
+        SerialAllocator::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{collections::HashSet, sync::Arc, thread};
+
+    #[test]
+    fn test_serial_allocator() {
+        let serials = SerialAllocator::new();
+        assert_eq!(serials.next().get(), 1);
+        assert_eq!(serials.next().get(), 2);
+
+        let serials = SerialAllocator::starting_at(NonZeroU32::new(u32::MAX).unwrap());
+        assert_eq!(serials.next().get(), u32::MAX);
+        assert_eq!(serials.next().get(), 1);
+    }
+
+    #[test]
+    fn test_serial_allocator_threads() {
+        let serials = Arc::new(SerialAllocator::new());
+        let threads = (0..4)
+            .map(|_| {
+                let serials = serials.clone();
+                thread::spawn(move || (0..1000).map(|_| serials.next().get()).collect::<Vec<_>>())
+            })
+            .collect::<Vec<_>>();
+
+        let mut all = HashSet::new();
+        for thread in threads {
+            all.extend(thread.join().unwrap());
+        }
+        assert_eq!(all.len(), 4000);
+        assert_eq!(serials.next().get(), 4001);
+    }
+}
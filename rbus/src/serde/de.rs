@@ -0,0 +1,507 @@
+use super::{mismatch, struct_fields, token_type, Config, EnumEncoding};
+use crate::{
+    marshal::Marshaller,
+    types::{BasicType, DBusType, ObjectPath, Signature, SignatureType},
+    Error, Result,
+};
+use serde::{
+    de::{
+        self,
+        value::{StringDeserializer, U32Deserializer},
+        DeserializeSeed, IntoDeserializer, Visitor,
+    },
+    forward_to_deserialize_any,
+};
+use std::{borrow::Cow, io, slice};
+
+/// Deserialize a value of the given type, after skipping the padding up to
+/// its alignment.
+fn deserialize<'de, R, S>(
+    marshaller: &mut Marshaller<R>,
+    ty: &SignatureType,
+    config: Config,
+    seed: S,
+) -> Result<S::Value>
+where
+    R: io::Read,
+    S: DeserializeSeed<'de>,
+{
+    marshaller.read_padding(ty.alignment())?;
+    seed.deserialize(Deserializer::new(marshaller, ty, config))
+}
+
+/// Read the signature of a variant, and skip the padding up to its value.
+fn read_variant_type<R: io::Read>(marshaller: &mut Marshaller<R>) -> Result<SignatureType> {
+    let signature = Signature::decode(marshaller)?;
+    let mut types = signature.types();
+    if types.len() != 1 {
+        return Err(Error::Custom {
+            message: format!("Variant signature `{}` isn't a single complete type", signature),
+        });
+    }
+
+    let ty = types.remove(0);
+    marshaller.read_padding(ty.alignment())?;
+    Ok(ty)
+}
+
+/// Variants hold their own type, so deserializing one means reading its
+/// signature and deserializing the value inside with the same method.
+macro_rules! forward_variant {
+    ($self:ident, $method:ident($($arg:expr),*)) => {
+        if let SignatureType::Variant = $self.ty {
+            let ty = read_variant_type($self.marshaller)?;
+            let config = $self.config;
+            return $self.marshaller.read_variant(|marshaller| {
+                de::Deserializer::$method(Deserializer::new(marshaller, &ty, config), $($arg),*)
+            });
+        }
+    };
+}
+
+/// A deserializer reading values of a known D-Bus type.
+///
+/// The input must already be aligned to the type alignment.
+pub struct Deserializer<'m, 't, R> {
+    marshaller: &'m mut Marshaller<R>,
+    ty: &'t SignatureType,
+    config: Config,
+    packed: bool,
+    single: bool,
+}
+
+impl<'m, 't, R: io::Read> Deserializer<'m, 't, R> {
+    pub fn new(marshaller: &'m mut Marshaller<R>, ty: &'t SignatureType, config: Config) -> Self {
+        Deserializer {
+            marshaller,
+            ty,
+            config,
+            packed: false,
+            single: false,
+        }
+    }
+
+    /// Read structs without aligning them, like the fields of a message
+    /// body.
+    pub fn packed(mut self) -> Self {
+        self.packed = true;
+        self
+    }
+
+    /// Read tuples of a single element as their element, like the only
+    /// field of a message body.
+    pub fn single(mut self) -> Self {
+        self.single = true;
+        self
+    }
+}
+
+impl<'de, 'm, 't, R: io::Read> de::Deserializer<'de> for Deserializer<'m, 't, R> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        forward_variant!(self, deserialize_any(visitor));
+
+        match self.ty {
+            SignatureType::Basic(ty) => match ty {
+                BasicType::Byte => visitor.visit_u8(u8::decode(self.marshaller)?),
+                BasicType::Boolean => visitor.visit_bool(bool::decode(self.marshaller)?),
+                BasicType::Int16 => visitor.visit_i16(i16::decode(self.marshaller)?),
+                BasicType::UInt16 => visitor.visit_u16(u16::decode(self.marshaller)?),
+                BasicType::Int32 => visitor.visit_i32(i32::decode(self.marshaller)?),
+                BasicType::UInt32 => visitor.visit_u32(u32::decode(self.marshaller)?),
+                BasicType::Int64 => visitor.visit_i64(i64::decode(self.marshaller)?),
+                BasicType::UInt64 => visitor.visit_u64(u64::decode(self.marshaller)?),
+                BasicType::Double => visitor.visit_f64(f64::decode(self.marshaller)?),
+                BasicType::UnixFd => {
+                    // Unix fds are deserialized as their index in the fd list.
+                    let index = u32::decode(self.marshaller)?;
+                    self.marshaller.fd(index)?;
+                    visitor.visit_u32(index)
+                }
+                BasicType::String => visitor.visit_string(String::decode(self.marshaller)?),
+                BasicType::ObjectPath => {
+                    let path = String::decode(self.marshaller)?;
+                    ObjectPath::validate(&path)?;
+                    visitor.visit_string(path)
+                }
+                BasicType::Signature => visitor.visit_string(Signature::decode(self.marshaller)?.to_string()),
+            },
+            SignatureType::Array(element) => match **element {
+                SignatureType::DictEntry(..) => self.deserialize_map(visitor),
+                _ => self.deserialize_seq(visitor),
+            },
+            SignatureType::Struct(fields) => {
+                let len = fields.len();
+                Deserializer { single: false, ..self }.deserialize_tuple(len, visitor)
+            }
+            SignatureType::DictEntry(..) => self.deserialize_tuple(2, visitor),
+            SignatureType::Variant => unreachable!("Variants are forwarded to their value"),
+            #[cfg(feature = "gvariant")]
+            SignatureType::Maybe(_) => Err(crate::gvariant::maybe_unsupported()),
+        }
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_byte_buf(visitor)
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        forward_variant!(self, deserialize_byte_buf(visitor));
+
+        match self.ty {
+            SignatureType::Array(element) if **element == SignatureType::Basic(BasicType::Byte) => {
+                let end = self.marshaller.begin_read_array(1)?;
+                let data = self.marshaller.read_bytes(end - self.marshaller.read_position())?;
+                self.marshaller.end_read_array(end)?;
+                visitor.visit_byte_buf(data)
+            }
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        forward_variant!(self, deserialize_option(visitor));
+
+        match self.ty {
+            SignatureType::Array(element) => {
+                let end = self.marshaller.begin_read_array(element.alignment())?;
+                let value = if self.marshaller.read_position() < end {
+                    visitor.visit_some(Deserializer::new(&mut *self.marshaller, element, self.config))?
+                } else {
+                    visitor.visit_none::<Error>()?
+                };
+                self.marshaller.end_read_array(end)?;
+
+                Ok(value)
+            }
+            ty => Err(mismatch(ty, "array")),
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        forward_variant!(self, deserialize_unit(visitor));
+
+        match self.ty {
+            SignatureType::Basic(BasicType::Byte) => {
+                u8::decode(self.marshaller)?;
+                visitor.visit_unit()
+            }
+            ty => Err(mismatch(ty, BasicType::Byte)),
+        }
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, name: &'static str, visitor: V) -> Result<V::Value> {
+        forward_variant!(self, deserialize_newtype_struct(name, visitor));
+
+        if let Some(expected) = token_type(name) {
+            let expected = SignatureType::Basic(expected);
+            if *self.ty != expected {
+                return Err(mismatch(self.ty, expected));
+            }
+            return visitor.visit_newtype_struct(self);
+        }
+
+        if !self.config.newtype_as_struct {
+            return visitor.visit_newtype_struct(self);
+        }
+
+        let config = self.config;
+        let field = match self.ty {
+            SignatureType::Struct(fields) if fields.len() == 1 => &fields[0],
+            ty => return Err(mismatch(ty, "struct of 1 fields")),
+        };
+
+        self.marshaller.read_struct(|marshaller| {
+            marshaller.read_padding(field.alignment())?;
+            visitor.visit_newtype_struct(Deserializer::new(marshaller, field, config))
+        })
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        forward_variant!(self, deserialize_seq(visitor));
+
+        match self.ty {
+            SignatureType::Array(element) => {
+                let end = self.marshaller.begin_read_array(element.alignment())?;
+                let value = visitor.visit_seq(ArrayAccess {
+                    marshaller: &mut *self.marshaller,
+                    element,
+                    end,
+                    config: self.config,
+                })?;
+                self.marshaller.end_read_array(end)?;
+
+                Ok(value)
+            }
+            ty => Err(mismatch(ty, "array")),
+        }
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value> {
+        forward_variant!(self, deserialize_tuple(len, visitor));
+
+        let config = self.config;
+        if self.single && len == 1 {
+            return visit_fields(self.marshaller, &[Cow::Borrowed(self.ty)], config, visitor);
+        }
+
+        let fields = struct_fields(self.ty, len)?;
+
+        if self.packed {
+            visit_fields(self.marshaller, &fields, config, visitor)
+        } else {
+            self.marshaller
+                .read_struct(|marshaller| visit_fields(marshaller, &fields, config, visitor))
+        }
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value> {
+        Deserializer { single: false, ..self }.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        forward_variant!(self, deserialize_map(visitor));
+
+        let (key, value) = match self.ty {
+            SignatureType::Array(element) => match **element {
+                SignatureType::DictEntry(key, ref value) => (key, value),
+                _ => return Err(mismatch(self.ty, "dict")),
+            },
+            ty => return Err(mismatch(ty, "dict")),
+        };
+
+        let end = self.marshaller.begin_read_array(8)?;
+        let result = visitor.visit_map(DictAccess {
+            marshaller: &mut *self.marshaller,
+            key: SignatureType::Basic(key),
+            value,
+            end,
+            config: self.config,
+        })?;
+        self.marshaller.end_read_array(end)?;
+
+        Ok(result)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        Deserializer { single: false, ..self }.deserialize_tuple(fields.len(), visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        forward_variant!(self, deserialize_enum(name, variants, visitor));
+
+        let expected = self.config.enums.signature_type();
+        if *self.ty != expected {
+            return Err(mismatch(self.ty, expected));
+        }
+
+        let config = self.config;
+        match config.enums {
+            EnumEncoding::Index => {
+                let index: U32Deserializer<Error> = u32::decode(self.marshaller)?.into_deserializer();
+                visitor.visit_enum(index)
+            }
+            EnumEncoding::Name => {
+                let name: StringDeserializer<Error> = String::decode(self.marshaller)?.into_deserializer();
+                visitor.visit_enum(name)
+            }
+            EnumEncoding::TaggedIndex | EnumEncoding::TaggedName => self.marshaller.read_struct(|marshaller| {
+                let tag = match config.enums {
+                    EnumEncoding::TaggedIndex => Tag::Index(marshaller.read_value()?),
+                    _ => Tag::Name(marshaller.read_value()?),
+                };
+                let ty = read_variant_type(marshaller)?;
+
+                visitor.visit_enum(TaggedEnum {
+                    tag,
+                    variant: VariantAccess { marshaller, ty, config },
+                })
+            }),
+        }
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string identifier ignored_any
+    }
+}
+
+fn visit_fields<'de, R, V>(
+    marshaller: &mut Marshaller<R>,
+    fields: &[Cow<SignatureType>],
+    config: Config,
+    visitor: V,
+) -> Result<V::Value>
+where
+    R: io::Read,
+    V: Visitor<'de>,
+{
+    let mut access = StructAccess {
+        marshaller,
+        fields: fields.iter(),
+        config,
+    };
+    let value = visitor.visit_seq(&mut access)?;
+
+    if access.fields.len() != 0 {
+        return Err(Error::Custom {
+            message: format!("{} struct fields left unread", access.fields.len()),
+        });
+    }
+
+    Ok(value)
+}
+
+struct StructAccess<'a, 'f, R> {
+    marshaller: &'a mut Marshaller<R>,
+    fields: slice::Iter<'f, Cow<'f, SignatureType>>,
+    config: Config,
+}
+
+impl<'de, 'a, 'f, R: io::Read> de::SeqAccess<'de> for StructAccess<'a, 'f, R> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>> {
+        match self.fields.next() {
+            Some(ty) => deserialize(self.marshaller, ty, self.config, seed).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.fields.len())
+    }
+}
+
+struct ArrayAccess<'a, 't, R> {
+    marshaller: &'a mut Marshaller<R>,
+    element: &'t SignatureType,
+    end: usize,
+    config: Config,
+}
+
+impl<'de, 'a, 't, R: io::Read> de::SeqAccess<'de> for ArrayAccess<'a, 't, R> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>> {
+        if self.marshaller.read_position() >= self.end {
+            return Ok(None);
+        }
+
+        deserialize(self.marshaller, self.element, self.config, seed).map(Some)
+    }
+}
+
+struct DictAccess<'a, 't, R> {
+    marshaller: &'a mut Marshaller<R>,
+    key: SignatureType,
+    value: &'t SignatureType,
+    end: usize,
+    config: Config,
+}
+
+impl<'de, 'a, 't, R: io::Read> de::MapAccess<'de> for DictAccess<'a, 't, R> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        if self.marshaller.read_position() >= self.end {
+            return Ok(None);
+        }
+
+        // Dict entries count as structs, the depth is restored once the
+        // value is read.
+        self.marshaller.read_padding(8)?;
+        self.marshaller.context_mut().enter_struct()?;
+        deserialize(self.marshaller, &self.key, self.config, seed).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+        let value = deserialize(self.marshaller, self.value, self.config, seed);
+        self.marshaller.context_mut().struct_depth -= 1;
+        value
+    }
+}
+
+enum Tag {
+    Index(u32),
+    Name(String),
+}
+
+struct TaggedEnum<'a, R> {
+    tag: Tag,
+    variant: VariantAccess<'a, R>,
+}
+
+impl<'de, 'a, R: io::Read> de::EnumAccess<'de> for TaggedEnum<'a, R> {
+    type Error = Error;
+    type Variant = VariantAccess<'a, R>;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self::Variant)> {
+        let value = match self.tag {
+            Tag::Index(index) => {
+                let index: U32Deserializer<Error> = index.into_deserializer();
+                seed.deserialize(index)?
+            }
+            Tag::Name(name) => {
+                let name: StringDeserializer<Error> = name.into_deserializer();
+                seed.deserialize(name)?
+            }
+        };
+
+        Ok((value, self.variant))
+    }
+}
+
+/// The data of a tagged enum variant, held in a variant of type `ty`.
+struct VariantAccess<'a, R> {
+    marshaller: &'a mut Marshaller<R>,
+    ty: SignatureType,
+    config: Config,
+}
+
+impl<'de, 'a, R: io::Read> de::VariantAccess<'de> for VariantAccess<'a, R> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<()> {
+        de::Deserialize::deserialize(Deserializer::new(self.marshaller, &self.ty, self.config))
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value> {
+        seed.deserialize(Deserializer::new(self.marshaller, &self.ty, self.config))
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value> {
+        de::Deserializer::deserialize_tuple(Deserializer::new(self.marshaller, &self.ty, self.config), len, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(self, fields: &'static [&'static str], visitor: V) -> Result<V::Value> {
+        de::Deserializer::deserialize_tuple(
+            Deserializer::new(self.marshaller, &self.ty, self.config),
+            fields.len(),
+            visitor,
+        )
+    }
+}
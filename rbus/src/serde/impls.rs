@@ -0,0 +1,163 @@
+use super::{UnixFdIndex, OBJECT_PATH_TOKEN, SIGNATURE_TOKEN, UNIX_FD_TOKEN};
+use crate::types::{ByteBuf, ObjectPath, Signature, Struct};
+use serde::{
+    de::{self, Deserialize, Deserializer, SeqAccess, Visitor},
+    ser::{Serialize, Serializer},
+};
+use std::{convert::TryFrom, fmt};
+
+// These types are serialized as newtype structs with a special name, so the
+// D-Bus serializer can tell them apart from plain strings and integers while
+// other formats just see the inner value.
+
+impl Serialize for ObjectPath {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let path: &str = self.as_ref();
+        serializer.serialize_newtype_struct(OBJECT_PATH_TOKEN, path)
+    }
+}
+
+impl Serialize for Signature {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_newtype_struct(SIGNATURE_TOKEN, self.as_str())
+    }
+}
+
+impl Serialize for UnixFdIndex {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_newtype_struct(UNIX_FD_TOKEN, &self.0)
+    }
+}
+
+struct StrVisitor<F> {
+    expecting: &'static str,
+    parse: F,
+}
+
+impl<'de, F, T, E> Visitor<'de> for StrVisitor<F>
+where
+    F: FnOnce(&str) -> Result<T, E>,
+    E: fmt::Display,
+{
+    type Value = T;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.expecting)
+    }
+
+    fn visit_str<Er: de::Error>(self, value: &str) -> Result<T, Er> {
+        (self.parse)(value).map_err(Er::custom)
+    }
+
+    fn visit_newtype_struct<D: Deserializer<'de>>(self, deserializer: D) -> Result<T, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        self.visit_str(&value)
+    }
+}
+
+impl<'de> Deserialize<'de> for ObjectPath {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let visitor = StrVisitor {
+            expecting: "an object path",
+            parse: |path: &str| ObjectPath::new(path),
+        };
+        deserializer.deserialize_newtype_struct(OBJECT_PATH_TOKEN, visitor)
+    }
+}
+
+impl<'de> Deserialize<'de> for Signature {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let visitor = StrVisitor {
+            expecting: "a signature",
+            parse: |sig: &str| Signature::new(sig),
+        };
+        deserializer.deserialize_newtype_struct(SIGNATURE_TOKEN, visitor)
+    }
+}
+
+struct UnixFdIndexVisitor;
+
+impl<'de> Visitor<'de> for UnixFdIndexVisitor {
+    type Value = UnixFdIndex;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a unix fd index")
+    }
+
+    fn visit_u32<E: de::Error>(self, value: u32) -> Result<UnixFdIndex, E> {
+        Ok(UnixFdIndex(value))
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<UnixFdIndex, E> {
+        u32::try_from(value)
+            .map(UnixFdIndex)
+            .map_err(|_| E::invalid_value(de::Unexpected::Unsigned(value), &self))
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<UnixFdIndex, E> {
+        u32::try_from(value)
+            .map(UnixFdIndex)
+            .map_err(|_| E::invalid_value(de::Unexpected::Signed(value), &self))
+    }
+
+    fn visit_newtype_struct<D: Deserializer<'de>>(self, deserializer: D) -> Result<UnixFdIndex, D::Error> {
+        u32::deserialize(deserializer).map(UnixFdIndex)
+    }
+}
+
+impl<'de> Deserialize<'de> for UnixFdIndex {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_newtype_struct(UNIX_FD_TOKEN, UnixFdIndexVisitor)
+    }
+}
+
+impl Serialize for ByteBuf {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(self)
+    }
+}
+
+struct ByteBufVisitor;
+
+impl<'de> Visitor<'de> for ByteBufVisitor {
+    type Value = ByteBuf;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a byte array")
+    }
+
+    fn visit_bytes<E: de::Error>(self, value: &[u8]) -> Result<ByteBuf, E> {
+        Ok(ByteBuf::from(value))
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, value: Vec<u8>) -> Result<ByteBuf, E> {
+        Ok(ByteBuf::from(value))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<ByteBuf, A::Error> {
+        let mut data = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(4096));
+        while let Some(byte) = seq.next_element()? {
+            data.push(byte);
+        }
+        Ok(ByteBuf::from(data))
+    }
+}
+
+impl<'de> Deserialize<'de> for ByteBuf {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_byte_buf(ByteBufVisitor)
+    }
+}
+
+// Serde tuples are already D-Bus structs, the wrapper is transparent.
+impl<T: Serialize> Serialize for Struct<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Struct<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize(deserializer).map(Struct)
+    }
+}
@@ -0,0 +1,567 @@
+//! serde support for the D-Bus wire format.
+//!
+//! Serialization and deserialization are driven by a signature: it can be
+//! given explicitly or computed from a type with `signature_of`. Structs map
+//! to `(..)`, maps to `a{..}`, sequences to `a..`, options to an array of zero
+//! or one element and unit values to a single zero byte (`y`).
+//!
+//! A tuple holding a whole value is packed like `DBusType` tuples, so
+//! `(u8, bool)` is `yb` and `(u8,)` is `y`, as method arguments are. Nested
+//! tuples map to `(..)`, like `types::Struct`, since a packed tuple isn't a
+//! single complete type.
+pub use de::Deserializer;
+pub use ser::Serializer;
+pub use trace::{signature_of, signature_of_with_config, variant_types_of, VariantTypes};
+pub use value::to_value;
+
+use crate::{
+    marshal::{Endianness, Marshaller},
+    types::{BasicType, Signature, SignatureType, Value},
+    Error, Result,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{borrow::Cow, fmt, io};
+
+mod de;
+mod impls;
+mod ser;
+mod trace;
+mod value;
+
+pub(crate) const OBJECT_PATH_TOKEN: &str = "$rbus::ObjectPath";
+pub(crate) const SIGNATURE_TOKEN: &str = "$rbus::Signature";
+pub(crate) const UNIX_FD_TOKEN: &str = "$rbus::UnixFd";
+
+/// A Unix fd (`h`), as an index in the fd list of the marshaller.
+///
+/// `UnixFd` itself can't go through serde, so serialized values refer to fds
+/// which were pushed to the marshaller beforehand with `Marshaller::push_fd`,
+/// and deserialized values are looked up with `Marshaller::fd`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct UnixFdIndex(pub u32);
+
+/// How Rust enums are mapped to D-Bus types.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnumEncoding {
+    /// Enums are encoded as their variant index (`u`), only unit variants are
+    /// supported.
+    Index,
+    /// Enums are encoded as their variant name (`s`), only unit variants are
+    /// supported.
+    Name,
+    /// Enums are encoded as a `(uv)` struct holding the variant index and its
+    /// data, unit variants hold a single zero byte.
+    TaggedIndex,
+    /// Enums are encoded as a `(sv)` struct holding the variant name and its
+    /// data, unit variants hold a single zero byte.
+    TaggedName,
+}
+
+impl EnumEncoding {
+    fn is_tagged(self) -> bool {
+        match self {
+            EnumEncoding::TaggedIndex | EnumEncoding::TaggedName => true,
+            EnumEncoding::Index | EnumEncoding::Name => false,
+        }
+    }
+
+    fn signature_type(self) -> SignatureType {
+        match self {
+            EnumEncoding::Index => SignatureType::Basic(BasicType::UInt32),
+            EnumEncoding::Name => SignatureType::Basic(BasicType::String),
+            EnumEncoding::TaggedIndex => SignatureType::Struct(vec![
+                SignatureType::Basic(BasicType::UInt32),
+                SignatureType::Variant,
+            ]),
+            EnumEncoding::TaggedName => SignatureType::Struct(vec![
+                SignatureType::Basic(BasicType::String),
+                SignatureType::Variant,
+            ]),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Config {
+    pub enums: EnumEncoding,
+    /// Encode newtype structs as a single field struct instead of encoding
+    /// their inner value directly.
+    pub newtype_as_struct: bool,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            enums: EnumEncoding::Index,
+            newtype_as_struct: false,
+        }
+    }
+}
+
+impl serde::ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Error {
+        Error::Custom {
+            message: msg.to_string(),
+        }
+    }
+}
+
+impl serde::de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Error {
+        Error::Custom {
+            message: msg.to_string(),
+        }
+    }
+}
+
+/// Return the value identifying an enum variant.
+fn enum_tag(config: Config, index: u32, name: &str) -> Value {
+    match config.enums {
+        EnumEncoding::Index | EnumEncoding::TaggedIndex => Value::UInt32(index),
+        EnumEncoding::Name | EnumEncoding::TaggedName => Value::String(name.into()),
+    }
+}
+
+/// Return the tag of an enum variant holding data.
+fn variant_tag(config: Config, index: u32, name: &str) -> Result<Value> {
+    if !config.enums.is_tagged() {
+        return Err(Error::Custom {
+            message: format!("Enum variant `{}` holds data, which needs a tagged enum encoding", name),
+        });
+    }
+
+    Ok(enum_tag(config, index, name))
+}
+
+/// Return the D-Bus type of the newtype structs used to serialize object
+/// paths, signatures and unix fds.
+fn token_type(name: &str) -> Option<BasicType> {
+    match name {
+        OBJECT_PATH_TOKEN => Some(BasicType::ObjectPath),
+        SIGNATURE_TOKEN => Some(BasicType::Signature),
+        UNIX_FD_TOKEN => Some(BasicType::UnixFd),
+        _ => None,
+    }
+}
+
+/// Return the field types of a struct or dict entry type, which must have
+/// `len` fields.
+fn struct_fields(ty: &SignatureType, len: usize) -> Result<Vec<Cow<'_, SignatureType>>> {
+    let fields = match ty {
+        SignatureType::Struct(fields) => fields.iter().map(Cow::Borrowed).collect::<Vec<_>>(),
+        SignatureType::DictEntry(key, value) => vec![Cow::Owned(SignatureType::Basic(*key)), Cow::Borrowed(&**value)],
+        ty => return Err(mismatch(ty, "struct")),
+    };
+
+    if fields.len() != len {
+        return Err(mismatch(ty, format!("struct of {} fields", len)));
+    }
+
+    Ok(fields)
+}
+
+fn mismatch<T: fmt::Display>(expected: &SignatureType, found: T) -> Error {
+    Error::SignatureMismatch {
+        expected: expected.to_string(),
+        found: found.to_string(),
+    }
+}
+
+/// Serialize `value` as the given signature.
+///
+/// If the signature contains more than one single complete type, `value`
+/// must be a tuple or a struct whose fields are written one after the other
+/// without the struct padding, like a message body.
+///
+/// The type of the data of tagged enum variants is inferred from the data,
+/// which fails for empty sequences, empty maps and `None`. Use
+/// `to_writer_with_variants` with the types found by `variant_types_of` to
+/// serialize them.
+pub fn to_writer<W, T>(marshaller: &mut Marshaller<W>, signature: &Signature, value: &T, config: Config) -> Result<()>
+where
+    W: io::Write,
+    T: Serialize + ?Sized,
+{
+    write(marshaller, signature, None, value, config)
+}
+
+/// Serialize `value` as the given signature, the data of tagged enum
+/// variants being written with the types in `variants`.
+pub fn to_writer_with_variants<W, T>(
+    marshaller: &mut Marshaller<W>,
+    signature: &Signature,
+    variants: &VariantTypes,
+    value: &T,
+    config: Config,
+) -> Result<()>
+where
+    W: io::Write,
+    T: Serialize + ?Sized,
+{
+    write(marshaller, signature, Some(variants), value, config)
+}
+
+fn write<W, T>(
+    marshaller: &mut Marshaller<W>,
+    signature: &Signature,
+    variants: Option<&VariantTypes>,
+    value: &T,
+    config: Config,
+) -> Result<()>
+where
+    W: io::Write,
+    T: Serialize + ?Sized,
+{
+    let mut types = signature.types();
+    if types.len() == 1 {
+        ser::serialize_single(marshaller, &types.remove(0), config, variants, value)
+    } else {
+        let ty = SignatureType::Struct(types);
+        let serializer = Serializer::new(marshaller, &ty, config).packed();
+        value.serialize(match variants {
+            Some(variants) => serializer.variant_types(variants),
+            None => serializer,
+        })
+    }
+}
+
+/// Deserialize a value of the given signature, see `to_writer`.
+pub fn from_reader<R, T>(marshaller: &mut Marshaller<R>, signature: &Signature, config: Config) -> Result<T>
+where
+    R: io::Read,
+    T: DeserializeOwned,
+{
+    let mut types = signature.types();
+    if types.len() == 1 {
+        T::deserialize(Deserializer::new(marshaller, &types.remove(0), config).single())
+    } else {
+        let ty = SignatureType::Struct(types);
+        T::deserialize(Deserializer::new(marshaller, &ty, config).packed())
+    }
+}
+
+/// Serialize `value` as the given signature with the default configuration.
+///
+/// The signature of a type which can also be deserialized is given by
+/// `signature_of`.
+pub fn to_bytes<T>(value: &T, signature: &Signature, endianness: Endianness) -> Result<Vec<u8>>
+where
+    T: Serialize + ?Sized,
+{
+    to_bytes_with_config(value, signature, endianness, Config::default())
+}
+
+/// Serialize `value` as the given signature, see `to_writer`.
+pub fn to_bytes_with_config<T>(
+    value: &T,
+    signature: &Signature,
+    endianness: Endianness,
+    config: Config,
+) -> Result<Vec<u8>>
+where
+    T: Serialize + ?Sized,
+{
+    let mut marshaller = Marshaller::new(Vec::new(), endianness);
+    to_writer(&mut marshaller, signature, value, config)?;
+    Ok(marshaller.into_inner())
+}
+
+/// Deserialize a value with the default configuration, using the signature
+/// computed by `signature_of`.
+pub fn from_bytes<T>(data: &[u8], endianness: Endianness) -> Result<T>
+where
+    T: DeserializeOwned,
+{
+    from_bytes_with_config(data, endianness, Config::default())
+}
+
+/// Deserialize a value, using the signature computed by
+/// `signature_of_with_config`.
+pub fn from_bytes_with_config<T>(data: &[u8], endianness: Endianness, config: Config) -> Result<T>
+where
+    T: DeserializeOwned,
+{
+    let signature = signature_of_with_config::<T>(config)?;
+
+    let mut marshaller = Marshaller::new(data, endianness);
+    from_reader(&mut marshaller, &signature, config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{ByteBuf, ObjectPath, UnixFd, Value, MAX_STRUCT_DEPTH};
+    use serde::{Deserialize, Serialize};
+    use std::{collections::HashMap, fs::File};
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    enum State {
+        Idle,
+        Running,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Job {
+        id: u32,
+        path: ObjectPath,
+        tags: Vec<String>,
+        env: HashMap<String, u64>,
+        state: State,
+        parent: Option<u8>,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    enum Event {
+        Started(u32),
+        Moved { from: String, to: String },
+        Stopped,
+    }
+
+    fn job() -> Job {
+        let mut env = HashMap::new();
+        env.insert("HOME".into(), 1);
+
+        Job {
+            id: 3,
+            path: ObjectPath::new("/org/example/Job").unwrap(),
+            tags: vec!["a".into(), "bc".into()],
+            env,
+            state: State::Running,
+            parent: None,
+        }
+    }
+
+    #[test]
+    fn test_signature_of() {
+        assert_eq!(signature_of::<Job>().unwrap().as_str(), "(uoasa{st}uay)");
+        assert_eq!(signature_of::<(u8, bool)>().unwrap().as_str(), "yb");
+        assert_eq!(signature_of::<(u8,)>().unwrap().as_str(), "y");
+        assert_eq!(signature_of::<Vec<(u8, bool)>>().unwrap().as_str(), "a(yb)");
+
+        let config = Config {
+            enums: EnumEncoding::TaggedName,
+            ..Config::default()
+        };
+        assert_eq!(signature_of_with_config::<Event>(config).unwrap().as_str(), "(sv)");
+    }
+
+    #[test]
+    fn test_tuple_mapping() {
+        use crate::types::{DBusType, Struct};
+
+        assert_eq!(signature_of::<(u8, bool)>().unwrap().as_str(), <(u8, bool)>::signature());
+        assert_eq!(signature_of::<(Job,)>().unwrap().as_str(), "(uoasa{st}uay)");
+
+        // Nested tuples are encoded like `Struct`.
+        let value = ((7u32,), (1u8, String::from("a")));
+        let mut expected = Marshaller::new(Vec::new(), Endianness::Little);
+        expected.write_value(&(Struct(value.0), Struct(value.1.clone()))).unwrap();
+        let signature = signature_of::<((u32,), (u8, String))>().unwrap();
+        assert_eq!(signature.as_str(), "(u)(ys)");
+        assert_eq!(to_bytes(&value, &signature, Endianness::Little).unwrap(), expected.into_inner());
+
+        let data = to_bytes(&(3u16,), &signature_of::<(u16,)>().unwrap(), Endianness::Little).unwrap();
+        assert_eq!(data, vec![3, 0]);
+        assert_eq!(from_bytes::<(u16,)>(&data, Endianness::Little).unwrap(), (3,));
+
+        #[derive(Debug, Serialize, Deserialize)]
+        enum Pair {
+            Values((u8, bool)),
+        }
+        let config = Config {
+            enums: EnumEncoding::TaggedIndex,
+            ..Config::default()
+        };
+        assert_eq!(signature_of_with_config::<Pair>(config).unwrap().as_str(), "(uv)");
+        let variants = variant_types_of::<Pair>(config).unwrap();
+        assert_eq!(variants.get("Pair", "Values").unwrap().to_string(), "(yb)");
+    }
+
+    #[test]
+    fn test_signature_of_recursive_type() {
+        #[derive(Debug, Serialize, Deserialize)]
+        struct Node {
+            children: Vec<Node>,
+        }
+
+        assert_err_matches!(signature_of::<Node>(), Error::NestingTooDeep);
+        assert_err_matches!(from_bytes::<Node>(&[], Endianness::Little), Error::NestingTooDeep);
+    }
+
+    #[test]
+    fn test_dict_entry_depth() {
+        // Dict entries count as structs, the variant brings the nesting over
+        // the limit without making the signature invalid.
+        let depth = MAX_STRUCT_DEPTH / 2;
+        let value = (0..depth).fold(Value::Variant(Box::new(Value::Struct(vec![Value::Byte(0)]))), |value, _| {
+            Value::Dict {
+                key: BasicType::String,
+                value: SignatureType::Struct(vec![value.signature_type()]),
+                entries: vec![(Value::from("a"), Value::Struct(vec![value]))],
+            }
+        });
+
+        let mut marshaller = Marshaller::new(Vec::new(), Endianness::Little);
+        value.encode(&mut marshaller).unwrap();
+        let data = marshaller.into_inner();
+
+        let signature = value.signature().unwrap();
+        let mut marshaller = Marshaller::from_slice(&data, Endianness::Little);
+        assert_err_matches!(
+            from_reader::<_, serde::de::IgnoredAny>(&mut marshaller, &signature, Config::default()),
+            Error::StructTooDeep
+        );
+    }
+
+    #[test]
+    fn test_roundtrip_struct() {
+        for &endianness in &[Endianness::Little, Endianness::Big] {
+            let value = job();
+            let signature = signature_of::<Job>().unwrap();
+            let data = to_bytes(&value, &signature, endianness).unwrap();
+            assert_eq!(from_bytes::<Job>(&data, endianness).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn test_same_wire_format() {
+        let value = (7u32, vec![1u64, 2], ByteBuf::from(vec![3, 4]));
+        let signature = Signature::new("uatay").unwrap();
+
+        let mut marshaller = Marshaller::new(Vec::new(), Endianness::Little);
+        to_writer(&mut marshaller, &signature, &value, Config::default()).unwrap();
+
+        let mut expected = Marshaller::new(Vec::new(), Endianness::Little);
+        expected.write_value(&value).unwrap();
+
+        assert_eq!(marshaller.into_inner(), expected.into_inner());
+    }
+
+    #[test]
+    fn test_roundtrip_tagged_enum() {
+        let config = Config {
+            enums: EnumEncoding::TaggedIndex,
+            ..Config::default()
+        };
+        let signature = signature_of_with_config::<Vec<Event>>(config).unwrap();
+        let value = vec![
+            Event::Started(1),
+            Event::Moved {
+                from: "a".into(),
+                to: "b".into(),
+            },
+            Event::Stopped,
+        ];
+
+        let mut marshaller = Marshaller::new(Vec::new(), Endianness::Little);
+        to_writer(&mut marshaller, &signature, &value, config).unwrap();
+        let data = marshaller.into_inner();
+
+        let mut marshaller = Marshaller::new(data.as_slice(), Endianness::Little);
+        let decoded: Vec<Event> = from_reader(&mut marshaller, &signature, config).unwrap();
+        assert_eq!(decoded, value);
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    enum Command {
+        Set(Vec<String>),
+        Clear(Option<u8>),
+        Replace { keys: HashMap<String, u32>, value: String },
+    }
+
+    #[test]
+    fn test_roundtrip_empty_variant_data() {
+        let config = Config {
+            enums: EnumEncoding::TaggedName,
+            ..Config::default()
+        };
+        let value = vec![
+            Command::Set(vec![]),
+            Command::Clear(None),
+            Command::Replace {
+                keys: HashMap::new(),
+                value: "a".into(),
+            },
+        ];
+
+        let variants = variant_types_of::<Vec<Command>>(config).unwrap();
+        assert_eq!(variants.get("Command", "Set").unwrap().to_string(), "as");
+        assert_eq!(variants.get("Command", "Replace").unwrap().to_string(), "(a{su}s)");
+
+        let signature = signature_of_with_config::<Vec<Command>>(config).unwrap();
+        let mut marshaller = Marshaller::new(Vec::new(), Endianness::Little);
+        to_writer_with_variants(&mut marshaller, &signature, &variants, &value, config).unwrap();
+        let data = marshaller.into_inner();
+        assert_eq!(from_bytes_with_config::<Vec<Command>>(&data, Endianness::Little, config).unwrap(), value);
+
+        assert_err!(to_bytes_with_config(&value, &signature, Endianness::Little, config));
+    }
+
+    #[test]
+    fn test_to_bytes_borrowed() {
+        #[derive(Serialize)]
+        struct Borrowed<'a> {
+            name: &'a str,
+            values: &'a [u32],
+        }
+
+        let value = Borrowed {
+            name: "a",
+            values: &[1, 2],
+        };
+        let signature = Signature::new("(sau)").unwrap();
+        let data = to_bytes(&value, &signature, Endianness::Big).unwrap();
+        assert_eq!(from_bytes::<(String, Vec<u32>)>(&data, Endianness::Big).unwrap(), ("a".into(), vec![1, 2]));
+    }
+
+    #[test]
+    fn test_to_value() {
+        let value = to_value(&(1u8, "a"), Config::default()).unwrap();
+        assert_eq!(value, Value::Struct(vec![Value::Byte(1), Value::from("a")]));
+    }
+
+    #[test]
+    fn test_unix_fd_index() {
+        let signature = signature_of::<(String, UnixFdIndex)>().unwrap();
+        assert_eq!(signature.as_str(), "sh");
+
+        let mut marshaller = Marshaller::new(Vec::new(), Endianness::Little);
+        let index = marshaller.push_fd(&UnixFd::new(File::open("/dev/null").unwrap()));
+        let value = (String::from("null"), UnixFdIndex(index));
+        to_writer(&mut marshaller, &signature, &value, Config::default()).unwrap();
+        assert_err_matches!(
+            to_writer(&mut marshaller, &signature, &("", UnixFdIndex(1)), Config::default()),
+            Error::InvalidUnixFd { index: 1, count: 1 }
+        );
+
+        let fds = marshaller.take_fds();
+        let data = marshaller.into_inner();
+        let mut marshaller = Marshaller::new(data.as_slice(), Endianness::Little);
+        marshaller.set_fds(fds);
+        let decoded: (String, UnixFdIndex) = from_reader(&mut marshaller, &signature, Config::default()).unwrap();
+        assert_eq!(decoded, value);
+
+        assert_err_matches!(to_value(&UnixFdIndex(0), Config::default()), Error::InvalidUnixFd { .. });
+    }
+
+    #[test]
+    fn test_empty_struct_variant() {
+        #[derive(Debug, Serialize, Deserialize)]
+        enum Empty {
+            Fields {},
+            Tuple(),
+        }
+
+        let config = Config {
+            enums: EnumEncoding::TaggedIndex,
+            ..Config::default()
+        };
+        let signature = signature_of_with_config::<Empty>(config).unwrap();
+        for value in &[Empty::Fields {}, Empty::Tuple()] {
+            let mut marshaller = Marshaller::new(Vec::new(), Endianness::Little);
+            assert_err_matches!(to_writer(&mut marshaller, &signature, value, config), Error::EmptyStruct);
+            assert_err_matches!(to_value(value, config), Error::EmptyStruct);
+        }
+        assert_err!(to_bytes_with_config(&Empty::Fields {}, &signature, Endianness::Little, config));
+    }
+}
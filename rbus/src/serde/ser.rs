@@ -0,0 +1,632 @@
+use super::{
+    enum_tag, mismatch, struct_fields, token_type, value::to_value_with_fds, variant_tag, Config, VariantTypes,
+};
+use crate::{
+    marshal::Marshaller,
+    types::{BasicType, DBusType, ObjectPath, Signature, SignatureType, Value},
+    Error, Result,
+};
+use serde::ser::{self, Serialize};
+use std::{borrow::Cow, convert::TryFrom, io};
+
+/// Serialize a value as the given type, after aligning the output.
+///
+/// Variants can hold any type, so values serialized as a variant are first
+/// converted to a dynamic `Value`.
+pub(crate) fn serialize<W, T>(
+    marshaller: &mut Marshaller<W>,
+    ty: &SignatureType,
+    config: Config,
+    variants: Option<&VariantTypes>,
+    value: &T,
+) -> Result<()>
+where
+    W: io::Write,
+    T: Serialize + ?Sized,
+{
+    if let SignatureType::Variant = ty {
+        let value = to_value_with_fds(value, config, marshaller.fds())?;
+        return Value::Variant(Box::new(value)).encode(marshaller);
+    }
+
+    marshaller.write_padding(ty.alignment())?;
+    let serializer = Serializer::new(marshaller, ty, config);
+    value.serialize(match variants {
+        Some(variants) => serializer.variant_types(variants),
+        None => serializer,
+    })
+}
+
+/// Serialize a value as the only type of a signature, where a tuple of a
+/// single element is written as its element.
+pub(crate) fn serialize_single<W, T>(
+    marshaller: &mut Marshaller<W>,
+    ty: &SignatureType,
+    config: Config,
+    variants: Option<&VariantTypes>,
+    value: &T,
+) -> Result<()>
+where
+    W: io::Write,
+    T: Serialize + ?Sized,
+{
+    if let SignatureType::Variant = ty {
+        return serialize(marshaller, ty, config, variants, value);
+    }
+
+    marshaller.write_padding(ty.alignment())?;
+    let serializer = Serializer::new(marshaller, ty, config).single();
+    value.serialize(match variants {
+        Some(variants) => serializer.variant_types(variants),
+        None => serializer,
+    })
+}
+
+/// Write the start of the `(tag, variant)` struct of an enum variant whose
+/// data has the type `ty`.
+fn write_variant_header<W: io::Write>(marshaller: &mut Marshaller<W>, tag: &Value, ty: &SignatureType) -> Result<()> {
+    marshaller.write_padding(8)?;
+    marshaller.context_mut().struct_depth += 1;
+
+    tag.encode(marshaller)?;
+    Signature::try_from(ty.clone())?.encode(marshaller)
+}
+
+/// A serializer writing values of a known D-Bus type.
+///
+/// The output must already be aligned to the type alignment.
+pub struct Serializer<'m, 't, W> {
+    marshaller: &'m mut Marshaller<W>,
+    ty: &'t SignatureType,
+    config: Config,
+    variants: Option<&'t VariantTypes>,
+    packed: bool,
+    single: bool,
+}
+
+impl<'m, 't, W: io::Write> Serializer<'m, 't, W> {
+    pub fn new(marshaller: &'m mut Marshaller<W>, ty: &'t SignatureType, config: Config) -> Self {
+        Serializer {
+            marshaller,
+            ty,
+            config,
+            variants: None,
+            packed: false,
+            single: false,
+        }
+    }
+
+    /// Write structs without aligning them, like the fields of a message
+    /// body.
+    pub fn packed(mut self) -> Self {
+        self.packed = true;
+        self
+    }
+
+    /// Write tuples of a single element as their element, like the only
+    /// field of a message body.
+    pub fn single(mut self) -> Self {
+        self.single = true;
+        self
+    }
+
+    /// Write the data of tagged enum variants with the types found by
+    /// `variant_types_of`, instead of inferring them from the data.
+    pub fn variant_types(mut self, variants: &'t VariantTypes) -> Self {
+        self.variants = Some(variants);
+        self
+    }
+
+    fn basic<T: DBusType>(self, expected: BasicType, value: T) -> Result<()> {
+        match self.ty {
+            SignatureType::Basic(ty) if *ty == expected => value.encode(self.marshaller),
+            ty => Err(mismatch(ty, expected)),
+        }
+    }
+
+    fn begin_struct(self, len: usize) -> Result<StructSerializer<'m, 't, W>> {
+        let fields = struct_fields(self.ty, len)?;
+
+        if !self.packed {
+            self.marshaller.write_padding(8)?;
+            self.marshaller.context_mut().struct_depth += 1;
+        }
+
+        Ok(StructSerializer {
+            marshaller: self.marshaller,
+            fields,
+            index: 0,
+            config: self.config,
+            variants: self.variants,
+            depth: if self.packed { 0 } else { 1 },
+        })
+    }
+
+    /// Check the enum type and return the tag of a variant holding data,
+    /// along with the type of its data when it was traced.
+    fn variant(
+        &self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+    ) -> Result<(Value, Option<&'t SignatureType>)> {
+        let tag = variant_tag(self.config, index, variant)?;
+        if *self.ty != self.config.enums.signature_type() {
+            return Err(mismatch(self.ty, self.config.enums.signature_type()));
+        }
+
+        Ok((tag, self.variants.and_then(|variants| variants.get(name, variant))))
+    }
+
+    fn begin_variant(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<VariantSerializer<'m, 't, W>> {
+        let (tag, ty) = self.variant(name, index, variant)?;
+        // The variant data is a struct, which can't be empty.
+        if len == 0 {
+            return Err(Error::EmptyStruct);
+        }
+
+        let ty = match ty {
+            Some(ty) => ty,
+            None => {
+                return Ok(VariantSerializer(VariantData::Dynamic {
+                    marshaller: self.marshaller,
+                    tag,
+                    fields: Vec::new(),
+                    config: self.config,
+                }))
+            }
+        };
+
+        let fields = struct_fields(ty, len)?;
+        write_variant_header(self.marshaller, &tag, ty)?;
+        self.marshaller.write_padding(8)?;
+        self.marshaller.context_mut().struct_depth += 1;
+
+        Ok(VariantSerializer(VariantData::Typed(StructSerializer {
+            marshaller: self.marshaller,
+            fields,
+            index: 0,
+            config: self.config,
+            variants: self.variants,
+            depth: 2,
+        })))
+    }
+}
+
+impl<'m, 't, W: io::Write> ser::Serializer for Serializer<'m, 't, W> {
+    type Ok = ();
+    type Error = Error;
+
+    type SerializeSeq = SeqSerializer<'m, 't, W>;
+    type SerializeTuple = StructSerializer<'m, 't, W>;
+    type SerializeTupleStruct = StructSerializer<'m, 't, W>;
+    type SerializeTupleVariant = VariantSerializer<'m, 't, W>;
+    type SerializeMap = MapSerializer<'m, 't, W>;
+    type SerializeStruct = StructSerializer<'m, 't, W>;
+    type SerializeStructVariant = VariantSerializer<'m, 't, W>;
+
+    fn serialize_bool(self, v: bool) -> Result<()> {
+        self.basic(BasicType::Boolean, v)
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<()> {
+        self.basic(BasicType::Int16, i16::from(v))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<()> {
+        self.basic(BasicType::Int16, v)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<()> {
+        self.basic(BasicType::Int32, v)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<()> {
+        self.basic(BasicType::Int64, v)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<()> {
+        self.basic(BasicType::Byte, v)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<()> {
+        self.basic(BasicType::UInt16, v)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<()> {
+        // Unix fds are serialized as their index in the fd list.
+        if let SignatureType::Basic(BasicType::UnixFd) = self.ty {
+            let fd = self.marshaller.fd(v)?;
+            return self.basic(BasicType::UnixFd, fd);
+        }
+        self.basic(BasicType::UInt32, v)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<()> {
+        self.basic(BasicType::UInt64, v)
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<()> {
+        self.basic(BasicType::Double, f64::from(v))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<()> {
+        self.basic(BasicType::Double, v)
+    }
+
+    fn serialize_char(self, v: char) -> Result<()> {
+        self.basic(BasicType::String, v.to_string())
+    }
+
+    fn serialize_str(self, v: &str) -> Result<()> {
+        match self.ty {
+            SignatureType::Basic(BasicType::String) => v.encode(self.marshaller),
+            SignatureType::Basic(BasicType::ObjectPath) => ObjectPath::new(v)?.encode(self.marshaller),
+            SignatureType::Basic(BasicType::Signature) => Signature::new(v)?.encode(self.marshaller),
+            ty => Err(mismatch(ty, BasicType::String)),
+        }
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<()> {
+        match self.ty {
+            SignatureType::Array(element) if **element == SignatureType::Basic(BasicType::Byte) => {
+                self.marshaller.write_fixed_array(v)
+            }
+            ty => Err(mismatch(ty, "ay")),
+        }
+    }
+
+    fn serialize_none(self) -> Result<()> {
+        match self.ty {
+            SignatureType::Array(element) => self.marshaller.write_array(element.alignment(), |_| Ok(())),
+            ty => Err(mismatch(ty, "array")),
+        }
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<()> {
+        let (config, variants) = (self.config, self.variants);
+        match self.ty {
+            SignatureType::Array(element) => self.marshaller.write_array(element.alignment(), |marshaller| {
+                serialize(marshaller, element, config, variants, value)
+            }),
+            ty => Err(mismatch(ty, "array")),
+        }
+    }
+
+    fn serialize_unit(self) -> Result<()> {
+        self.basic(BasicType::Byte, 0u8)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<()> {
+        self.serialize_unit()
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, index: u32, variant: &'static str) -> Result<()> {
+        let expected = self.config.enums.signature_type();
+        if *self.ty != expected {
+            return Err(mismatch(self.ty, expected));
+        }
+
+        let tag = enum_tag(self.config, index, variant);
+        if self.config.enums.is_tagged() {
+            let unit = Value::Variant(Box::new(Value::Byte(0)));
+            Value::Struct(vec![tag, unit]).encode(self.marshaller)
+        } else {
+            tag.encode(self.marshaller)
+        }
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, name: &'static str, value: &T) -> Result<()> {
+        if let Some(expected) = token_type(name) {
+            let expected = SignatureType::Basic(expected);
+            if *self.ty != expected {
+                return Err(mismatch(self.ty, expected));
+            }
+            return value.serialize(self);
+        }
+
+        if self.config.newtype_as_struct {
+            let mut fields = self.begin_struct(1)?;
+            ser::SerializeTupleStruct::serialize_field(&mut fields, value)?;
+            ser::SerializeTupleStruct::end(fields)
+        } else {
+            value.serialize(self)
+        }
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        let (tag, ty) = self.variant(name, index, variant)?;
+
+        match ty {
+            Some(ty) => {
+                write_variant_header(self.marshaller, &tag, ty)?;
+                serialize(self.marshaller, ty, self.config, self.variants, value)?;
+                self.marshaller.context_mut().struct_depth -= 1;
+                Ok(())
+            }
+            None => {
+                let value = to_value_with_fds(value, self.config, self.marshaller.fds())?;
+                Value::Struct(vec![tag, Value::Variant(Box::new(value))]).encode(self.marshaller)
+            }
+        }
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
+        match self.ty {
+            SignatureType::Array(element) => Ok(SeqSerializer {
+                inner: self.marshaller.begin_array(element.alignment()),
+                marshaller: self.marshaller,
+                element,
+                config: self.config,
+                variants: self.variants,
+            }),
+            ty => Err(mismatch(ty, "array")),
+        }
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple> {
+        if self.single && len == 1 {
+            return Ok(StructSerializer {
+                marshaller: self.marshaller,
+                fields: vec![Cow::Borrowed(self.ty)],
+                index: 0,
+                config: self.config,
+                variants: self.variants,
+                depth: 0,
+            });
+        }
+
+        self.begin_struct(len)
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<Self::SerializeTupleStruct> {
+        self.begin_struct(len)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleVariant> {
+        self.begin_variant(name, index, variant, len)
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
+        match self.ty {
+            SignatureType::Array(element) => match **element {
+                SignatureType::DictEntry(key, ref value) => Ok(MapSerializer {
+                    inner: self.marshaller.begin_array(8),
+                    marshaller: self.marshaller,
+                    key: SignatureType::Basic(key),
+                    value,
+                    config: self.config,
+                    variants: self.variants,
+                }),
+                _ => Err(mismatch(self.ty, "dict")),
+            },
+            ty => Err(mismatch(ty, "dict")),
+        }
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<Self::SerializeStruct> {
+        self.begin_struct(len)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStructVariant> {
+        self.begin_variant(name, index, variant, len)
+    }
+}
+
+pub struct SeqSerializer<'m, 't, W> {
+    marshaller: &'m mut Marshaller<W>,
+    inner: Marshaller<Vec<u8>>,
+    element: &'t SignatureType,
+    config: Config,
+    variants: Option<&'t VariantTypes>,
+}
+
+impl<'m, 't, W: io::Write> ser::SerializeSeq for SeqSerializer<'m, 't, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        serialize(&mut self.inner, self.element, self.config, self.variants, value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.marshaller.end_array(self.element.alignment(), self.inner)
+    }
+}
+
+pub struct StructSerializer<'m, 't, W> {
+    marshaller: &'m mut Marshaller<W>,
+    fields: Vec<Cow<'t, SignatureType>>,
+    index: usize,
+    config: Config,
+    variants: Option<&'t VariantTypes>,
+    /// The number of nested structs to leave once the fields are written.
+    depth: usize,
+}
+
+impl<'m, 't, W: io::Write> StructSerializer<'m, 't, W> {
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        let ty = self.fields.get(self.index).ok_or_else(|| Error::Custom {
+            message: "Too many struct fields".into(),
+        })?;
+        self.index += 1;
+
+        serialize(self.marshaller, ty, self.config, self.variants, value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.marshaller.context_mut().struct_depth -= self.depth;
+
+        if self.index != self.fields.len() {
+            return Err(Error::Custom {
+                message: format!("Expected {} struct fields, found {}", self.fields.len(), self.index),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+impl<'m, 't, W: io::Write> ser::SerializeTuple for StructSerializer<'m, 't, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.serialize_field(value)
+    }
+
+    fn end(self) -> Result<()> {
+        StructSerializer::end(self)
+    }
+}
+
+impl<'m, 't, W: io::Write> ser::SerializeTupleStruct for StructSerializer<'m, 't, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        StructSerializer::serialize_field(self, value)
+    }
+
+    fn end(self) -> Result<()> {
+        StructSerializer::end(self)
+    }
+}
+
+impl<'m, 't, W: io::Write> ser::SerializeStruct for StructSerializer<'m, 't, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, _key: &'static str, value: &T) -> Result<()> {
+        StructSerializer::serialize_field(self, value)
+    }
+
+    fn end(self) -> Result<()> {
+        StructSerializer::end(self)
+    }
+}
+
+pub struct MapSerializer<'m, 't, W> {
+    marshaller: &'m mut Marshaller<W>,
+    inner: Marshaller<Vec<u8>>,
+    key: SignatureType,
+    value: &'t SignatureType,
+    config: Config,
+    variants: Option<&'t VariantTypes>,
+}
+
+impl<'m, 't, W: io::Write> ser::SerializeMap for MapSerializer<'m, 't, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<()> {
+        self.inner.write_padding(8)?;
+        serialize(&mut self.inner, &self.key, self.config, self.variants, key)
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        serialize(&mut self.inner, self.value, self.config, self.variants, value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.marshaller.end_array(8, self.inner)
+    }
+}
+
+/// Enum variants holding data are written as a `(tag, variant)` struct.
+///
+/// The variant data is written as it comes when its type was traced,
+/// otherwise it is collected as a dynamic value first to infer its type.
+pub struct VariantSerializer<'m, 't, W>(VariantData<'m, 't, W>);
+
+enum VariantData<'m, 't, W> {
+    Typed(StructSerializer<'m, 't, W>),
+    Dynamic {
+        marshaller: &'m mut Marshaller<W>,
+        tag: Value,
+        fields: Vec<Value>,
+        config: Config,
+    },
+}
+
+impl<'m, 't, W: io::Write> VariantSerializer<'m, 't, W> {
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        match &mut self.0 {
+            VariantData::Typed(fields) => fields.serialize_field(value),
+            VariantData::Dynamic {
+                marshaller,
+                fields,
+                config,
+                ..
+            } => {
+                fields.push(to_value_with_fds(value, *config, marshaller.fds())?);
+                Ok(())
+            }
+        }
+    }
+
+    fn end(self) -> Result<()> {
+        match self.0 {
+            VariantData::Typed(fields) => fields.end(),
+            VariantData::Dynamic {
+                marshaller,
+                tag,
+                fields,
+                ..
+            } => {
+                let data = Value::Variant(Box::new(Value::Struct(fields)));
+                Value::Struct(vec![tag, data]).encode(marshaller)
+            }
+        }
+    }
+}
+
+impl<'m, 't, W: io::Write> ser::SerializeTupleVariant for VariantSerializer<'m, 't, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        VariantSerializer::serialize_field(self, value)
+    }
+
+    fn end(self) -> Result<()> {
+        VariantSerializer::end(self)
+    }
+}
+
+impl<'m, 't, W: io::Write> ser::SerializeStructVariant for VariantSerializer<'m, 't, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, _key: &'static str, value: &T) -> Result<()> {
+        VariantSerializer::serialize_field(self, value)
+    }
+
+    fn end(self) -> Result<()> {
+        VariantSerializer::end(self)
+    }
+}
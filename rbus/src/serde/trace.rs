@@ -0,0 +1,545 @@
+use super::{token_type, Config, EnumEncoding};
+use crate::{
+    marshal::MAX_TOTAL_DEPTH,
+    types::{BasicType, Signature, SignatureType},
+    Error, Result,
+};
+use serde::de::{
+    self,
+    value::{StrDeserializer, U32Deserializer},
+    DeserializeOwned, DeserializeSeed, IntoDeserializer, Visitor,
+};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    mem,
+};
+
+/// The types of the data held by the variants of tagged enums, which isn't
+/// part of the enum signature since it is held in a variant.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VariantTypes {
+    types: HashMap<(&'static str, &'static str), SignatureType>,
+}
+
+impl VariantTypes {
+    /// Return the type of the data of the variant `variant` of the enum
+    /// `name`, if it holds data.
+    pub fn get(&self, name: &'static str, variant: &'static str) -> Option<&SignatureType> {
+        self.types.get(&(name, variant))
+    }
+}
+
+/// Compute the D-Bus signature of a type, with the default configuration.
+pub fn signature_of<T: DeserializeOwned>() -> Result<Signature> {
+    signature_of_with_config::<T>(Config::default())
+}
+
+/// Compute the D-Bus signature of a type.
+///
+/// The signature is found by deserializing a dummy value of `T`, recording
+/// the types requested along the way. Sequences and maps are traced with a
+/// single element and enums with a single variant, so recursive types fail
+/// with `Error::NestingTooDeep`.
+pub fn signature_of_with_config<T: DeserializeOwned>(config: Config) -> Result<Signature> {
+    let state = State::new(config);
+    let ty = trace::<T>(&state)?;
+
+    // The fields of a tuple holding the whole value are packed.
+    match ty {
+        SignatureType::Struct(fields) if state.tuple.get() => {
+            Ok(Signature::new(fields.iter().map(SignatureType::signature).collect::<String>())?)
+        }
+        ty => Ok(Signature::new(ty.signature())?),
+    }
+}
+
+/// Compute the types of the data of the tagged enum variants a type
+/// contains, by tracing it once per variant.
+pub fn variant_types_of<T: DeserializeOwned>(config: Config) -> Result<VariantTypes> {
+    let state = State::new(config);
+
+    // Every pass traces the variants not traced yet, until none is left.
+    loop {
+        trace::<T>(&state)?;
+        if !mem::replace(&mut state.inner.borrow_mut().new_variants, false) {
+            break;
+        }
+    }
+
+    Ok(VariantTypes {
+        types: state.inner.into_inner().types,
+    })
+}
+
+fn trace<T: DeserializeOwned>(state: &State) -> Result<SignatureType> {
+    let mut ty = None;
+    T::deserialize(Tracer { ty: &mut ty, state })?;
+    ty.ok_or_else(|| unknown_type("type"))
+}
+
+fn unknown_type(what: &str) -> Error {
+    Error::Custom {
+        message: format!("Can't infer the D-Bus type of {}", what),
+    }
+}
+
+/// The state shared by the tracers of a type.
+struct State {
+    config: Config,
+    inner: RefCell<Variants>,
+    /// The number of containers being traced.
+    depth: Cell<usize>,
+    /// Whether the traced type is a tuple.
+    tuple: Cell<bool>,
+}
+
+#[derive(Default)]
+struct Variants {
+    /// The index of the next variant to trace, for each enum.
+    next: HashMap<&'static str, usize>,
+    types: HashMap<(&'static str, &'static str), SignatureType>,
+    /// Whether variants not traced before were traced during this pass.
+    new_variants: bool,
+}
+
+impl State {
+    fn new(config: Config) -> State {
+        State {
+            config,
+            inner: RefCell::new(Variants::default()),
+            depth: Cell::new(0),
+            tuple: Cell::new(false),
+        }
+    }
+
+    /// Trace the contents of a container.
+    fn nested<T, F: FnOnce() -> Result<T>>(&self, trace: F) -> Result<T> {
+        if self.depth.get() >= MAX_TOTAL_DEPTH {
+            return Err(Error::NestingTooDeep);
+        }
+
+        self.depth.set(self.depth.get() + 1);
+        let result = trace();
+        self.depth.set(self.depth.get() - 1);
+        result
+    }
+
+    /// Return the index of the variant of the enum `name` to trace.
+    fn choose_variant(&self, name: &'static str, variants: &'static [&'static str]) -> usize {
+        let mut inner = self.inner.borrow_mut();
+        let inner = &mut *inner;
+        let next = inner.next.entry(name).or_insert(0);
+        if *next >= variants.len() {
+            return 0;
+        }
+
+        *next += 1;
+        inner.new_variants = true;
+        *next - 1
+    }
+
+    fn record_variant(&self, name: &'static str, variant: &'static str, ty: Option<SignatureType>) {
+        if let Some(ty) = ty {
+            self.inner.borrow_mut().types.insert((name, variant), ty);
+        }
+    }
+}
+
+/// A deserializer producing dummy values, recording the type of the value
+/// it was asked for in `ty`.
+struct Tracer<'a> {
+    ty: &'a mut Option<SignatureType>,
+    state: &'a State,
+}
+
+impl<'a> Tracer<'a> {
+    fn basic(self, ty: BasicType) {
+        *self.ty = Some(SignatureType::Basic(ty));
+    }
+}
+
+impl<'de, 'a> de::Deserializer<'de> for Tracer<'a> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value> {
+        Err(unknown_type("self-describing values"))
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.basic(BasicType::Boolean);
+        visitor.visit_bool(false)
+    }
+
+    fn deserialize_i8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.basic(BasicType::Int16);
+        visitor.visit_i8(0)
+    }
+
+    fn deserialize_i16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.basic(BasicType::Int16);
+        visitor.visit_i16(0)
+    }
+
+    fn deserialize_i32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.basic(BasicType::Int32);
+        visitor.visit_i32(0)
+    }
+
+    fn deserialize_i64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.basic(BasicType::Int64);
+        visitor.visit_i64(0)
+    }
+
+    fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.basic(BasicType::Byte);
+        visitor.visit_u8(0)
+    }
+
+    fn deserialize_u16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.basic(BasicType::UInt16);
+        visitor.visit_u16(0)
+    }
+
+    fn deserialize_u32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.basic(BasicType::UInt32);
+        visitor.visit_u32(0)
+    }
+
+    fn deserialize_u64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.basic(BasicType::UInt64);
+        visitor.visit_u64(0)
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.basic(BasicType::Double);
+        visitor.visit_f32(0.0)
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.basic(BasicType::Double);
+        visitor.visit_f64(0.0)
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.basic(BasicType::String);
+        visitor.visit_char('\0')
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.basic(BasicType::String);
+        visitor.visit_str("")
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        *self.ty = Some(SignatureType::Array(Box::new(BasicType::Byte.into())));
+        visitor.visit_bytes(&[])
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let mut element = None;
+        let state = self.state;
+        let value = state.nested(|| {
+            visitor.visit_some(Tracer {
+                ty: &mut element,
+                state,
+            })
+        })?;
+
+        let element = element.ok_or_else(|| unknown_type("an option"))?;
+        *self.ty = Some(SignatureType::Array(Box::new(element)));
+        Ok(value)
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.basic(BasicType::Byte);
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, name: &'static str, visitor: V) -> Result<V::Value> {
+        if let Some(ty) = token_type(name) {
+            self.basic(ty);
+            return match ty {
+                BasicType::ObjectPath => {
+                    let path: StrDeserializer<Error> = "/".into_deserializer();
+                    visitor.visit_newtype_struct(path)
+                }
+                BasicType::Signature => {
+                    let signature: StrDeserializer<Error> = "".into_deserializer();
+                    visitor.visit_newtype_struct(signature)
+                }
+                _ => {
+                    let fd: U32Deserializer<Error> = 0u32.into_deserializer();
+                    visitor.visit_newtype_struct(fd)
+                }
+            };
+        }
+
+        if self.state.config.newtype_as_struct {
+            let mut fields = vec![None];
+            let state = self.state;
+            let value = state.nested(|| visitor.visit_seq(FieldsTracer::new(&mut fields, state)))?;
+            *self.ty = Some(collect_fields(fields)?);
+            Ok(value)
+        } else {
+            visitor.visit_newtype_struct(self)
+        }
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let mut element = vec![None];
+        let state = self.state;
+        let value = state.nested(|| visitor.visit_seq(FieldsTracer::new(&mut element, state)))?;
+
+        let element = element.remove(0).ok_or_else(|| unknown_type("a sequence"))?;
+        *self.ty = Some(SignatureType::Array(Box::new(element)));
+        Ok(value)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value> {
+        if self.state.depth.get() == 0 {
+            self.state.tuple.set(true);
+        }
+        self.deserialize_tuple_struct("", len, visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value> {
+        let mut fields = vec![None; len];
+        let state = self.state;
+        let value = state.nested(|| visitor.visit_seq(FieldsTracer::new(&mut fields, state)))?;
+
+        *self.ty = Some(collect_fields(fields)?);
+        Ok(value)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let mut key = None;
+        let mut value = None;
+        let state = self.state;
+        let result = state.nested(|| {
+            visitor.visit_map(EntryTracer {
+                key: &mut key,
+                value: &mut value,
+                state,
+                done: false,
+            })
+        })?;
+
+        let key = match key.ok_or_else(|| unknown_type("a map key"))? {
+            SignatureType::Basic(key) => key,
+            key => {
+                return Err(Error::Custom {
+                    message: format!("Dict keys must be basic types, found `{}`", key),
+                })
+            }
+        };
+        let value = value.ok_or_else(|| unknown_type("a map value"))?;
+
+        *self.ty = Some(SignatureType::Array(Box::new(SignatureType::DictEntry(
+            key,
+            Box::new(value),
+        ))));
+        Ok(result)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.deserialize_tuple_struct("", fields.len(), visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        let config = self.state.config;
+        *self.ty = Some(config.enums.signature_type());
+
+        match config.enums {
+            EnumEncoding::Index | EnumEncoding::Name => {
+                let index: U32Deserializer<Error> = 0u32.into_deserializer();
+                visitor.visit_enum(index)
+            }
+            EnumEncoding::TaggedIndex | EnumEncoding::TaggedName => {
+                let index = self.state.choose_variant(name, variants);
+                visitor.visit_enum(VariantTracer {
+                    state: self.state,
+                    name,
+                    variant: variants.get(index).cloned().unwrap_or_default(),
+                    index: index as u32,
+                })
+            }
+        }
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_u32(0)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_unit()
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+fn collect_fields(fields: Vec<Option<SignatureType>>) -> Result<SignatureType> {
+    fields
+        .into_iter()
+        .collect::<Option<Vec<_>>>()
+        .map(SignatureType::Struct)
+        .ok_or_else(|| unknown_type("a struct field"))
+}
+
+/// Trace the fields of a struct, or the element of a sequence.
+struct FieldsTracer<'a> {
+    fields: std::slice::IterMut<'a, Option<SignatureType>>,
+    state: &'a State,
+}
+
+impl<'a> FieldsTracer<'a> {
+    fn new(fields: &'a mut [Option<SignatureType>], state: &'a State) -> FieldsTracer<'a> {
+        FieldsTracer {
+            fields: fields.iter_mut(),
+            state,
+        }
+    }
+}
+
+impl<'de, 'a> de::SeqAccess<'de> for FieldsTracer<'a> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>> {
+        match self.fields.next() {
+            Some(ty) => seed.deserialize(Tracer { ty, state: self.state }).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.fields.len())
+    }
+}
+
+/// Trace a single map entry.
+struct EntryTracer<'a> {
+    key: &'a mut Option<SignatureType>,
+    value: &'a mut Option<SignatureType>,
+    state: &'a State,
+    done: bool,
+}
+
+impl<'de, 'a> de::MapAccess<'de> for EntryTracer<'a> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        if self.done {
+            return Ok(None);
+        }
+        self.done = true;
+
+        seed.deserialize(Tracer {
+            ty: &mut *self.key,
+            state: self.state,
+        })
+        .map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+        seed.deserialize(Tracer {
+            ty: &mut *self.value,
+            state: self.state,
+        })
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(1)
+    }
+}
+
+/// Trace a variant of a tagged enum, recording the type of its data.
+struct VariantTracer<'a> {
+    state: &'a State,
+    name: &'static str,
+    variant: &'static str,
+    index: u32,
+}
+
+impl<'de, 'a> de::EnumAccess<'de> for VariantTracer<'a> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self)> {
+        let index: U32Deserializer<Error> = self.index.into_deserializer();
+        Ok((seed.deserialize(index)?, self))
+    }
+}
+
+impl<'de, 'a> de::VariantAccess<'de> for VariantTracer<'a> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<()> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value> {
+        let mut ty = None;
+        let state = self.state;
+        let value = state.nested(|| seed.deserialize(Tracer { ty: &mut ty, state }))?;
+
+        self.state.record_variant(self.name, self.variant, ty);
+        Ok(value)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value> {
+        let mut ty = None;
+        let tracer = Tracer {
+            ty: &mut ty,
+            state: self.state,
+        };
+        let value = self
+            .state
+            .nested(|| de::Deserializer::deserialize_tuple_struct(tracer, "", len, visitor))?;
+
+        self.state.record_variant(self.name, self.variant, ty);
+        Ok(value)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(self, fields: &'static [&'static str], visitor: V) -> Result<V::Value> {
+        let mut ty = None;
+        let tracer = Tracer {
+            ty: &mut ty,
+            state: self.state,
+        };
+        let value = self
+            .state
+            .nested(|| de::Deserializer::deserialize_struct(tracer, "", fields, visitor))?;
+
+        self.state.record_variant(self.name, self.variant, ty);
+        Ok(value)
+    }
+}
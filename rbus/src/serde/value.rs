@@ -0,0 +1,390 @@
+use super::{enum_tag, variant_tag, Config, OBJECT_PATH_TOKEN, SIGNATURE_TOKEN, UNIX_FD_TOKEN};
+use crate::{
+    types::{ObjectPath, Signature, SignatureType, UnixFd, Value},
+    Error, Result,
+};
+use serde::ser::{self, Serialize};
+
+/// Convert a value to a dynamically typed `Value`.
+///
+/// The D-Bus type is inferred from the value itself, so empty sequences,
+/// empty maps and `None` can't be converted. There is no fd list to look
+/// `UnixFdIndex` values up in either, so they can't be converted.
+pub fn to_value<T: Serialize + ?Sized>(value: &T, config: Config) -> Result<Value> {
+    to_value_with_fds(value, config, &[])
+}
+
+/// Convert a value to a dynamically typed `Value`, looking `UnixFdIndex`
+/// values up in `fds`.
+pub(crate) fn to_value_with_fds<T: Serialize + ?Sized>(value: &T, config: Config, fds: &[UnixFd]) -> Result<Value> {
+    value.serialize(ValueSerializer { config, fds })
+}
+
+fn unknown_type(what: &str) -> Error {
+    Error::Custom {
+        message: format!("Can't infer the D-Bus type of an empty {}", what),
+    }
+}
+
+fn array(values: Vec<Value>) -> Result<Value> {
+    let ty = match values.first() {
+        Some(value) => value.signature_type(),
+        None => return Err(unknown_type("sequence")),
+    };
+
+    if values.iter().any(|value| value.signature_type() != ty) {
+        return Err(Error::Custom {
+            message: "Sequence elements don't all have the same D-Bus type".into(),
+        });
+    }
+
+    Ok(Value::Array { ty, values })
+}
+
+#[derive(Clone, Copy)]
+struct ValueSerializer<'f> {
+    config: Config,
+    fds: &'f [UnixFd],
+}
+
+impl<'f> ser::Serializer for ValueSerializer<'f> {
+    type Ok = Value;
+    type Error = Error;
+
+    type SerializeSeq = SeqSerializer<'f>;
+    type SerializeTuple = SeqSerializer<'f>;
+    type SerializeTupleStruct = SeqSerializer<'f>;
+    type SerializeTupleVariant = SeqSerializer<'f>;
+    type SerializeMap = MapSerializer<'f>;
+    type SerializeStruct = SeqSerializer<'f>;
+    type SerializeStructVariant = SeqSerializer<'f>;
+
+    fn serialize_bool(self, v: bool) -> Result<Value> {
+        Ok(Value::Boolean(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Value> {
+        Ok(Value::Int16(i16::from(v)))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Value> {
+        Ok(Value::Int16(v))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Value> {
+        Ok(Value::Int32(v))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Value> {
+        Ok(Value::Int64(v))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Value> {
+        Ok(Value::Byte(v))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Value> {
+        Ok(Value::UInt16(v))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Value> {
+        Ok(Value::UInt32(v))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Value> {
+        Ok(Value::UInt64(v))
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Value> {
+        Ok(Value::Double(f64::from(v)))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Value> {
+        Ok(Value::Double(v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Value> {
+        Ok(Value::String(v.to_string()))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Value> {
+        Ok(Value::String(v.into()))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Value> {
+        Ok(Value::Array {
+            ty: SignatureType::Basic(crate::types::BasicType::Byte),
+            values: v.iter().cloned().map(Value::Byte).collect(),
+        })
+    }
+
+    fn serialize_none(self) -> Result<Value> {
+        Err(unknown_type("option"))
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Value> {
+        array(vec![value.serialize(self)?])
+    }
+
+    fn serialize_unit(self) -> Result<Value> {
+        Ok(Value::Byte(0))
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Value> {
+        self.serialize_unit()
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, index: u32, variant: &'static str) -> Result<Value> {
+        let tag = enum_tag(self.config, index, variant);
+        if self.config.enums.is_tagged() {
+            Ok(Value::Struct(vec![tag, Value::Variant(Box::new(Value::Byte(0)))]))
+        } else {
+            Ok(tag)
+        }
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, name: &'static str, value: &T) -> Result<Value> {
+        let (config, fds) = (self.config, self.fds);
+        let value = value.serialize(self)?;
+
+        let value = match (name, value) {
+            (OBJECT_PATH_TOKEN, Value::String(path)) => Value::ObjectPath(ObjectPath::new(path)?),
+            (SIGNATURE_TOKEN, Value::String(sig)) => Value::Signature(Signature::new(sig)?),
+            (UNIX_FD_TOKEN, Value::UInt32(index)) => {
+                let count = fds.len();
+                let fd = fds.get(index as usize).ok_or(Error::InvalidUnixFd { index, count })?;
+                Value::UnixFd(fd.clone())
+            }
+            (_, value) if config.newtype_as_struct => Value::Struct(vec![value]),
+            (_, value) => value,
+        };
+
+        Ok(value)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Value> {
+        let tag = variant_tag(self.config, index, variant)?;
+        let value = value.serialize(self)?;
+        Ok(Value::Struct(vec![tag, Value::Variant(Box::new(value))]))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SeqSerializer<'f>> {
+        Ok(SeqSerializer::new(self, len.unwrap_or(0)))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SeqSerializer<'f>> {
+        Ok(SeqSerializer::new(self, len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<SeqSerializer<'f>> {
+        Ok(SeqSerializer::new(self, len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SeqSerializer<'f>> {
+        SeqSerializer::variant(self, index, variant, len)
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<MapSerializer<'f>> {
+        Ok(MapSerializer {
+            serializer: self,
+            entries: Vec::new(),
+            key: None,
+        })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<SeqSerializer<'f>> {
+        Ok(SeqSerializer::new(self, len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SeqSerializer<'f>> {
+        SeqSerializer::variant(self, index, variant, len)
+    }
+}
+
+struct SeqSerializer<'f> {
+    serializer: ValueSerializer<'f>,
+    values: Vec<Value>,
+    /// The variant tag, for enum variants.
+    tag: Option<Value>,
+}
+
+impl<'f> SeqSerializer<'f> {
+    fn new(serializer: ValueSerializer<'f>, len: usize) -> SeqSerializer<'f> {
+        SeqSerializer {
+            serializer,
+            values: Vec::with_capacity(len),
+            tag: None,
+        }
+    }
+
+    fn variant(
+        serializer: ValueSerializer<'f>,
+        index: u32,
+        name: &'static str,
+        len: usize,
+    ) -> Result<SeqSerializer<'f>> {
+        // The variant data is a struct, which can't be empty.
+        if len == 0 {
+            return Err(Error::EmptyStruct);
+        }
+
+        Ok(SeqSerializer {
+            tag: Some(variant_tag(serializer.config, index, name)?),
+            serializer,
+            values: Vec::new(),
+        })
+    }
+
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.values.push(value.serialize(self.serializer)?);
+        Ok(())
+    }
+
+    fn into_variant(self) -> Value {
+        let tag = self.tag.expect("Not an enum variant");
+        let data = Value::Struct(self.values);
+
+        Value::Struct(vec![tag, Value::Variant(Box::new(data))])
+    }
+}
+
+impl<'f> ser::SerializeSeq for SeqSerializer<'f> {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value> {
+        array(self.values)
+    }
+}
+
+impl<'f> ser::SerializeTuple for SeqSerializer<'f> {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value> {
+        Ok(Value::Struct(self.values))
+    }
+}
+
+impl<'f> ser::SerializeTupleStruct for SeqSerializer<'f> {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value> {
+        Ok(Value::Struct(self.values))
+    }
+}
+
+impl<'f> ser::SerializeStruct for SeqSerializer<'f> {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, _key: &'static str, value: &T) -> Result<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value> {
+        Ok(Value::Struct(self.values))
+    }
+}
+
+impl<'f> ser::SerializeTupleVariant for SeqSerializer<'f> {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value> {
+        Ok(self.into_variant())
+    }
+}
+
+impl<'f> ser::SerializeStructVariant for SeqSerializer<'f> {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, _key: &'static str, value: &T) -> Result<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value> {
+        Ok(self.into_variant())
+    }
+}
+
+struct MapSerializer<'f> {
+    serializer: ValueSerializer<'f>,
+    entries: Vec<(Value, Value)>,
+    key: Option<Value>,
+}
+
+impl<'f> ser::SerializeMap for MapSerializer<'f> {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<()> {
+        self.key = Some(key.serialize(self.serializer)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        let key = self.key.take().ok_or_else(|| Error::Custom {
+            message: "Map value serialized before its key".into(),
+        })?;
+        self.entries.push((key, value.serialize(self.serializer)?));
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value> {
+        let (key, value) = match self.entries.first() {
+            Some((key, value)) => (key.signature_type(), value.signature_type()),
+            None => return Err(unknown_type("map")),
+        };
+        let key = match key {
+            SignatureType::Basic(key) => key,
+            key => {
+                return Err(Error::Custom {
+                    message: format!("Dict keys must be basic types, found `{}`", key),
+                })
+            }
+        };
+
+        Ok(Value::Dict {
+            key,
+            value,
+            entries: self.entries,
+        })
+    }
+}
@@ -1,5 +1,27 @@
 use super::{impl_type, DBusType};
-use std::io::prelude::*;
+use crate::{marshal::Marshaller, Result};
+use derive_more::*;
+use std::{
+    collections::{BTreeSet, HashSet, VecDeque},
+    convert::TryFrom,
+    hash::Hash,
+    io,
+};
+
+/// Encode the elements of a collection which isn't stored as a slice.
+fn encode_iter<'a, T, I, Inner>(values: I, marshaller: &mut Marshaller<Inner>) -> Result<()>
+where
+    T: DBusType + 'a,
+    I: IntoIterator<Item = &'a T>,
+    Inner: io::Write,
+{
+    marshaller.write_array(T::alignment(), |marshaller| {
+        for value in values {
+            marshaller.write_value(value)?;
+        }
+        Ok(())
+    })
+}
 
 impl_type! {
     #[dbus(align = 4, module = crate)]
@@ -9,37 +31,224 @@ impl_type! {
         }
 
         encode(marshaller) {
-            use crate::marshal::Marshaller;
+            T::encode_array(self, marshaller)
+        }
+
+        decode(marshaller) {
+            T::decode_array(marshaller)
+        }
+    }
+}
 
-            let mut inner = Marshaller::new(Vec::new(), marshaller.endianness);
-            for value in self.iter() {
-                inner.write_value(value)?;
-            }
-            let data = inner.into_inner();
+impl_type! {
+    #[dbus(align = 4, module = crate)]
+    impl<T: DBusType> &[T]: 'a' {
+        signature() {
+            <Vec<T>>::signature()
+        }
 
-            marshaller.io().write_u32(data.len() as u32)?;
-            marshaller.write_padding(T::alignment())?;
-            marshaller.io().write_all(&data)?;
-            Ok(())
+        encode(marshaller) {
+            T::encode_array(self, marshaller)
+        }
+
+        decode(_marshaller) {
+            use crate::Error;
+
+            Err(Error::Custom {
+                message: "References cannot be decoded, use `DBusBorrowType` instead".into(),
+            })
+        }
+    }
+}
+
+impl_type! {
+    #[dbus(align = 4, module = crate)]
+    impl<T: DBusType, const N: usize> [T; N]: 'a' {
+        signature() {
+            <Vec<T>>::signature()
+        }
+
+        encode(marshaller) {
+            T::encode_array(self, marshaller)
         }
 
         decode(marshaller) {
-            use crate::marshal::Marshaller;
+            use crate::Error;
 
-            let length = marshaller.io().read_u32()?;
-            marshaller.read_padding(T::alignment())?;
+            let values = T::decode_array(marshaller)?;
+            let found = values.len();
+            <[T; N]>::try_from(values).map_err(|_| Error::FixedArrayLengthMismatch { expected: N, found })
+        }
+    }
+}
+
+impl_type! {
+    #[dbus(align = 4, module = crate)]
+    impl<T: DBusType> VecDeque<T>: 'a' {
+        signature() {
+            <Vec<T>>::signature()
+        }
+
+        encode(marshaller) {
+            encode_iter(self, marshaller)
+        }
+
+        decode(marshaller) {
+            T::decode_array(marshaller).map(VecDeque::from)
+        }
+    }
+}
 
-            let mut data = vec![0; length as usize];
-            marshaller.io().read_exact(&mut data)?;
+// Sets are sent as arrays, duplicated elements are merged when decoding.
+impl_type! {
+    #[dbus(align = 4, module = crate)]
+    impl<T: DBusType + Eq + Hash> HashSet<T>: 'a' {
+        signature() {
+            <Vec<T>>::signature()
+        }
 
-            let mut values = vec![];
-            let mut inner = Marshaller::new(data.as_slice(), marshaller.endianness);
-            while !inner.is_empty() {
-                let value = inner.read_value()?;
-                values.push(value);
-            }
+        encode(marshaller) {
+            encode_iter(self, marshaller)
+        }
 
-            Ok(values)
+        decode(marshaller) {
+            Ok(T::decode_array(marshaller)?.into_iter().collect())
         }
     }
 }
+
+impl_type! {
+    #[dbus(align = 4, module = crate)]
+    impl<T: DBusType + Ord> BTreeSet<T>: 'a' {
+        signature() {
+            <Vec<T>>::signature()
+        }
+
+        encode(marshaller) {
+            encode_iter(self, marshaller)
+        }
+
+        decode(marshaller) {
+            Ok(T::decode_array(marshaller)?.into_iter().collect())
+        }
+    }
+}
+
+/// An owned byte array (`ay`).
+///
+/// `Vec<u8>` has the same encoding, this type makes the intent explicit and
+/// is serialized as bytes rather than as a sequence with serde.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Deref, DerefMut)]
+pub struct ByteBuf(Vec<u8>);
+
+impl ByteBuf {
+    pub fn new() -> ByteBuf {
+        ByteBuf(Vec::new())
+    }
+
+    pub fn into_vec(self) -> Vec<u8> {
+        self.0
+    }
+}
+
+impl From<Vec<u8>> for ByteBuf {
+    fn from(data: Vec<u8>) -> ByteBuf {
+        ByteBuf(data)
+    }
+}
+
+impl From<&[u8]> for ByteBuf {
+    fn from(data: &[u8]) -> ByteBuf {
+        ByteBuf(data.to_vec())
+    }
+}
+
+impl From<ByteBuf> for Vec<u8> {
+    fn from(data: ByteBuf) -> Vec<u8> {
+        data.0
+    }
+}
+
+impl_type! {
+    #[dbus(align = 4, module = crate)]
+    ByteBuf: 'a' {
+        signature() {
+            "ay".into()
+        }
+
+        encode(marshaller) {
+            marshaller.write_fixed_array(&self.0)
+        }
+
+        decode(marshaller) {
+            marshaller.read_fixed_array().map(ByteBuf)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        marshal::{Endianness, Marshaller},
+        Error,
+    };
+
+    fn roundtrip<T: DBusType + PartialEq + std::fmt::Debug>(value: T, endianness: Endianness, bytes: &[u8]) {
+        let mut marshaller = Marshaller::new(Vec::new(), endianness);
+        marshaller.write_value(&value).unwrap();
+        assert_eq!(marshaller.into_inner(), bytes);
+
+        let mut marshaller = Marshaller::new(bytes, endianness);
+        assert_eq!(marshaller.read_value::<T>().unwrap(), value);
+        assert!(marshaller.is_empty());
+
+        let mut marshaller = Marshaller::from_slice(bytes, endianness);
+        assert_eq!(marshaller.read_value::<T>().unwrap(), value);
+    }
+
+    #[test]
+    fn test_fixed_arrays() {
+        roundtrip(vec![1u8, 2, 3], Endianness::Little, &[3, 0, 0, 0, 1, 2, 3]);
+        roundtrip(ByteBuf::from(vec![1, 2, 3]), Endianness::Big, &[0, 0, 0, 3, 1, 2, 3]);
+        roundtrip(vec![1u32, 2], Endianness::Little, &[8, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0]);
+        roundtrip(vec![1u32, 2], Endianness::Big, &[0, 0, 0, 8, 0, 0, 0, 1, 0, 0, 0, 2]);
+        roundtrip(vec![-2i16, 3], Endianness::Big, &[0, 0, 0, 4, 0xff, 0xfe, 0, 3]);
+        roundtrip(
+            vec![1.5f64],
+            Endianness::Big,
+            &[0, 0, 0, 8, 0, 0, 0, 0, 0x3f, 0xf8, 0, 0, 0, 0, 0, 0],
+        );
+        roundtrip(Vec::<u64>::new(), Endianness::Little, &[0, 0, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_collections() {
+        let bytes: &[u8] = &[8, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0];
+        roundtrip([1u32, 2], Endianness::Little, bytes);
+        roundtrip(VecDeque::from(vec![1u32, 2]), Endianness::Little, bytes);
+        roundtrip(vec![1u32, 2].into_iter().collect::<BTreeSet<_>>(), Endianness::Little, bytes);
+        roundtrip(vec![2u32].into_iter().collect::<HashSet<_>>(), Endianness::Little, &[4, 0, 0, 0, 2, 0, 0, 0]);
+        assert_eq!(<[u32; 2]>::signature(), "au");
+
+        let mut marshaller = Marshaller::new(Vec::new(), Endianness::Little);
+        marshaller.write_value(&&[1u32, 2][..]).unwrap();
+        assert_eq!(marshaller.into_inner(), bytes);
+
+        let mut marshaller = Marshaller::new(bytes, Endianness::Little);
+        assert_err_matches!(
+            marshaller.read_value::<[u32; 3]>(),
+            Error::FixedArrayLengthMismatch { expected: 3, found: 2 }
+        );
+    }
+
+    #[test]
+    fn test_fixed_array_length_mismatch() {
+        let bytes: &[u8] = &[6, 0, 0, 0, 1, 0, 0, 0, 2, 0];
+        let mut marshaller = Marshaller::new(bytes, Endianness::Little);
+        assert_err_matches!(
+            marshaller.read_value::<Vec<u32>>(),
+            Error::ArrayElementSizeMismatch { length: 6, size: 4 }
+        );
+    }
+}
@@ -1,4 +1,13 @@
 use rbus_derive::impl_basic_type;
+use std::{
+    fmt,
+    fs::File,
+    io,
+    mem::ManuallyDrop,
+    num::{NonZeroI16, NonZeroI32, NonZeroI64, NonZeroU16, NonZeroU32, NonZeroU64, NonZeroU8},
+    os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd},
+    sync::Arc,
+};
 
 impl_basic_type! {
     #[dbus(align = "size")]
@@ -13,8 +22,14 @@ impl_basic_type! {
         }
 
         decode(marshaller) {
-            let value = marshaller.io().read_u32()?;
-            Ok(value != 0)
+            use crate::Error;
+
+            match marshaller.io().read_u32()? {
+                0 => Ok(false),
+                1 => Ok(true),
+                value if marshaller.is_strict() => Err(Error::InvalidBoolean { value }),
+                _ => Ok(true),
+            }
         }
     }
 }
@@ -47,21 +62,130 @@ impl_basic_type! {
     f64: 'd'
 }
 
+// Non-zero integers are encoded as the integer they wrap, decoding zero is
+// an error.
+macro_rules! impl_non_zero_dbus_type {
+    ($($ty:ident($inner:ty): $code:tt),*) => {
+        $(
+        rbus_derive::impl_type! {
+            #[dbus(basic, align = "size", module = crate)]
+            $ty: $code {
+                encode(marshaller) {
+                    self.get().encode(marshaller)
+                }
+
+                decode(marshaller) {
+                    use crate::Error;
+
+                    $ty::new(<$inner>::decode(marshaller)?).ok_or_else(|| Error::ZeroValue {
+                        ty: stringify!($ty).into(),
+                    })
+                }
+            }
+        }
+        )*
+    }
+}
+
+impl_non_zero_dbus_type! {
+    NonZeroU8(u8): 'y',
+    NonZeroI16(i16): 'n',
+    NonZeroU16(u16): 'q',
+    NonZeroI32(i32): 'i',
+    NonZeroU32(u32): 'u',
+    NonZeroI64(i64): 'x',
+    NonZeroU64(u64): 't'
+}
+
 // Unix FD
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub struct UnixFd(pub u32);
+enum Fd {
+    Owned(File),
+    Borrowed(RawFd),
+}
+
+/// A Unix file descriptor.
+///
+/// Owned fds are closed once the last clone is dropped, borrowed ones are
+/// never closed and must stay open as long as they are used.
+#[derive(Clone)]
+pub struct UnixFd(Arc<Fd>);
+
+impl UnixFd {
+    /// Take ownership of a file descriptor.
+    pub fn new<T: IntoRawFd>(fd: T) -> UnixFd {
+        unsafe { UnixFd::from_raw_fd(fd.into_raw_fd()) }
+    }
+
+    /// Borrow a file descriptor owned by someone else.
+    ///
+    /// # Safety
+    ///
+    /// `fd` must be an open file descriptor, and stay open as long as the
+    /// returned value or any of its clones are used.
+    pub unsafe fn borrowed(fd: RawFd) -> UnixFd {
+        UnixFd(Arc::new(Fd::Borrowed(fd)))
+    }
+
+    pub fn is_owned(&self) -> bool {
+        match *self.0 {
+            Fd::Owned(_) => true,
+            Fd::Borrowed(_) => false,
+        }
+    }
+
+    /// Duplicate the file descriptor into a new owned one.
+    pub fn try_clone(&self) -> io::Result<UnixFd> {
+        let file = match *self.0 {
+            Fd::Owned(ref file) => file.try_clone()?,
+            Fd::Borrowed(fd) => ManuallyDrop::new(unsafe { File::from_raw_fd(fd) }).try_clone()?,
+        };
+
+        Ok(UnixFd::new(file))
+    }
+}
+
+impl AsRawFd for UnixFd {
+    fn as_raw_fd(&self) -> RawFd {
+        match *self.0 {
+            Fd::Owned(ref file) => file.as_raw_fd(),
+            Fd::Borrowed(fd) => fd,
+        }
+    }
+}
+
+impl FromRawFd for UnixFd {
+    unsafe fn from_raw_fd(fd: RawFd) -> UnixFd {
+        UnixFd(Arc::new(Fd::Owned(File::from_raw_fd(fd))))
+    }
+}
+
+impl fmt::Debug for UnixFd {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("UnixFd").field(&self.as_raw_fd()).finish()
+    }
+}
+
+impl PartialEq for UnixFd {
+    fn eq(&self, other: &UnixFd) -> bool {
+        self.as_raw_fd() == other.as_raw_fd()
+    }
+}
+
+impl Eq for UnixFd {}
 
+// Unix fds are written as an index in the fd list sent along the message.
 impl_basic_type! {
     #[dbus(align = 4)]
     UnixFd: 'h' {
         encode(marshaller) {
-            marshaller.io().write_u32(self.0)?;
+            let index = marshaller.push_fd(self);
+            marshaller.io().write_u32(index)?;
             Ok(())
         }
 
         decode(marshaller) {
-            let value = marshaller.io().read_u32()?;
-            Ok(UnixFd(value))
+            let index = marshaller.io().read_u32()?;
+            marshaller.fd(index)
         }
     }
 }
@@ -0,0 +1,386 @@
+use super::{
+    DBusType, Dict, DictEntry, ObjectPath, ObjectPathRef, Signature, SignatureRef, Struct, UnixFd, Variant,
+};
+use crate::{marshal::Marshaller, Result};
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::Hash,
+    marker::PhantomData,
+    str,
+};
+
+/// Types which can be decoded straight out of a byte slice, borrowing from
+/// it instead of copying.
+pub trait DBusBorrowType<'de>: Sized {
+    fn borrow_signature() -> String;
+
+    fn borrow_alignment() -> u8;
+
+    fn decode_borrowed(marshaller: &mut Marshaller<&'de [u8]>) -> Result<Self>;
+}
+
+macro_rules! impl_borrow_type_owned {
+    ($($ty:ty),*) => {
+        $(
+        impl<'de> DBusBorrowType<'de> for $ty {
+            fn borrow_signature() -> String {
+                <$ty as DBusType>::signature()
+            }
+
+            fn borrow_alignment() -> u8 {
+                <$ty as DBusType>::alignment()
+            }
+
+            fn decode_borrowed(marshaller: &mut Marshaller<&'de [u8]>) -> Result<Self> {
+                <$ty as DBusType>::decode(marshaller)
+            }
+        }
+        )*
+    }
+}
+
+impl_borrow_type_owned!(u8, bool, i16, u16, i32, u32, i64, u64, f64, UnixFd, String, ObjectPath, Signature, Variant);
+
+impl<'de> DBusBorrowType<'de> for &'de str {
+    fn borrow_signature() -> String {
+        "s".into()
+    }
+
+    fn borrow_alignment() -> u8 {
+        4
+    }
+
+    fn decode_borrowed(marshaller: &mut Marshaller<&'de [u8]>) -> Result<Self> {
+        let length = marshaller.io().read_u32()?;
+        marshaller.read_str(length as usize)
+    }
+}
+
+impl<'de> DBusBorrowType<'de> for &'de [u8] {
+    fn borrow_signature() -> String {
+        "ay".into()
+    }
+
+    fn borrow_alignment() -> u8 {
+        4
+    }
+
+    fn decode_borrowed(marshaller: &mut Marshaller<&'de [u8]>) -> Result<Self> {
+        let end = marshaller.begin_read_array(1)?;
+        let data = marshaller.read_slice(end - marshaller.read_position())?;
+        marshaller.end_read_array(end)?;
+        Ok(data)
+    }
+}
+
+impl<'de> DBusBorrowType<'de> for ObjectPathRef<'de> {
+    fn borrow_signature() -> String {
+        "o".into()
+    }
+
+    fn borrow_alignment() -> u8 {
+        4
+    }
+
+    fn decode_borrowed(marshaller: &mut Marshaller<&'de [u8]>) -> Result<Self> {
+        let path = <&str>::decode_borrowed(marshaller)?;
+        Ok(ObjectPathRef::new(path)?)
+    }
+}
+
+impl<'de> DBusBorrowType<'de> for SignatureRef<'de> {
+    fn borrow_signature() -> String {
+        "g".into()
+    }
+
+    fn borrow_alignment() -> u8 {
+        1
+    }
+
+    fn decode_borrowed(marshaller: &mut Marshaller<&'de [u8]>) -> Result<Self> {
+        let length = marshaller.io().read_u8()?;
+        Ok(SignatureRef::new(marshaller.read_str(length as usize)?)?)
+    }
+}
+
+/// A lazily decoded array, elements are only decoded while iterating.
+pub struct ArrayIter<'de, T> {
+    marshaller: Marshaller<&'de [u8]>,
+    index: usize,
+    failed: bool,
+    _element: PhantomData<T>,
+}
+
+impl<'de, T> ArrayIter<'de, T> {
+    /// Return the size in bytes of the elements left to decode.
+    pub fn remaining_bytes(&self) -> usize {
+        self.marshaller.len()
+    }
+}
+
+impl<'de, T: DBusBorrowType<'de>> Iterator for ArrayIter<'de, T> {
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed || self.marshaller.is_empty() {
+            return None;
+        }
+
+        let offset = self.marshaller.read_position();
+        let index = self.index;
+        let value = self
+            .marshaller
+            .read_borrowed()
+            .map_err(|err| err.in_path(&format!("[{}]", index), offset));
+        self.index += 1;
+        self.failed = value.is_err();
+        Some(value)
+    }
+}
+
+impl<'de, T: DBusBorrowType<'de>> DBusBorrowType<'de> for ArrayIter<'de, T> {
+    fn borrow_signature() -> String {
+        format!("a{}", T::borrow_signature())
+    }
+
+    fn borrow_alignment() -> u8 {
+        4
+    }
+
+    fn decode_borrowed(marshaller: &mut Marshaller<&'de [u8]>) -> Result<Self> {
+        let end = marshaller.begin_read_array(T::borrow_alignment())?;
+
+        let offset = marshaller.read_position();
+        let mut context = *marshaller.context();
+        context.input_end = Some(end);
+        let data = marshaller.read_slice(end - offset)?;
+        marshaller.end_read_array(end)?;
+
+        let mut inner = Marshaller::with_context(data, context, offset);
+        inner.set_fds(marshaller.fds().to_vec());
+
+        Ok(ArrayIter {
+            marshaller: inner,
+            index: 0,
+            failed: false,
+            _element: PhantomData,
+        })
+    }
+}
+
+impl<'de, T: DBusBorrowType<'de>> DBusBorrowType<'de> for Vec<T> {
+    fn borrow_signature() -> String {
+        format!("a{}", T::borrow_signature())
+    }
+
+    fn borrow_alignment() -> u8 {
+        4
+    }
+
+    fn decode_borrowed(marshaller: &mut Marshaller<&'de [u8]>) -> Result<Self> {
+        ArrayIter::<T>::decode_borrowed(marshaller)?.collect()
+    }
+}
+
+impl<'de, K, V> DBusBorrowType<'de> for DictEntry<K, V>
+where
+    K: DBusBorrowType<'de>,
+    V: DBusBorrowType<'de>,
+{
+    fn borrow_signature() -> String {
+        format!("{{{}{}}}", K::borrow_signature(), V::borrow_signature())
+    }
+
+    fn borrow_alignment() -> u8 {
+        8
+    }
+
+    fn decode_borrowed(marshaller: &mut Marshaller<&'de [u8]>) -> Result<Self> {
+        marshaller.read_struct(|marshaller| {
+            let key = marshaller.read_borrowed()?;
+            let value = marshaller.read_borrowed()?;
+            Ok(DictEntry::new(key, value))
+        })
+    }
+}
+
+macro_rules! impl_tuple_borrow_type {
+    ($($ty:ident),*) => {
+        impl<'de, $($ty: DBusBorrowType<'de>),*> DBusBorrowType<'de> for ($($ty,)*) {
+            fn borrow_signature() -> String {
+                String::new() $(+ &$ty::borrow_signature())*
+            }
+
+            fn borrow_alignment() -> u8 {
+                1
+            }
+
+            #[allow(unused_variables)]
+            fn decode_borrowed(marshaller: &mut Marshaller<&'de [u8]>) -> Result<Self> {
+                Ok(($(marshaller.read_borrowed::<$ty>()?,)*))
+            }
+        }
+    }
+}
+
+impl_tuple_borrow_type!();
+impl_tuple_borrow_type!(A);
+impl_tuple_borrow_type!(A, B);
+impl_tuple_borrow_type!(A, B, C);
+impl_tuple_borrow_type!(A, B, C, D);
+impl_tuple_borrow_type!(A, B, C, D, E);
+impl_tuple_borrow_type!(A, B, C, D, E, F);
+impl_tuple_borrow_type!(A, B, C, D, E, F, G);
+impl_tuple_borrow_type!(A, B, C, D, E, F, G, H);
+impl_tuple_borrow_type!(A, B, C, D, E, F, G, H, I);
+impl_tuple_borrow_type!(A, B, C, D, E, F, G, H, I, J);
+impl_tuple_borrow_type!(A, B, C, D, E, F, G, H, I, J, K);
+impl_tuple_borrow_type!(A, B, C, D, E, F, G, H, I, J, K, L);
+impl_tuple_borrow_type!(A, B, C, D, E, F, G, H, I, J, K, L, M);
+impl_tuple_borrow_type!(A, B, C, D, E, F, G, H, I, J, K, L, M, N);
+impl_tuple_borrow_type!(A, B, C, D, E, F, G, H, I, J, K, L, M, N, O);
+impl_tuple_borrow_type!(A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P);
+
+macro_rules! impl_dict_borrow_type {
+    ($ty:ident, $convert:path $(, $bound:path)*) => {
+        impl<'de, K, V> DBusBorrowType<'de> for $ty<K, V>
+        where
+            K: DBusBorrowType<'de> $(+ $bound)*,
+            V: DBusBorrowType<'de>,
+        {
+            fn borrow_signature() -> String {
+                <Vec<DictEntry<K, V>>>::borrow_signature()
+            }
+
+            fn borrow_alignment() -> u8 {
+                4
+            }
+
+            fn decode_borrowed(marshaller: &mut Marshaller<&'de [u8]>) -> Result<Self> {
+                let entries = <Vec<DictEntry<K, V>>>::decode_borrowed(marshaller)?;
+                Ok($convert(Dict::from(entries)))
+            }
+        }
+    };
+}
+
+impl_dict_borrow_type!(Dict, std::convert::identity);
+impl_dict_borrow_type!(HashMap, Dict::into_hashmap, Eq, Hash);
+impl_dict_borrow_type!(BTreeMap, Dict::into_btreemap, Ord);
+
+impl<'de, T: DBusBorrowType<'de>> DBusBorrowType<'de> for Struct<T> {
+    fn borrow_signature() -> String {
+        format!("({})", T::borrow_signature())
+    }
+
+    fn borrow_alignment() -> u8 {
+        8
+    }
+
+    fn decode_borrowed(marshaller: &mut Marshaller<&'de [u8]>) -> Result<Self> {
+        marshaller.read_struct(|marshaller| marshaller.read_borrowed()).map(Struct)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{marshal::Endianness, Error};
+
+    #[test]
+    fn test_decode_borrowed_str() {
+        let bytes: &[u8] = &[
+            5, 0, 0, 0, b'h', b'e', b'l', b'l', b'o', 0, // String
+            0, 0, // Padding
+            42, 0, 0, 0, // u32
+        ];
+
+        let mut marshaller = Marshaller::new(bytes, Endianness::Little);
+        let (value, number) = marshaller.read_borrowed::<(&str, u32)>().unwrap();
+
+        assert_eq!(value, "hello");
+        assert_eq!(value.as_ptr(), bytes[4..].as_ptr());
+        assert_eq!(number, 42);
+        assert!(marshaller.is_empty());
+    }
+
+    #[test]
+    fn test_decode_borrowed_large_tuple() {
+        type Args<'a> = (u8, u16, u32, u64, i16, i32, i64, bool, Struct<(&'a str,)>);
+        let value = (1u8, 2u16, 3u32, 4u64, 5i16, 6i32, 7i64, true, Struct((String::from("a"),)));
+
+        let mut marshaller = Marshaller::new(Vec::new(), Endianness::Little);
+        marshaller.write_value(&value).unwrap();
+        let bytes = marshaller.into_inner();
+
+        let mut marshaller = Marshaller::new(bytes.as_slice(), Endianness::Little);
+        let decoded = marshaller.read_borrowed::<Args>().unwrap();
+        assert!(decoded.7);
+        assert_eq!((decoded.8).0, ("a",));
+        assert!(marshaller.is_empty());
+    }
+
+    #[test]
+    fn test_decode_borrowed_array_iter() {
+        let bytes: &[u8] = &[
+            1, 0, 0, 0, b'/', 0, // First element
+            0, 0, // Padding
+            2, 0, 0, 0, b'/', b'a', 0, // Second element
+        ];
+        let mut data = vec![bytes.len() as u8, 0, 0, 0];
+        data.extend_from_slice(bytes);
+
+        let mut marshaller = Marshaller::new(data.as_slice(), Endianness::Little);
+        let paths = marshaller.read_borrowed::<ArrayIter<ObjectPathRef>>().unwrap();
+        let paths = paths.map(|path| path.map(|path| path.as_str())).collect::<Result<Vec<_>>>();
+
+        assert_eq!(paths.unwrap(), vec!["/", "/a"]);
+        assert!(marshaller.is_empty());
+    }
+
+    #[test]
+    fn test_decode_borrowed_error_path() {
+        let data: &[u8] = &[
+            14, 0, 0, 0, // Array size in bytes = 14
+            1, 0, 0, 0, b'/', 0, // First element
+            0, 0, // Padding
+            1, 0, 0, 0, b'a', 0, // Invalid second element
+        ];
+
+        let mut marshaller = Marshaller::new(data, Endianness::Little);
+        match marshaller.read_borrowed::<Vec<ObjectPathRef>>() {
+            Err(Error::Decode { path, offset, .. }) => {
+                assert_eq!(path, "[1].o");
+                assert_eq!(offset, 12);
+            }
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_decode_borrowed_dict() {
+        let mut value = HashMap::new();
+        value.insert(String::from("a"), 1u32);
+
+        let mut marshaller = Marshaller::new(Vec::new(), Endianness::Little);
+        marshaller.write_value(&value).unwrap();
+        let bytes = marshaller.into_inner();
+
+        let mut marshaller = Marshaller::new(bytes.as_slice(), Endianness::Little);
+        let decoded = marshaller.read_borrowed::<HashMap<&str, u32>>().unwrap();
+        assert_eq!(decoded.get("a"), Some(&1));
+        assert_eq!(<BTreeMap<&str, u32>>::borrow_signature(), "a{su}");
+        assert!(marshaller.is_empty());
+    }
+
+    #[test]
+    fn test_decode_borrowed_bytes() {
+        let bytes: &[u8] = &[3, 0, 0, 0, 1, 2, 3];
+
+        let mut marshaller = Marshaller::new(bytes, Endianness::Little);
+        let value = marshaller.read_borrowed::<&[u8]>().unwrap();
+
+        assert_eq!(value, &[1, 2, 3]);
+        assert!(marshaller.is_empty());
+    }
+}
@@ -1,6 +1,11 @@
 use super::{DBusBasicType, DBusType};
 use rbus_derive::impl_type;
-use std::{collections::HashMap, hash::Hash, iter::FromIterator, ops::Deref};
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::Hash,
+    iter::FromIterator,
+    ops::Deref,
+};
 
 // Dict (list of dict entries)
 #[derive(Debug, Clone, PartialEq)]
@@ -36,6 +41,13 @@ impl<K, V> Dict<K, V> {
     {
         self.0.into_iter().map(|entry| (entry.0, entry.1)).collect()
     }
+
+    pub fn into_btreemap(self) -> BTreeMap<K, V>
+    where
+        K: Ord,
+    {
+        self.0.into_iter().map(|entry| (entry.0, entry.1)).collect()
+    }
 }
 
 impl<K, V> Deref for Dict<K, V> {
@@ -84,18 +96,18 @@ impl_type! {
         }
 
         encode(marshaller) {
-            marshaller.write_padding(Self::alignment())?;
-            self.0.encode(marshaller)?;
-            marshaller.write_padding(Self::alignment())?;
-            self.1.encode(marshaller)?;
-            Ok(())
+            marshaller.write_struct(|marshaller| {
+                marshaller.write_value(&self.0)?;
+                marshaller.write_value(&self.1)
+            })
         }
 
         decode(marshaller) {
-            marshaller.read_padding(Self::alignment())?;
-            let key = K::decode(marshaller)?;
-            marshaller.read_padding(Self::alignment())?;
-            let value = V::decode(marshaller)?;
+            let (key, value) = marshaller.read_struct(|marshaller| {
+                let key = marshaller.read_field::<K>("key")?;
+                let value = marshaller.read_field::<V>("value")?;
+                Ok((key, value))
+            })?;
 
             Ok(DictEntry(key, value))
         }
@@ -103,8 +115,8 @@ impl_type! {
 }
 
 impl_type! {
-    #[dbus(align = 8, module = crate)]
-    impl<K: DBusBasicType, V: DBusType> Dict<K, V>: 'e' {
+    #[dbus(align = 4, module = crate)]
+    impl<K: DBusBasicType, V: DBusType> Dict<K, V>: 'a' {
         signature() {
             <Vec<DictEntry<K, V>>>::signature()
         }
@@ -122,8 +134,8 @@ impl_type! {
 
 // HashMap
 impl_type! {
-    #[dbus(align = 8, module = crate)]
-    impl<K, V> HashMap<K, V>: 'e'
+    #[dbus(align = 4, module = crate)]
+    impl<K, V> HashMap<K, V>: 'a'
     where
         K: DBusBasicType + Eq + Hash,
         V: DBusType,
@@ -143,3 +155,51 @@ impl_type! {
         }
     }
 }
+
+// BTreeMap
+impl_type! {
+    #[dbus(align = 4, module = crate)]
+    impl<K, V> BTreeMap<K, V>: 'a'
+    where
+        K: DBusBasicType + Ord,
+        V: DBusType,
+    {
+        signature() {
+            <Dict<K, V>>::signature()
+        }
+
+        encode(marshaller) {
+            let data = self.iter().collect::<Dict<_, _>>();
+            data.encode(marshaller)
+        }
+
+        decode(marshaller) {
+            let dict = <Dict<_, _>>::decode(marshaller)?;
+            Ok(dict.into_btreemap())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::marshal::{Endianness, Marshaller};
+
+    #[test]
+    fn test_btreemap_roundtrip() {
+        let mut value = BTreeMap::new();
+        value.insert(String::from("b"), 2u32);
+        value.insert(String::from("a"), 1u32);
+        assert_eq!(<BTreeMap<String, u32>>::signature(), "a{su}");
+
+        let mut marshaller = Marshaller::new(Vec::new(), Endianness::Little);
+        marshaller.write_value(&value).unwrap();
+        let data = marshaller.into_inner();
+        // Entries are written in key order.
+        assert_eq!(&data[8..14], &[1, 0, 0, 0, b'a', 0]);
+
+        let mut marshaller = Marshaller::new(data.as_slice(), Endianness::Little);
+        assert_eq!(marshaller.read_value::<BTreeMap<String, u32>>().unwrap(), value);
+        assert!(marshaller.is_empty());
+    }
+}
@@ -1,17 +1,24 @@
 use crate::{marshal::Marshaller, Result};
 pub use array::*;
 pub use basic::*;
+pub use borrowed::*;
 pub use dict::*;
 #[doc(hidden)]
 pub use rbus_derive::{impl_type, DBusType};
-use std::io;
+pub use signature::*;
+use std::{borrow::Cow, io, rc::Rc, sync::Arc};
 pub use string::*;
+pub use tuple::*;
+pub use value::*;
 
 mod array;
 mod basic;
+mod borrowed;
 mod dict;
+mod signature;
 mod string;
 mod tuple;
+mod value;
 
 pub trait DBusType: Sized {
     fn code() -> u8;
@@ -24,6 +31,36 @@ pub trait DBusType: Sized {
     fn decode<Inner>(marshaller: &mut Marshaller<Inner>) -> Result<Self>
     where
         Inner: io::Read;
+
+    /// Encode an array of this type, fixed size basic types override this
+    /// to write the whole array at once.
+    #[doc(hidden)]
+    fn encode_array<Inner>(values: &[Self], marshaller: &mut Marshaller<Inner>) -> Result<()>
+    where
+        Inner: io::Write,
+    {
+        marshaller.write_array(Self::alignment(), |marshaller| {
+            for value in values.iter() {
+                marshaller.write_value(value)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Decode an array of this type, see `encode_array`.
+    #[doc(hidden)]
+    fn decode_array<Inner>(marshaller: &mut Marshaller<Inner>) -> Result<Vec<Self>>
+    where
+        Inner: io::Read,
+    {
+        let mut values = vec![];
+        marshaller.read_array(Self::alignment(), |marshaller| {
+            values.push(marshaller.read_value()?);
+            Ok(())
+        })?;
+
+        Ok(values)
+    }
 }
 
 impl_type! {
@@ -54,6 +91,113 @@ impl_type! {
     }
 }
 
+// Smart pointers are encoded as the value they point to.
+macro_rules! impl_pointer_dbus_type {
+    ($($ty:ident),*) => {
+        $(
+        impl_type! {
+            impl<T: DBusType> $ty<T> {
+                code() {
+                    T::code()
+                }
+
+                signature() {
+                    T::signature()
+                }
+
+                alignment() {
+                    T::alignment()
+                }
+
+                encode(marshaller) {
+                    (**self).encode(marshaller)
+                }
+
+                decode(marshaller) {
+                    T::decode(marshaller).map($ty::new)
+                }
+            }
+        }
+
+        impl<T: DBusBasicType> DBusBasicType for $ty<T> {}
+        )*
+    }
+}
+
+impl_pointer_dbus_type!(Box, Rc, Arc);
+
+impl_type! {
+    impl<'a, B> Cow<'a, B>
+    where
+        B: ToOwned + ?Sized,
+        B::Owned: DBusType,
+        for<'b> &'b B: DBusType,
+    {
+        code() {
+            <B::Owned>::code()
+        }
+
+        signature() {
+            <B::Owned>::signature()
+        }
+
+        alignment() {
+            <B::Owned>::alignment()
+        }
+
+        encode(marshaller) {
+            (&**self).encode(marshaller)
+        }
+
+        decode(marshaller) {
+            <B::Owned>::decode(marshaller).map(Cow::Owned)
+        }
+    }
+}
+
 pub trait DBusBasicType: DBusType {}
 
 impl<T: DBusBasicType> DBusBasicType for &T {}
+
+impl<'a, B> DBusBasicType for Cow<'a, B>
+where
+    B: ToOwned + ?Sized,
+    B::Owned: DBusBasicType,
+    for<'b> &'b B: DBusType,
+{
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::marshal::Endianness;
+    use std::num::NonZeroU32;
+
+    fn roundtrip<T: DBusType + PartialEq + std::fmt::Debug>(value: T) {
+        let mut marshaller = Marshaller::new(Vec::new(), Endianness::Little);
+        marshaller.write_value(&value).unwrap();
+        let data = marshaller.into_inner();
+
+        let mut marshaller = Marshaller::new(data.as_slice(), Endianness::Little);
+        assert_eq!(marshaller.read_value::<T>().unwrap(), value);
+        assert!(marshaller.is_empty());
+    }
+
+    #[test]
+    fn test_wrapper_types() {
+        assert_eq!(<Box<u32>>::signature(), "u");
+        assert_eq!(<Arc<Vec<String>>>::signature(), "as");
+        assert_eq!(<Cow<str>>::signature(), "s");
+        assert_eq!(<Cow<[u8]>>::signature(), "ay");
+
+        roundtrip(Box::new(1u32));
+        roundtrip(Rc::new(String::from("a")));
+        roundtrip(Arc::new((1u8, 2u64)));
+        roundtrip(Cow::Borrowed("a"));
+        roundtrip(Cow::<[u8]>::Borrowed(&[1, 2]));
+        roundtrip(NonZeroU32::new(3).unwrap());
+
+        let mut marshaller = Marshaller::new(&[0u8, 0, 0, 0][..], Endianness::Little);
+        assert_err!(marshaller.read_value::<NonZeroU32>());
+    }
+}
@@ -0,0 +1,577 @@
+use super::impl_type;
+use custom_error::custom_error;
+use derive_more::*;
+use std::{convert::TryFrom, fmt, io::prelude::*};
+
+/// The maximum length of a signature, in bytes.
+pub const MAX_SIGNATURE_LENGTH: usize = 255;
+/// The maximum number of nested arrays in a single complete type.
+pub const MAX_ARRAY_DEPTH: usize = 32;
+/// The maximum number of nested structs in a single complete type.
+pub const MAX_STRUCT_DEPTH: usize = 32;
+
+custom_error! {
+    pub SignatureError
+        InvalidSignature { message: String }
+            = "Invalid signature: {message}"
+}
+
+impl SignatureError {
+    fn invalid<T: Into<String>>(message: T) -> SignatureError {
+        SignatureError::InvalidSignature {
+            message: message.into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BasicType {
+    Byte,
+    Boolean,
+    Int16,
+    UInt16,
+    Int32,
+    UInt32,
+    Int64,
+    UInt64,
+    Double,
+    UnixFd,
+    String,
+    ObjectPath,
+    Signature,
+}
+
+impl BasicType {
+    pub fn from_code(code: u8) -> Option<BasicType> {
+        let ty = match code {
+            b'y' => BasicType::Byte,
+            b'b' => BasicType::Boolean,
+            b'n' => BasicType::Int16,
+            b'q' => BasicType::UInt16,
+            b'i' => BasicType::Int32,
+            b'u' => BasicType::UInt32,
+            b'x' => BasicType::Int64,
+            b't' => BasicType::UInt64,
+            b'd' => BasicType::Double,
+            b'h' => BasicType::UnixFd,
+            b's' => BasicType::String,
+            b'o' => BasicType::ObjectPath,
+            b'g' => BasicType::Signature,
+            _ => return None,
+        };
+
+        Some(ty)
+    }
+
+    pub fn code(self) -> u8 {
+        match self {
+            BasicType::Byte => b'y',
+            BasicType::Boolean => b'b',
+            BasicType::Int16 => b'n',
+            BasicType::UInt16 => b'q',
+            BasicType::Int32 => b'i',
+            BasicType::UInt32 => b'u',
+            BasicType::Int64 => b'x',
+            BasicType::UInt64 => b't',
+            BasicType::Double => b'd',
+            BasicType::UnixFd => b'h',
+            BasicType::String => b's',
+            BasicType::ObjectPath => b'o',
+            BasicType::Signature => b'g',
+        }
+    }
+
+    pub fn alignment(self) -> u8 {
+        match self {
+            BasicType::Byte | BasicType::Signature => 1,
+            BasicType::Int16 | BasicType::UInt16 => 2,
+            BasicType::Boolean
+            | BasicType::Int32
+            | BasicType::UInt32
+            | BasicType::UnixFd
+            | BasicType::String
+            | BasicType::ObjectPath => 4,
+            BasicType::Int64 | BasicType::UInt64 | BasicType::Double => 8,
+        }
+    }
+}
+
+impl fmt::Display for BasicType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.code() as char)
+    }
+}
+
+/// A single complete type, as described by the "Type System" section of the
+/// D-Bus specification.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum SignatureType {
+    Basic(BasicType),
+    Array(Box<SignatureType>),
+    Struct(Vec<SignatureType>),
+    /// A dict entry, only valid as the element type of an array.
+    DictEntry(BasicType, Box<SignatureType>),
+    Variant,
+    /// A GVariant maybe type (`m`), which doesn't exist in D-Bus signatures.
+    #[cfg(feature = "gvariant")]
+    Maybe(Box<SignatureType>),
+}
+
+impl SignatureType {
+    /// Parse a string containing exactly one single complete type.
+    pub fn parse<T: AsRef<str>>(sig: T) -> Result<SignatureType, SignatureError> {
+        let mut types = parse_signature(sig.as_ref())?;
+        if types.len() != 1 {
+            return Err(SignatureError::invalid("Expected exactly one single complete type"));
+        }
+
+        Ok(types.remove(0))
+    }
+
+    /// Parse a GVariant type string containing exactly one single complete
+    /// type, which may contain maybe types and the unit type `()`.
+    #[cfg(feature = "gvariant")]
+    pub fn parse_gvariant<T: AsRef<str>>(sig: T) -> Result<SignatureType, SignatureError> {
+        let sig = sig.as_ref();
+        check_length(sig)?;
+
+        let mut types = Parser::gvariant(sig).parse_all()?;
+        if types.len() != 1 {
+            return Err(SignatureError::invalid("Expected exactly one single complete type"));
+        }
+
+        Ok(types.remove(0))
+    }
+
+    pub fn code(&self) -> u8 {
+        match self {
+            SignatureType::Basic(ty) => ty.code(),
+            SignatureType::Array(_) => b'a',
+            SignatureType::Struct(_) => b'r',
+            SignatureType::DictEntry(..) => b'e',
+            SignatureType::Variant => b'v',
+            #[cfg(feature = "gvariant")]
+            SignatureType::Maybe(_) => b'm',
+        }
+    }
+
+    pub fn alignment(&self) -> u8 {
+        match self {
+            SignatureType::Basic(ty) => ty.alignment(),
+            SignatureType::Array(_) => 4,
+            SignatureType::Struct(_) | SignatureType::DictEntry(..) => 8,
+            SignatureType::Variant => 1,
+            #[cfg(feature = "gvariant")]
+            SignatureType::Maybe(element) => element.alignment(),
+        }
+    }
+
+    pub fn is_basic(&self) -> bool {
+        matches!(self, SignatureType::Basic(_))
+    }
+
+    pub fn signature(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl fmt::Display for SignatureType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SignatureType::Basic(ty) => write!(f, "{}", ty),
+            SignatureType::Array(element) => write!(f, "a{}", element),
+            SignatureType::Struct(fields) => {
+                write!(f, "(")?;
+                for field in fields.iter() {
+                    write!(f, "{}", field)?;
+                }
+                write!(f, ")")
+            }
+            SignatureType::DictEntry(key, value) => write!(f, "{{{}{}}}", key, value),
+            SignatureType::Variant => write!(f, "v"),
+            #[cfg(feature = "gvariant")]
+            SignatureType::Maybe(element) => write!(f, "m{}", element),
+        }
+    }
+}
+
+impl From<BasicType> for SignatureType {
+    fn from(ty: BasicType) -> SignatureType {
+        SignatureType::Basic(ty)
+    }
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    pos: usize,
+    array_depth: usize,
+    struct_depth: usize,
+    /// Whether GVariant maybe and unit types are allowed.
+    gvariant: bool,
+}
+
+impl<'a> Parser<'a> {
+    fn new(input: &'a str) -> Parser<'a> {
+        Parser {
+            input: input.as_bytes(),
+            pos: 0,
+            array_depth: 0,
+            struct_depth: 0,
+            gvariant: false,
+        }
+    }
+
+    #[cfg(feature = "gvariant")]
+    fn gvariant(input: &'a str) -> Parser<'a> {
+        Parser {
+            gvariant: true,
+            ..Parser::new(input)
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.input.len()
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).cloned()
+    }
+
+    fn next(&mut self) -> Result<u8, SignatureError> {
+        let code = self
+            .peek()
+            .ok_or_else(|| SignatureError::invalid("Unexpected end of signature"))?;
+        self.pos += 1;
+        Ok(code)
+    }
+
+    fn parse_all(mut self) -> Result<Vec<SignatureType>, SignatureError> {
+        let mut types = Vec::new();
+        while !self.is_empty() {
+            types.push(self.parse_single()?);
+        }
+        Ok(types)
+    }
+
+    fn parse_single(&mut self) -> Result<SignatureType, SignatureError> {
+        match self.next()? {
+            b'a' => self.parse_array(),
+            b'(' => self.parse_struct(),
+            b'v' => Ok(SignatureType::Variant),
+            #[cfg(feature = "gvariant")]
+            b'm' if self.gvariant => self.parse_maybe(),
+            b'{' => Err(SignatureError::invalid(
+                "Dict entries are only allowed as the element type of an array",
+            )),
+            b')' | b'}' => Err(SignatureError::invalid(
+                "Unbalanced closing parenthesis or curly bracket",
+            )),
+            code => BasicType::from_code(code).map(SignatureType::Basic).ok_or_else(|| {
+                SignatureError::invalid(
+                    "Only type codes, open and close parentheses, and open and close curly brackets are allowed in \
+                     the signature. The STRUCT type code is not allowed in signatures, because parentheses are used \
+                     instead. Similarly, the DICT_ENTRY type code is not allowed in signatures, because curly \
+                     brackets are used instead.",
+                )
+            }),
+        }
+    }
+
+    fn parse_array(&mut self) -> Result<SignatureType, SignatureError> {
+        self.array_depth += 1;
+        if self.array_depth > MAX_ARRAY_DEPTH {
+            return Err(SignatureError::invalid(format!(
+                "The maximum depth of array type nesting is {}",
+                MAX_ARRAY_DEPTH
+            )));
+        }
+
+        let element = if self.peek() == Some(b'{') {
+            self.pos += 1;
+            self.parse_dict_entry()?
+        } else {
+            self.parse_single()?
+        };
+
+        self.array_depth -= 1;
+        Ok(SignatureType::Array(Box::new(element)))
+    }
+
+    /// Parse a maybe type, which counts as an array since it holds zero or
+    /// one element.
+    #[cfg(feature = "gvariant")]
+    fn parse_maybe(&mut self) -> Result<SignatureType, SignatureError> {
+        self.array_depth += 1;
+        if self.array_depth > MAX_ARRAY_DEPTH {
+            return Err(SignatureError::invalid(format!(
+                "The maximum depth of array type nesting is {}",
+                MAX_ARRAY_DEPTH
+            )));
+        }
+
+        let element = self.parse_single()?;
+        self.array_depth -= 1;
+        Ok(SignatureType::Maybe(Box::new(element)))
+    }
+
+    /// Enter a struct or dict entry, both count as struct nesting.
+    fn enter_struct(&mut self) -> Result<(), SignatureError> {
+        self.struct_depth += 1;
+        if self.struct_depth > MAX_STRUCT_DEPTH {
+            return Err(SignatureError::invalid(format!(
+                "The maximum depth of struct type nesting is {}",
+                MAX_STRUCT_DEPTH
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn parse_struct(&mut self) -> Result<SignatureType, SignatureError> {
+        self.enter_struct()?;
+
+        let mut fields = Vec::new();
+        while self.peek() != Some(b')') {
+            fields.push(self.parse_single()?);
+        }
+        self.pos += 1;
+
+        if fields.is_empty() && !self.gvariant {
+            return Err(SignatureError::invalid("Empty structures are not allowed"));
+        }
+
+        self.struct_depth -= 1;
+        Ok(SignatureType::Struct(fields))
+    }
+
+    fn parse_dict_entry(&mut self) -> Result<SignatureType, SignatureError> {
+        self.enter_struct()?;
+
+        let key = match self.parse_single()? {
+            SignatureType::Basic(key) => key,
+            _ => return Err(SignatureError::invalid("Dict entry keys must be a basic type")),
+        };
+        let value = self.parse_single()?;
+
+        if self.next()? != b'}' {
+            return Err(SignatureError::invalid("Dict entries must contain exactly two types"));
+        }
+
+        self.struct_depth -= 1;
+        Ok(SignatureType::DictEntry(key, Box::new(value)))
+    }
+}
+
+/// Parse a signature into the list of single complete types it contains.
+pub fn parse_signature(sig: &str) -> Result<Vec<SignatureType>, SignatureError> {
+    check_length(sig)?;
+    Parser::new(sig).parse_all()
+}
+
+fn check_length(sig: &str) -> Result<(), SignatureError> {
+    if sig.len() > MAX_SIGNATURE_LENGTH {
+        return Err(SignatureError::invalid(format!(
+            "The maximum length of a signature is {}",
+            MAX_SIGNATURE_LENGTH
+        )));
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq, Deref)]
+pub struct Signature(String);
+
+impl Signature {
+    pub fn new<T: AsRef<str>>(sig: T) -> Result<Self, SignatureError> {
+        let sig = sig.as_ref();
+        parse_signature(sig)?;
+
+        Ok(Signature(sig.into()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+
+    /// Return the single complete types this signature is made of.
+    pub fn types(&self) -> Vec<SignatureType> {
+        parse_signature(&self.0).expect("Signature was validated on creation")
+    }
+}
+
+impl AsRef<str> for Signature {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// A signature borrowed from a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deref)]
+pub struct SignatureRef<'a>(&'a str);
+
+impl<'a> SignatureRef<'a> {
+    pub fn new(sig: &'a str) -> Result<Self, SignatureError> {
+        parse_signature(sig)?;
+
+        Ok(SignatureRef(sig))
+    }
+
+    pub fn as_str(&self) -> &'a str {
+        self.0
+    }
+
+    pub fn types(&self) -> Vec<SignatureType> {
+        parse_signature(self.0).expect("Signature was validated on creation")
+    }
+
+    pub fn into_owned(self) -> Signature {
+        Signature(self.0.into())
+    }
+}
+
+impl<'a> AsRef<str> for SignatureRef<'a> {
+    fn as_ref(&self) -> &str {
+        self.0
+    }
+}
+
+impl TryFrom<SignatureType> for Signature {
+    type Error = SignatureError;
+
+    /// Render a single complete type as a signature, which fails for types
+    /// which can't stand alone in a signature, like empty structs or dict
+    /// entries outside of arrays.
+    fn try_from(ty: SignatureType) -> Result<Signature, SignatureError> {
+        Signature::new(ty.to_string())
+    }
+}
+
+impl_type! {
+    #[dbus(basic, module = crate)]
+    Signature: 'g' {
+        encode(marshaller) {
+            use crate::Error;
+
+            if self.len() > MAX_SIGNATURE_LENGTH {
+                return Err(Error::SignatureTooLong { length: self.len() });
+            }
+
+            marshaller.io().write_u8(self.len() as u8)?;
+            marshaller.io().write_all(self.as_bytes())?;
+            marshaller.io().write_u8(0)?;
+            Ok(())
+        }
+
+        decode(marshaller) {
+            let length = marshaller.io().read_u8()?;
+            let value = marshaller.read_string(length as usize)?;
+            Ok(Signature::new(value)?)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VALID_SIGNATURES: &[&str] = &["", "s", "a{sv}", "sss", "sv", "a{sv}as", "(ii)", "a(yv)", "aa{oa{sv}}"];
+
+    const INVALID_SIGNATURES: &[&str] = &[
+        "a", "a{s_}", "(i", "i)", "()", "a{vs}", "{sv}", "a{s}", "a{sss}", "a{sv", "r", "e", "(a)",
+    ];
+
+    #[test]
+    fn test_valid_signatures() {
+        for sig in VALID_SIGNATURES {
+            assert_ok!(Signature::new(sig), sig);
+        }
+    }
+
+    #[test]
+    fn test_invalid_signatures() {
+        for sig in INVALID_SIGNATURES {
+            assert_err!(Signature::new(sig), sig);
+        }
+    }
+
+    #[test]
+    fn test_signature_nesting_limits() {
+        let arrays = format!("{}y", "a".repeat(MAX_ARRAY_DEPTH));
+        assert_ok!(Signature::new(&arrays));
+        let arrays = format!("{}y", "a".repeat(MAX_ARRAY_DEPTH + 1));
+        assert_err!(Signature::new(&arrays));
+
+        let structs = format!("{}y{}", "(".repeat(MAX_STRUCT_DEPTH), ")".repeat(MAX_STRUCT_DEPTH));
+        assert_ok!(Signature::new(&structs));
+        let structs = format!("{}y{}", "(".repeat(MAX_STRUCT_DEPTH + 1), ")".repeat(MAX_STRUCT_DEPTH + 1));
+        assert_err!(Signature::new(&structs));
+
+        // Dict entries count as structs too.
+        let dicts = |depth: usize| format!("{}y{}", "a{s(".repeat(depth), ")}".repeat(depth));
+        assert_ok!(Signature::new(dicts(MAX_STRUCT_DEPTH / 2)));
+        assert_err!(Signature::new(dicts(MAX_STRUCT_DEPTH / 2 + 1)));
+
+        assert_err!(Signature::new("y".repeat(MAX_SIGNATURE_LENGTH + 1)));
+    }
+
+    #[test]
+    fn test_signature_types() {
+        let signature = Signature::new("ya{sv}(ao)").unwrap();
+
+        assert_eq!(
+            signature.types(),
+            vec![
+                SignatureType::Basic(BasicType::Byte),
+                SignatureType::Array(Box::new(SignatureType::DictEntry(
+                    BasicType::String,
+                    Box::new(SignatureType::Variant),
+                ))),
+                SignatureType::Struct(vec![SignatureType::Array(Box::new(SignatureType::Basic(
+                    BasicType::ObjectPath
+                )))]),
+            ]
+        );
+
+        let rendered = signature.types().iter().map(ToString::to_string).collect::<String>();
+        assert_eq!(rendered, signature.as_str());
+    }
+
+    #[test]
+    fn test_signature_from_type() {
+        let ty = SignatureType::Array(Box::new(SignatureType::Basic(BasicType::String)));
+        assert_eq!(Signature::try_from(ty).unwrap().as_str(), "as");
+
+        assert_err!(Signature::try_from(SignatureType::Struct(vec![])));
+        assert_err!(Signature::try_from(SignatureType::DictEntry(
+            BasicType::String,
+            Box::new(SignatureType::Variant)
+        )));
+    }
+
+    #[test]
+    #[cfg(feature = "gvariant")]
+    fn test_gvariant_types() {
+        assert_err!(Signature::new("ms"));
+        assert_err!(Signature::new("()"));
+        assert_err!(SignatureType::parse_gvariant("m"));
+
+        let ty = SignatureType::parse_gvariant("a{sms}").unwrap();
+        assert_eq!(
+            ty,
+            SignatureType::Array(Box::new(SignatureType::DictEntry(
+                BasicType::String,
+                Box::new(SignatureType::Maybe(Box::new(SignatureType::Basic(BasicType::String)))),
+            )))
+        );
+        assert_eq!(ty.to_string(), "a{sms}");
+        assert_eq!(SignatureType::parse_gvariant("()").unwrap(), SignatureType::Struct(vec![]));
+        assert_err!(Signature::try_from(ty));
+    }
+}
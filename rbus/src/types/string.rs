@@ -21,7 +21,7 @@ impl_type! {
             use crate::Error;
 
             Err(Error::Custom {
-                message: "References cannot be decoded".into(),
+                message: "References cannot be decoded, use `DBusBorrowType` instead".into(),
             })
         }
     }
@@ -36,11 +36,7 @@ impl_type! {
 
         decode(marshaller) {
             let length = marshaller.io().read_u32()?;
-            let mut data = vec![0; length as usize];
-            marshaller.io().read_exact(&mut data)?;
-            marshaller.io().read_u8()?;
-            let value = String::from_utf8(data)?;
-            Ok(value)
+            marshaller.read_string(length as usize)
         }
     }
 }
@@ -65,6 +61,14 @@ pub struct ObjectPath(String);
 
 impl ObjectPath {
     pub fn new<T: AsRef<str>>(path: T) -> Result<Self, ObjectPathError> {
+        let path = path.as_ref();
+        ObjectPath::validate(path)?;
+
+        Ok(ObjectPath(path.into()))
+    }
+
+    /// Check that `path` is a valid object path without allocating.
+    pub fn validate<T: AsRef<str>>(path: T) -> Result<(), ObjectPathError> {
         lazy_static! {
             static ref OBJECT_PATH_SEGMENT_REGEX: Regex = Regex::new(r"^([[:alnum:]]|_)+$").unwrap();
         }
@@ -103,7 +107,7 @@ impl ObjectPath {
             }
         }
 
-        Ok(ObjectPath(path.into()))
+        Ok(())
     }
 }
 
@@ -113,73 +117,29 @@ impl AsRef<str> for ObjectPath {
     }
 }
 
-// Signature
-custom_error! {
-    pub SignatureError
-        InvalidSignature { message: String }
-            = "Invalid signature"
-}
-
-#[derive(Debug, Clone, PartialEq, Deref)]
-pub struct Signature(String);
+/// An object path borrowed from a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deref)]
+pub struct ObjectPathRef<'a>(&'a str);
 
-impl Signature {
-    pub fn new<T: AsRef<str>>(sig: T) -> Result<Self, SignatureError> {
-        // TODO: Validate signature (rules 2 and 3), maybe need a parser...
-        lazy_static! {
-            static ref SIGNATURE_ALLOWED_CHARS: Regex = Regex::new(r"^[ybnqiuxtdhsogav(){}]*$").unwrap();
-        }
+impl<'a> ObjectPathRef<'a> {
+    pub fn new(path: &'a str) -> Result<Self, ObjectPathError> {
+        ObjectPath::validate(path)?;
 
-        let sig = sig.as_ref();
-
-        if sig.len() > 255 {
-            return Err(SignatureError::InvalidSignature {
-                message: "The maximum length of a signature is 255".into(),
-            });
-        }
-
-        if !SIGNATURE_ALLOWED_CHARS.is_match(sig) {
-            return Err(SignatureError::InvalidSignature {
-                message: "Only type codes, open and close parentheses, and open and close curly brackets are allowed \
-                          in the signature. The STRUCT type code is not allowed in signatures, because parentheses \
-                          are used instead. Similarly, the DICT_ENTRY type code is not allowed in signatures, because \
-                          curly brackets are used instead."
-                    .into(),
-            });
-        }
-
-        Ok(Signature(sig.into()))
+        Ok(ObjectPathRef(path))
     }
 
-    pub fn as_str(&self) -> &str {
-        self.0.as_str()
+    pub fn as_str(&self) -> &'a str {
+        self.0
     }
-}
 
-impl AsRef<str> for Signature {
-    fn as_ref(&self) -> &str {
-        self.as_str()
+    pub fn into_owned(self) -> ObjectPath {
+        ObjectPath(self.0.into())
     }
 }
 
-impl_type! {
-    #[dbus(basic, module = crate)]
-    Signature: 'g' {
-        encode(marshaller) {
-            marshaller.io().write_u8(self.len() as u8)?;
-            marshaller.io().write_all(self.as_bytes())?;
-            marshaller.io().write_u8(0)?;
-            Ok(())
-        }
-
-        decode(marshaller) {
-            let length = marshaller.io().read_u8()?;
-            let mut data = vec![0; length as usize];
-            marshaller.io().read_exact(&mut data)?;
-            marshaller.io().read_u8()?;
-            let value = String::from_utf8(data)?;
-            Ok(Signature(value))
-        }
+impl<'a> AsRef<str> for ObjectPathRef<'a> {
+    fn as_ref(&self) -> &str {
+        self.0
     }
 }
 
@@ -199,14 +159,6 @@ mod tests {
 
     const INVALID_OBJECT_PATHS: &[&str] = &["", ".", "//", "/a/", "/-", "/com//example/MyApp", "/$"];
 
-    const VALID_SIGNATURES: &[&str] = &["", "s", "a{sv}", "sss", "sv", "a{sv}as"];
-
-    const INVALID_SIGNATURES: &[&str] = &[
-        // TODO: Validate signature (rules 2 and 3), maybe need a parser...
-        // "a",
-        "a{s_}",
-    ];
-
     #[test]
     fn test_valid_object_paths() {
         for path in VALID_OBJECT_PATHS {
@@ -220,18 +172,4 @@ mod tests {
             assert_err!(ObjectPath::new(path), path);
         }
     }
-
-    #[test]
-    fn test_valid_signatures() {
-        for sig in VALID_SIGNATURES {
-            assert_ok!(Signature::new(sig), sig);
-        }
-    }
-
-    #[test]
-    fn test_invalid_signatures() {
-        for sig in INVALID_SIGNATURES {
-            assert_err!(Signature::new(sig), sig);
-        }
-    }
 }
@@ -1,4 +1,13 @@
 use super::{impl_type, DBusType};
+use crate::Error;
+use std::{
+    mem,
+    ops::{Deref, DerefMut},
+};
+
+// Tuples are packed: their fields are written one after the other without
+// any alignment, like the arguments of a method. `Struct` wraps them to get
+// an actual D-Bus struct.
 
 macro_rules! impl_tuple_dbus_type {
     ($($index:tt: $ty:ident),*) => {
@@ -18,7 +27,7 @@ macro_rules! impl_tuple_dbus_type {
 
                 #[allow(unused_variables)]
                 decode(marshaller) {
-                    Ok(($(marshaller.read_value::<$ty>()?,)*))
+                    Ok(($(marshaller.read_field::<$ty>(stringify!($index))?,)*))
                 }
             }
         }
@@ -34,6 +43,76 @@ impl_tuple_dbus_type!(0: A, 1: B, 2: C, 3: D, 4: E);
 impl_tuple_dbus_type!(0: A, 1: B, 2: C, 3: D, 4: E, 5: F);
 impl_tuple_dbus_type!(0: A, 1: B, 2: C, 3: D, 4: E, 5: F, 6: G);
 impl_tuple_dbus_type!(0: A, 1: B, 2: C, 3: D, 4: E, 5: F, 6: G, 7: H);
+impl_tuple_dbus_type!(0: A, 1: B, 2: C, 3: D, 4: E, 5: F, 6: G, 7: H, 8: I);
+impl_tuple_dbus_type!(0: A, 1: B, 2: C, 3: D, 4: E, 5: F, 6: G, 7: H, 8: I, 9: J);
+impl_tuple_dbus_type!(0: A, 1: B, 2: C, 3: D, 4: E, 5: F, 6: G, 7: H, 8: I, 9: J, 10: K);
+impl_tuple_dbus_type!(0: A, 1: B, 2: C, 3: D, 4: E, 5: F, 6: G, 7: H, 8: I, 9: J, 10: K, 11: L);
+impl_tuple_dbus_type!(0: A, 1: B, 2: C, 3: D, 4: E, 5: F, 6: G, 7: H, 8: I, 9: J, 10: K, 11: L, 12: M);
+impl_tuple_dbus_type!(0: A, 1: B, 2: C, 3: D, 4: E, 5: F, 6: G, 7: H, 8: I, 9: J, 10: K, 11: L, 12: M, 13: N);
+impl_tuple_dbus_type!(0: A, 1: B, 2: C, 3: D, 4: E, 5: F, 6: G, 7: H, 8: I, 9: J, 10: K, 11: L, 12: M, 13: N, 14: O);
+impl_tuple_dbus_type!(0: A, 1: B, 2: C, 3: D, 4: E, 5: F, 6: G, 7: H, 8: I, 9: J, 10: K, 11: L, 12: M, 13: N, 14: O, 15: P);
+
+/// A D-Bus struct, aligned to 8 bytes, holding the fields of a tuple.
+///
+/// `Struct((1u32, "a"))` has the signature `(us)` where the bare tuple is
+/// the packed `us`. Structs can't be empty, so encoding `Struct(())` fails.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Struct<T>(pub T);
+
+impl<T> Struct<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> From<T> for Struct<T> {
+    fn from(fields: T) -> Struct<T> {
+        Struct(fields)
+    }
+}
+
+impl<T> Deref for Struct<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Struct<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+/// Whether `T` has an empty signature, only built for zero-sized types since
+/// the others hold at least one field.
+fn has_no_fields<T: DBusType>() -> bool {
+    mem::size_of::<T>() == 0 && T::signature().is_empty()
+}
+
+impl_type! {
+    #[dbus(align = 8, module = crate)]
+    impl<T: DBusType> Struct<T>: 'r' {
+        signature() {
+            format!("({})", T::signature())
+        }
+
+        encode(marshaller) {
+            if has_no_fields::<T>() {
+                return Err(Error::EmptyStruct);
+            }
+            marshaller.write_struct(|marshaller| marshaller.write_value(&self.0))
+        }
+
+        decode(marshaller) {
+            if has_no_fields::<T>() {
+                return Err(Error::EmptyStruct);
+            }
+            marshaller.read_struct(|marshaller| marshaller.read_value()).map(Struct)
+        }
+    }
+}
 
 #[cfg(test)]
 mod tests {
@@ -52,4 +131,58 @@ mod tests {
 
         assert_eq!(<(u32, Arg)>::signature(), "u(ys)");
     }
+
+    #[test]
+    fn test_signature_struct_wrapper() {
+        use crate::types::SignatureType;
+
+        assert_eq!(<Struct<(u32, String)>>::signature(), "(us)");
+        assert_eq!(<(u8, Vec<Struct<(String, u64)>>)>::signature(), "ya(st)");
+        assert_eq!(<Struct<(u8, Struct<(u16,)>)>>::signature(), "(y(q))");
+        assert_eq!(<Struct<(u32, String)>>::code(), SignatureType::Struct(vec![]).code());
+    }
+
+    #[test]
+    fn test_signature_large_tuple() {
+        type Args = (u8, u8, u8, u8, u8, u8, u8, u8, u8, u8, u8, u8, u8, u8, u8, String);
+        assert_eq!(Args::signature(), "yyyyyyyyyyyyyyys");
+        assert_eq!(Struct::<Args>::signature(), "(yyyyyyyyyyyyyyys)");
+    }
+
+    #[test]
+    fn test_struct_alignment() {
+        use crate::marshal::{Endianness, Marshaller};
+
+        let value = (1u8, Struct((2u32, 3u8)));
+        let bytes: &[u8] = &[
+            1, 0, 0, 0, 0, 0, 0, 0, // Byte + padding to the struct
+            2, 0, 0, 0, // Struct u32
+            3, // Struct byte
+        ];
+
+        let mut marshaller = Marshaller::new(Vec::new(), Endianness::Little);
+        marshaller.write_value(&value).unwrap();
+        assert_eq!(marshaller.into_inner(), bytes);
+
+        let mut marshaller = Marshaller::new(bytes, Endianness::Little);
+        assert_eq!(marshaller.read_value::<(u8, Struct<(u32, u8)>)>().unwrap(), value);
+        assert!(marshaller.is_empty());
+    }
+
+    #[test]
+    fn test_empty_struct() {
+        use crate::{
+            marshal::{Endianness, Marshaller},
+            types::Value,
+        };
+
+        let mut marshaller = Marshaller::new(Vec::new(), Endianness::Little);
+        assert_err_matches!(marshaller.write_value(&Struct(())), Error::EmptyStruct);
+        assert_err_matches!(Value::Struct(vec![]).encode(&mut marshaller), Error::EmptyStruct);
+        assert!(marshaller.into_inner().is_empty());
+
+        let mut marshaller = Marshaller::new(&[0u8; 8][..], Endianness::Little);
+        assert_err_matches!(marshaller.read_value::<Struct<()>>(), Error::EmptyStruct);
+        assert_ok!(marshaller.read_value::<Struct<[u8; 0]>>());
+    }
 }
@@ -0,0 +1,374 @@
+use super::{impl_type, BasicType, DBusType, ObjectPath, Signature, SignatureType, UnixFd};
+use crate::{marshal::Marshaller, Error, Result};
+use std::{convert::TryFrom, io};
+
+/// A dynamically typed D-Bus value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Byte(u8),
+    Boolean(bool),
+    Int16(i16),
+    UInt16(u16),
+    Int32(i32),
+    UInt32(u32),
+    Int64(i64),
+    UInt64(u64),
+    Double(f64),
+    UnixFd(UnixFd),
+    String(String),
+    ObjectPath(ObjectPath),
+    Signature(Signature),
+    /// An array of values of type `ty`, the element type is kept around so
+    /// empty arrays still have a signature.
+    Array {
+        ty: SignatureType,
+        values: Vec<Value>,
+    },
+    /// An array of dict entries.
+    Dict {
+        key: BasicType,
+        value: SignatureType,
+        entries: Vec<(Value, Value)>,
+    },
+    Struct(Vec<Value>),
+    Variant(Box<Value>),
+    /// A GVariant maybe value of type `ty`, which can't be encoded in the
+    /// D-Bus format.
+    #[cfg(feature = "gvariant")]
+    Maybe {
+        ty: SignatureType,
+        value: Option<Box<Value>>,
+    },
+}
+
+impl Value {
+    pub fn signature_type(&self) -> SignatureType {
+        let basic = match self {
+            Value::Byte(_) => BasicType::Byte,
+            Value::Boolean(_) => BasicType::Boolean,
+            Value::Int16(_) => BasicType::Int16,
+            Value::UInt16(_) => BasicType::UInt16,
+            Value::Int32(_) => BasicType::Int32,
+            Value::UInt32(_) => BasicType::UInt32,
+            Value::Int64(_) => BasicType::Int64,
+            Value::UInt64(_) => BasicType::UInt64,
+            Value::Double(_) => BasicType::Double,
+            Value::UnixFd(_) => BasicType::UnixFd,
+            Value::String(_) => BasicType::String,
+            Value::ObjectPath(_) => BasicType::ObjectPath,
+            Value::Signature(_) => BasicType::Signature,
+            Value::Array { ty, .. } => return SignatureType::Array(Box::new(ty.clone())),
+            Value::Dict { key, value, .. } => {
+                let entry = SignatureType::DictEntry(*key, Box::new(value.clone()));
+                return SignatureType::Array(Box::new(entry));
+            }
+            Value::Struct(fields) => return SignatureType::Struct(fields.iter().map(Value::signature_type).collect()),
+            Value::Variant(_) => return SignatureType::Variant,
+            #[cfg(feature = "gvariant")]
+            Value::Maybe { ty, .. } => return SignatureType::Maybe(Box::new(ty.clone())),
+        };
+
+        SignatureType::Basic(basic)
+    }
+
+    /// Return the signature of the value, which fails for values which
+    /// can't be encoded, like empty structs.
+    pub fn signature(&self) -> Result<Signature> {
+        Ok(Signature::try_from(self.signature_type())?)
+    }
+
+    pub fn alignment(&self) -> u8 {
+        self.signature_type().alignment()
+    }
+
+    pub fn encode<Inner>(&self, marshaller: &mut Marshaller<Inner>) -> Result<()>
+    where
+        Inner: io::Write,
+    {
+        marshaller.write_padding(self.alignment())?;
+
+        match self {
+            Value::Byte(value) => value.encode(marshaller),
+            Value::Boolean(value) => value.encode(marshaller),
+            Value::Int16(value) => value.encode(marshaller),
+            Value::UInt16(value) => value.encode(marshaller),
+            Value::Int32(value) => value.encode(marshaller),
+            Value::UInt32(value) => value.encode(marshaller),
+            Value::Int64(value) => value.encode(marshaller),
+            Value::UInt64(value) => value.encode(marshaller),
+            Value::Double(value) => value.encode(marshaller),
+            Value::UnixFd(value) => value.encode(marshaller),
+            Value::String(value) => value.encode(marshaller),
+            Value::ObjectPath(value) => value.encode(marshaller),
+            Value::Signature(value) => value.encode(marshaller),
+            Value::Array { ty, values } => marshaller.write_array(ty.alignment(), |marshaller| {
+                for value in values.iter() {
+                    if value.signature_type() != *ty {
+                        return Err(Error::Custom {
+                            message: format!("Array element doesn't match the array type `{}`", ty),
+                        });
+                    }
+                    value.encode(marshaller)?;
+                }
+                Ok(())
+            }),
+            Value::Dict { key, value, entries } => marshaller.write_array(8, |marshaller| {
+                for (entry_key, entry_value) in entries.iter() {
+                    let key_matches = entry_key.signature_type() == SignatureType::Basic(*key);
+                    if !key_matches || entry_value.signature_type() != *value {
+                        return Err(Error::Custom {
+                            message: format!("Dict entry doesn't match the dict type `{{{}{}}}`", key, value),
+                        });
+                    }
+                    marshaller.write_padding(8)?;
+                    entry_key.encode(marshaller)?;
+                    entry_value.encode(marshaller)?;
+                }
+                Ok(())
+            }),
+            Value::Struct(fields) if fields.is_empty() => Err(Error::EmptyStruct),
+            Value::Struct(fields) => marshaller.write_struct(|marshaller| {
+                for field in fields.iter() {
+                    field.encode(marshaller)?;
+                }
+                Ok(())
+            }),
+            Value::Variant(value) => {
+                value.signature()?.encode(marshaller)?;
+                value.encode(marshaller)
+            }
+            #[cfg(feature = "gvariant")]
+            Value::Maybe { .. } => Err(crate::gvariant::maybe_unsupported()),
+        }
+    }
+
+    pub fn decode<Inner>(marshaller: &mut Marshaller<Inner>, ty: &SignatureType) -> Result<Value>
+    where
+        Inner: io::Read,
+    {
+        let offset = marshaller.read_position();
+        Value::decode_value(marshaller, ty).map_err(|err| err.in_value(&ty.to_string(), offset))
+    }
+
+    fn decode_value<Inner>(marshaller: &mut Marshaller<Inner>, ty: &SignatureType) -> Result<Value>
+    where
+        Inner: io::Read,
+    {
+        marshaller.read_padding(ty.alignment())?;
+
+        let value = match ty {
+            SignatureType::Basic(ty) => Value::decode_basic(marshaller, *ty)?,
+            SignatureType::Array(element) => match **element {
+                SignatureType::DictEntry(key, ref value) => {
+                    let mut entries = Vec::new();
+                    marshaller.read_array(8, |marshaller| {
+                        let entry = marshaller.read_struct(|marshaller| {
+                            let entry_key = Value::decode_basic(marshaller, key)?;
+                            let entry_value = Value::decode(marshaller, value)?;
+                            Ok((entry_key, entry_value))
+                        })?;
+                        entries.push(entry);
+                        Ok(())
+                    })?;
+
+                    Value::Dict {
+                        key,
+                        value: (**value).clone(),
+                        entries,
+                    }
+                }
+                ref element => {
+                    let mut values = Vec::new();
+                    marshaller.read_array(element.alignment(), |marshaller| {
+                        values.push(Value::decode(marshaller, element)?);
+                        Ok(())
+                    })?;
+
+                    Value::Array {
+                        ty: element.clone(),
+                        values,
+                    }
+                }
+            },
+            SignatureType::Struct(fields) => marshaller.read_struct(|marshaller| {
+                fields
+                    .iter()
+                    .map(|field| Value::decode(marshaller, field))
+                    .collect::<Result<Vec<_>>>()
+                    .map(Value::Struct)
+            })?,
+            SignatureType::DictEntry(..) => {
+                return Err(Error::Custom {
+                    message: "Dict entries can only be decoded as array elements".into(),
+                })
+            }
+            SignatureType::Variant => Value::Variant(Box::new(Variant::decode(marshaller)?.0)),
+            #[cfg(feature = "gvariant")]
+            SignatureType::Maybe(_) => return Err(crate::gvariant::maybe_unsupported()),
+        };
+
+        Ok(value)
+    }
+
+    fn decode_basic<Inner>(marshaller: &mut Marshaller<Inner>, ty: BasicType) -> Result<Value>
+    where
+        Inner: io::Read,
+    {
+        marshaller.read_padding(ty.alignment())?;
+
+        let value = match ty {
+            BasicType::Byte => Value::Byte(u8::decode(marshaller)?),
+            BasicType::Boolean => Value::Boolean(bool::decode(marshaller)?),
+            BasicType::Int16 => Value::Int16(i16::decode(marshaller)?),
+            BasicType::UInt16 => Value::UInt16(u16::decode(marshaller)?),
+            BasicType::Int32 => Value::Int32(i32::decode(marshaller)?),
+            BasicType::UInt32 => Value::UInt32(u32::decode(marshaller)?),
+            BasicType::Int64 => Value::Int64(i64::decode(marshaller)?),
+            BasicType::UInt64 => Value::UInt64(u64::decode(marshaller)?),
+            BasicType::Double => Value::Double(f64::decode(marshaller)?),
+            BasicType::UnixFd => Value::UnixFd(UnixFd::decode(marshaller)?),
+            BasicType::String => Value::String(String::decode(marshaller)?),
+            BasicType::ObjectPath => Value::ObjectPath(ObjectPath::decode(marshaller)?),
+            BasicType::Signature => Value::Signature(Signature::decode(marshaller)?),
+        };
+
+        Ok(value)
+    }
+}
+
+macro_rules! impl_value_from {
+    ($($ty:ty => $variant:ident),*) => {
+        $(
+        impl From<$ty> for Value {
+            fn from(value: $ty) -> Value {
+                Value::$variant(value)
+            }
+        }
+        )*
+    }
+}
+
+impl_value_from! {
+    u8 => Byte,
+    bool => Boolean,
+    i16 => Int16,
+    u16 => UInt16,
+    i32 => Int32,
+    u32 => UInt32,
+    i64 => Int64,
+    u64 => UInt64,
+    f64 => Double,
+    UnixFd => UnixFd,
+    String => String,
+    ObjectPath => ObjectPath,
+    Signature => Signature
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Value {
+        Value::String(value.into())
+    }
+}
+
+/// A D-Bus variant (`v`), holding a dynamically typed value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variant(pub Value);
+
+impl Variant {
+    pub fn new<T: Into<Value>>(value: T) -> Variant {
+        Variant(value.into())
+    }
+
+    pub fn into_inner(self) -> Value {
+        self.0
+    }
+}
+
+impl From<Value> for Variant {
+    fn from(value: Value) -> Variant {
+        Variant(value)
+    }
+}
+
+impl_type! {
+    #[dbus(align = 1, module = crate)]
+    Variant: 'v' {
+        encode(marshaller) {
+            self.0.signature()?.encode(marshaller)?;
+            self.0.encode(marshaller)
+        }
+
+        decode(marshaller) {
+            let signature = Signature::decode(marshaller)?;
+            let ty = SignatureType::parse(signature.as_str())?;
+            let value = marshaller.read_variant(|marshaller| Value::decode(marshaller, &ty))?;
+            Ok(Variant(value))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::marshal::Endianness;
+
+    #[test]
+    fn test_value_roundtrip_struct() {
+        let value = Value::Struct(vec![
+            Value::from(1u32),
+            Value::Array {
+                ty: SignatureType::Basic(BasicType::UInt32),
+                values: vec![Value::from(2u32), Value::from(3u32)],
+            },
+        ]);
+        assert_eq!(value.signature().unwrap().as_str(), "(uau)");
+
+        let mut marshaller = Marshaller::new(Vec::new(), Endianness::Little);
+        value.encode(&mut marshaller).unwrap();
+        let data = marshaller.into_inner();
+
+        let mut marshaller = Marshaller::new(data.as_slice(), Endianness::Little);
+        let decoded = Value::decode(&mut marshaller, &value.signature_type()).unwrap();
+        assert_eq!(decoded, value);
+        assert!(marshaller.is_empty());
+    }
+
+    #[test]
+    fn test_value_decode_dict() {
+        let bytes: &[u8] = &[
+            16, 0, 0, 0, // Array size in bytes = 16
+            0, 0, 0, 0, // Padding to the first dict entry
+            1, 0, 0, 0, b'a', 0, // Key
+            1, b'u', 0, // Variant signature
+            0, 0, 0, // Padding
+            42, 0, 0, 0, // Variant value
+        ];
+
+        let mut marshaller = Marshaller::new(bytes, Endianness::Little);
+        let ty = SignatureType::parse("a{sv}").unwrap();
+        let value = Value::decode(&mut marshaller, &ty).unwrap();
+
+        assert_eq!(
+            value,
+            Value::Dict {
+                key: BasicType::String,
+                value: SignatureType::Variant,
+                entries: vec![(Value::from("a"), Value::Variant(Box::new(Value::from(42u32))))],
+            }
+        );
+        assert!(marshaller.is_empty());
+    }
+
+    #[test]
+    fn test_value_decode_variant() {
+        let bytes: &[u8] = &[
+            1, b'u', 0, // Variant signature
+            0, // Padding
+            0x2a, 0, 0, 0, // Variant value
+        ];
+
+        let mut marshaller = Marshaller::new(bytes, Endianness::Little);
+        let variant = Variant::decode(&mut marshaller).unwrap();
+        assert_eq!(variant, Variant::new(42u32));
+    }
+}
@@ -61,3 +61,13 @@ macro_rules! assert_err {
         }
     };
 }
+
+#[macro_export]
+macro_rules! assert_err_matches {
+    ($expr:expr, $pattern:pat) => {
+        match $expr {
+            Err(ref err) if matches!(err.root(), $pattern) => {}
+            other => panic!("Expected Err({}) but got {:?}", stringify!($pattern), other),
+        }
+    };
+}